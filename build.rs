@@ -6,10 +6,16 @@ use std::path::PathBuf;
 fn main() {
     println!("cargo:rerun-if-changed=irsdk/wrapper.hpp");
 
-    let bindgens = bindgen::Builder::default()
+    let mut builder = bindgen::Builder::default()
         .header("irsdk/wrapper.hpp")
         .rustfmt_bindings(true)
-        .parse_callbacks(Box::new(bindgen::CargoCallbacks))
+        .parse_callbacks(Box::new(bindgen::CargoCallbacks));
+    if env::var("CARGO_CFG_TARGET_OS").unwrap() != "windows" {
+        // irsdk_defines.h includes the MSVC only tchar.h
+        builder = builder.clang_arg("-Iirsdk/compat");
+    }
+
+    let bindgens = builder
         .generate()
         .expect("Unable to generate bindgens");
 
@@ -18,6 +24,6 @@ fn main() {
         .write_to_file(out_path.join("bindings.rs"))
         .expect("Couldn't write bindings!");
     
-    prost_build::compile_protos(&["src/track.proto"], &["src/"])
+    prost_build::compile_protos(&["src/track.proto", "src/delta.proto"], &["src/"])
         .expect("Failed to generate protobuf code");
 }
@@ -0,0 +1,8 @@
+// Stand-in for the MSVC header, letting bindgen process irsdk_defines.h on other platforms
+#pragma once
+
+#include <string.h>
+#include <time.h>
+
+typedef char _TCHAR;
+#define _T(x) x
@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+
+use crate::iracing::telemetry_source::SourceConfig;
+
+pub const USAGE: &str = "Usage: uberlays [--shm <memory file> | --synthetic | --ibt <file> [--fast] [--loop]] [--record <directory>] [--pit-plan <file>]";
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub source: SourceConfig,
+    // Directory to record .ibt files of everything received from the source into
+    pub record: Option<PathBuf>,
+    // YAML file describing the pit service to request when approaching pit entry
+    pub pit_plan: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Config, String> {
+        let mut memory_file = None;
+        let mut ibt_path = None;
+        let mut synthetic = false;
+        let mut real_time = true;
+        let mut looping = false;
+        let mut record = None;
+        let mut pit_plan = None;
+
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--shm" => memory_file = Some(PathBuf::from(args.next().ok_or("--shm requires a file name")?)),
+                "--synthetic" => synthetic = true,
+                "--ibt" => ibt_path = Some(PathBuf::from(args.next().ok_or("--ibt requires a file name")?)),
+                "--fast" => real_time = false,
+                "--loop" => looping = true,
+                "--record" => record = Some(PathBuf::from(args.next().ok_or("--record requires a directory")?)),
+                "--pit-plan" => pit_plan = Some(PathBuf::from(args.next().ok_or("--pit-plan requires a file name")?)),
+                unknown => return Err(format!["Unknown argument {}", unknown]),
+            }
+        }
+
+        let source = match (memory_file, ibt_path, synthetic) {
+            (memory_file, None, false) => SourceConfig::Live { memory_file },
+            (None, Some(path), false) => SourceConfig::Ibt { path, real_time, looping },
+            (None, None, true) => SourceConfig::Synthetic,
+            _ => return Err("Only one of --shm, --ibt and --synthetic can be used".to_string()),
+        };
+
+        Ok(Config { source, record, pit_plan })
+    }
+}
@@ -0,0 +1,376 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use async_trait::async_trait;
+
+use crate::overlay::StateTracker;
+use crate::iracing::{ Update, Telemetry, SessionInfo };
+use crate::iracing::session_info::Session;
+use crate::iracing::lap_timer::Lap;
+use crate::iracing::flags::Flags;
+
+// Number of green flag laps the average consumption is taken over
+const WINDOW: usize = 5;
+// Fuel to arrive at the finish with, in laps of average consumption
+const MARGIN_LAPS: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FuelStatus {
+    // Liters in the tank
+    pub level: f32,
+    // Used on the last lap, green or not
+    pub last_lap: Option<f32>,
+    // Over the recent green flag laps
+    pub average: Option<f32>,
+    pub max: Option<f32>,
+    // What the estimates below use: the average, or the current consumption before a green lap is done
+    pub per_lap: Option<f32>,
+    // Laps the fuel in the tank lasts
+    pub laps_remaining: Option<f32>,
+    // None for sessions without a lap or time limit
+    pub laps_to_finish: Option<f32>,
+    pub fuel_to_finish: Option<f32>,
+    // At most a full tank, so more stops are needed when this is not enough to finish
+    pub fuel_to_add: Option<f32>,
+}
+
+// Everything that is known, e.g. `42.1 l, 2.85 l last lap, 2.80 l average, ...`
+impl fmt::Display for FuelStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = vec![format!["{:.1} l", self.level]];
+        let mut add = |value: Option<f32>, describe: fn(f32) -> String| {
+            if let Some(value) = value {
+                parts.push(describe(value));
+            }
+        };
+        add(self.last_lap, |liters| format!["{:.2} l last lap", liters]);
+        add(self.average, |liters| format!["{:.2} l average", liters]);
+        add(self.max, |liters| format!["{:.2} l max", liters]);
+        add(self.laps_remaining, |laps| format!["{:.1} laps left", laps]);
+        add(self.laps_to_finish, |laps| format!["{:.1} laps to finish", laps]);
+        add(self.fuel_to_finish, |liters| format!["{:.1} l to finish", liters]);
+        add(self.fuel_to_add, |liters| format!["{:.1} l to add", liters]);
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+// Keeps track of how much fuel the player uses per lap, and how much is needed to finish
+pub struct FuelTracker {
+    player_car_idx: Option<usize>,
+    sessions: Vec<Session>,
+    fuel_capacity: Option<f32>,
+    fuel_kg_per_liter: f32,
+    est_lap_time: Option<f64>,
+
+    // Green flag laps only, most recent last
+    usage: VecDeque<f32>,
+    lap_times: VecDeque<f64>,
+    max: Option<f32>,
+    last_lap: Option<f32>,
+
+    // Fuel at the start of the lap in progress, None until the player is seen crossing the line
+    lap_start_level: Option<f32>,
+    // Whether the lap in progress was driven under green, without stopping in the pits
+    lap_is_green: bool,
+    last_level: f32,
+    session_num: i32,
+    status: FuelStatus,
+    // Lap to log the status for with the next telemetry, which includes the fuel used on it
+    log_lap: Option<i32>,
+}
+
+impl Default for FuelTracker {
+    fn default() -> FuelTracker {
+        FuelTracker {
+            player_car_idx: None,
+            sessions: vec![],
+            fuel_capacity: None,
+            fuel_kg_per_liter: 0.0,
+            est_lap_time: None,
+            usage: VecDeque::new(),
+            lap_times: VecDeque::new(),
+            max: None,
+            last_lap: None,
+            lap_start_level: None,
+            lap_is_green: false,
+            last_level: 0.0,
+            session_num: -1,
+            status: FuelStatus::default(),
+            log_lap: None,
+        }
+    }
+}
+
+impl FuelTracker {
+    pub fn new() -> FuelTracker {
+        FuelTracker::default()
+    }
+
+    fn set_session(&mut self, session_info: &SessionInfo) {
+        self.player_car_idx = Some(session_info.driver.car_idx);
+        self.sessions = session_info.sessions.clone();
+        self.fuel_capacity = session_info.driver.fuel_capacity;
+        self.fuel_kg_per_liter = session_info.driver.fuel_kg_per_liter;
+        self.est_lap_time = session_info.driver(session_info.driver.car_idx)
+            .map(|driver| driver.car_class_est_lap_time as f64)
+            .filter(|&time| time > 0.0);
+    }
+
+    fn average(&self) -> Option<f32> {
+        if self.usage.is_empty() {
+            return None;
+        }
+        Some(self.usage.iter().sum::<f32>() / self.usage.len() as f32)
+    }
+
+    fn average_lap_time(&self) -> Option<f64> {
+        if self.lap_times.is_empty() {
+            return self.est_lap_time;
+        }
+        Some(self.lap_times.iter().sum::<f64>() / self.lap_times.len() as f64)
+    }
+
+    // Laps until the player takes the checkered flag, counted from where the car is now
+    fn laps_to_finish(&self, telemetry: &Telemetry, car_idx: usize) -> Option<f32> {
+        let session = self.sessions.iter().find(|session| session.num == telemetry.session_num)?;
+        let dist = telemetry.lap_dist_by_car.get(car_idx).copied().unwrap_or(0.0).max(0.0) as f64;
+        let completed = telemetry.car_laps_completed.get(car_idx).copied().unwrap_or(0).max(0) as f64;
+
+        let by_laps = session.laps.map(|laps| (laps as f64 - completed - dist).max(0.0));
+        // When the time runs out the lap in progress is still finished
+        let by_time = match (session.time, self.average_lap_time()) {
+            (Some(time), Some(lap_time)) => {
+                let remaining = (time.as_secs_f64() - telemetry.session_time).max(0.0);
+                Some((dist + remaining / lap_time).ceil() - dist)
+            },
+            _ => None,
+        };
+
+        match (by_laps, by_time) {
+            (Some(by_laps), Some(by_time)) => Some(by_laps.min(by_time) as f32),
+            (laps, time) => laps.or(time).map(|laps| laps as f32),
+        }
+    }
+
+    fn update(&mut self, telemetry: &Telemetry) {
+        let car_idx = match self.player_car_idx {
+            Some(car_idx) => car_idx,
+            None => return,
+        };
+
+        if telemetry.session_num != self.session_num {
+            self.session_num = telemetry.session_num;
+            self.lap_start_level = None;
+        }
+
+        let in_world = telemetry.lap_dist_by_car.get(car_idx).map_or(false, |&dist| dist >= 0.0);
+        if !in_world || !telemetry.is_on_track {
+            // Back in the garage, the fuel can be changed freely from there
+            self.lap_start_level = None;
+        }
+        let caution = telemetry.session_flags.intersects(Flags::Yellow | Flags::YellowWaving
+            | Flags::Caution | Flags::CautionWaving | Flags::Red);
+        if caution || telemetry.on_pit_road || telemetry.fuel_level > self.last_level {
+            self.lap_is_green = false;
+        }
+        self.last_level = telemetry.fuel_level;
+
+        let average = self.average();
+        let current = match (telemetry.fuel_use_per_hour, self.average_lap_time()) {
+            // The sim reports the consumption in kilograms per hour
+            (use_per_hour, Some(lap_time)) if use_per_hour > 0.0 && self.fuel_kg_per_liter > 0.0 =>
+                Some(use_per_hour / self.fuel_kg_per_liter * (lap_time / 3600.0) as f32),
+            _ => None,
+        };
+        let per_lap = average.or(current).filter(|&per_lap| per_lap > 0.0);
+
+        let laps_to_finish = self.laps_to_finish(telemetry, car_idx);
+        let fuel_to_finish = laps_to_finish.zip(per_lap).map(|(laps, per_lap)| laps * per_lap);
+        let fuel_to_add = fuel_to_finish.zip(per_lap).map(|(fuel, per_lap)| {
+            let needed = (fuel + MARGIN_LAPS * per_lap - telemetry.fuel_level).max(0.0);
+            // No more than fits in the tank on top of what is left
+            self.fuel_capacity.map_or(needed, |capacity| needed.min((capacity - telemetry.fuel_level).max(0.0)))
+        });
+
+        self.status = FuelStatus {
+            level: telemetry.fuel_level,
+            last_lap: self.last_lap,
+            average,
+            max: self.max,
+            per_lap,
+            laps_remaining: per_lap.map(|per_lap| telemetry.fuel_level / per_lap),
+            laps_to_finish,
+            fuel_to_finish,
+            fuel_to_add,
+        };
+
+        if let Some(lap) = self.log_lap.take() {
+            info!["Fuel after lap {}: {}", lap, self.status];
+        }
+    }
+
+    fn complete_lap(&mut self, lap: &Lap) {
+        if Some(lap.car_idx) != self.player_car_idx {
+            return;
+        }
+
+        let level = self.last_level;
+        if let Some(start_level) = self.lap_start_level {
+            let used = start_level - level;
+            self.last_lap = Some(used);
+            if self.lap_is_green && used > 0.0 {
+                self.usage.push_back(used);
+                self.lap_times.push_back(lap.time.as_secs_f64());
+                if self.usage.len() > WINDOW {
+                    self.usage.pop_front();
+                    self.lap_times.pop_front();
+                }
+                self.max = Some(self.max.map_or(used, |max| max.max(used)));
+            }
+            self.log_lap = Some(lap.lap);
+        }
+
+        self.lap_start_level = Some(level);
+        self.lap_is_green = true;
+    }
+}
+
+#[async_trait]
+impl StateTracker for FuelTracker {
+    async fn process(&mut self, update: &Update) {
+        match update {
+            Update::Session(session_info) => self.set_session(session_info),
+            Update::Telemetry(telemetry) => self.update(telemetry),
+            Update::LapCompleted(lap) => self.complete_lap(lap),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn assert_close(actual: Option<f32>, expected: f32) {
+        let actual = actual.unwrap_or_else(|| panic!("Nothing, expected {}", expected));
+        assert!((actual - expected).abs() < 1e-3, "{} is not {}", actual, expected);
+    }
+
+    // The player in car 0 in a session with the given limits, in a car lapping in 100 seconds
+    fn tracker(laps: Option<u32>, time: Option<u64>) -> FuelTracker {
+        FuelTracker {
+            player_car_idx: Some(0),
+            sessions: vec![Session {
+                laps,
+                time: time.map(Duration::from_secs),
+                ..Session::default()
+            }],
+            fuel_capacity: Some(60.0),
+            fuel_kg_per_liter: 0.75,
+            est_lap_time: Some(100.0),
+            ..FuelTracker::default()
+        }
+    }
+
+    fn telemetry(fuel_level: f32, laps_completed: i32, dist: f32) -> Telemetry {
+        Telemetry {
+            session_time: 3000.0,
+            fuel_level,
+            lap_dist_by_car: vec![dist],
+            car_laps_completed: vec![laps_completed],
+            is_on_track: true,
+            ..Telemetry::default()
+        }
+    }
+
+    fn lap(lap: i32) -> Lap {
+        Lap {
+            car_idx: 0,
+            lap,
+            time: Duration::from_secs(100),
+            sectors: vec![],
+            completed_at: 0.0,
+            is_personal_best: false,
+            is_session_best: false,
+        }
+    }
+
+    // Crosses the line with the given fuel level
+    fn complete_lap(tracker: &mut FuelTracker, telemetry: &Telemetry, laps_completed: i32) {
+        tracker.update(telemetry);
+        tracker.complete_lap(&lap(laps_completed));
+    }
+
+    #[test]
+    fn laps_to_finish_by_lap_limit() {
+        let tracker = tracker(Some(30), None);
+        assert_close(tracker.laps_to_finish(&telemetry(50.0, 10, 0.25), 0), 19.75);
+        assert_close(tracker.laps_to_finish(&telemetry(50.0, 30, 0.0), 0), 0.0);
+    }
+
+    #[test]
+    fn laps_to_finish_by_time_limit() {
+        let tracker = tracker(None, Some(3600));
+        // Ten minutes left is six laps, and the one in progress when the time runs out
+        assert_close(tracker.laps_to_finish(&telemetry(50.0, 10, 0.25), 0), 6.75);
+        assert_close(tracker.laps_to_finish(&telemetry(50.0, 10, 0.0), 0), 6.0);
+    }
+
+    #[test]
+    fn laps_to_finish_by_both_limits() {
+        // Whichever comes first
+        assert_close(tracker(Some(30), Some(3600)).laps_to_finish(&telemetry(50.0, 10, 0.25), 0), 6.75);
+        assert_close(tracker(Some(12), Some(3600)).laps_to_finish(&telemetry(50.0, 10, 0.25), 0), 1.75);
+        assert_eq!(tracker(None, None).laps_to_finish(&telemetry(50.0, 10, 0.25), 0), None);
+    }
+
+    #[test]
+    fn averages_green_laps_only() {
+        let mut tracker = tracker(Some(30), None);
+        complete_lap(&mut tracker, &telemetry(50.0, 0, 0.0), 0);
+        complete_lap(&mut tracker, &telemetry(47.0, 1, 0.0), 1);
+        assert_eq!(tracker.usage, [3.0]);
+
+        // Under yellow
+        let mut yellow = telemetry(45.0, 1, 0.5);
+        yellow.session_flags = Flags::Yellow;
+        tracker.update(&yellow);
+        complete_lap(&mut tracker, &telemetry(43.0, 2, 0.0), 2);
+        assert_eq!(tracker.last_lap, Some(4.0));
+
+        // Refueled in the pits
+        let mut pits = telemetry(58.0, 2, 0.5);
+        pits.on_pit_road = true;
+        tracker.update(&pits);
+        complete_lap(&mut tracker, &telemetry(57.0, 3, 0.0), 3);
+
+        complete_lap(&mut tracker, &telemetry(53.5, 4, 0.0), 4);
+        assert_eq!(tracker.usage, [3.0, 3.5]);
+        assert_eq!(tracker.max, Some(3.5));
+
+        tracker.update(&telemetry(53.5, 5, 0.0));
+        let status = tracker.status;
+        assert_close(status.last_lap, 3.5);
+        assert_close(status.average, 3.25);
+        assert_close(status.per_lap, 3.25);
+        assert_close(status.laps_remaining, 53.5 / 3.25);
+        assert_close(status.laps_to_finish, 25.0);
+        assert_close(status.fuel_to_finish, 25.0 * 3.25);
+        // The rest of the tank, not a full one on top of what is left
+        assert_close(status.fuel_to_add, 60.0 - 53.5);
+    }
+
+    #[test]
+    fn starts_from_the_current_consumption() {
+        let mut tracker = tracker(Some(30), None);
+        let mut telemetry = telemetry(10.0, 25, 0.5);
+        // 81 kg per hour is 108 l per hour, or 3 l in a lap of 100 seconds
+        telemetry.fuel_use_per_hour = 81.0;
+        tracker.update(&telemetry);
+        assert_eq!(tracker.status.average, None);
+        assert_close(tracker.status.per_lap, 3.0);
+        // Four and a half laps, and one more to spare
+        assert_close(tracker.status.fuel_to_add, 5.5 * 3.0 - 10.0);
+    }
+}
@@ -1,17 +1,27 @@
+use async_std::channel;
+use async_std::channel::{ Sender, Receiver };
 use skia_vulkan::skia_safe;
 use skia_vulkan::winit::window::Window;
 
 use crate::overlay::{ Overlay, Drawable, StateUpdater, StateTracker, WindowSpec };
 use crate::iracing::{ Update };
+use crate::iracing::sof;
 
 use async_trait::async_trait;
 
+enum StateUpdate {
+    StrengthOfField(Option<i32>),
+}
+
 pub struct Head2HeadOverlay {
     font: skia_safe::Font,
+    strength_of_field: Option<i32>,
+    receiver: Receiver<StateUpdate>,
 }
 
 pub struct Head2HeadStateTracker {
-
+    strength_of_field: Option<i32>,
+    sender: Sender<StateUpdate>,
 }
 
 impl Head2HeadOverlay {
@@ -27,11 +37,17 @@ impl Head2HeadOverlay {
         let mut font = skia_safe::Font::new(typeface, None);
         font.set_subpixel(true);
 
+        let (sender, receiver) = channel::unbounded();
         (
             Head2HeadOverlay {
-                font
+                font,
+                strength_of_field: None,
+                receiver,
+            },
+            Head2HeadStateTracker {
+                strength_of_field: None,
+                sender,
             },
-            Head2HeadStateTracker { },
         )
     }
 }
@@ -55,9 +71,10 @@ impl Drawable for Head2HeadOverlay {
                     //paint.set_style(skia_safe::paint::Style::Stroke);
                     // paint.set_stroke_width(1.0);
 
+        let header = self.strength_of_field.map_or(String::new(), |sof| format!["SOF {}", sof]);
         canvas.draw_str(
             // "Hello 😀",
-            "Hello",
+            &header,
             skia_safe::Point::new(50.0, 50.0),
             &self.font,
             &paint
@@ -67,13 +84,24 @@ impl Drawable for Head2HeadOverlay {
 
 #[async_trait]
 impl StateTracker for Head2HeadStateTracker {
-    async fn process(&mut self, _update: &Update) {
-        // TODO
+    async fn process(&mut self, update: &Update) {
+        if let Update::Session(session_info) = update {
+            let strength_of_field = sof::session_strength_of_field(session_info);
+            if strength_of_field != self.strength_of_field {
+                self.strength_of_field = strength_of_field;
+                self.sender.send(StateUpdate::StrengthOfField(strength_of_field)).await.unwrap();
+            }
+        }
     }
 }
 
 impl StateUpdater for Head2HeadOverlay {
     fn set_state(&mut self, window: &Window) {
+        while let Ok(update) = self.receiver.try_recv() {
+            match update {
+                StateUpdate::StrengthOfField(strength_of_field) => self.strength_of_field = strength_of_field,
+            }
+        }
         // TODO
     }
 }
@@ -0,0 +1,322 @@
+use std::fmt;
+
+use crate::iracing::{ Update, Telemetry, SessionInfo, SessionEvent, IncidentCounts, ConnectionStatus };
+use crate::iracing::flags::TrackLocation;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Incident {
+    pub car_idx: usize,
+    pub driver: String,
+    pub session_num: i32,
+    pub session_time: f64,
+    pub lap: i32,
+    pub lap_dist: f32,
+    pub track_location: TrackLocation,
+    // The increase of the count, e.g. 2 for a 2x
+    pub points: i32,
+    // The count of the car after the incident
+    pub total: i32,
+}
+
+impl fmt::Display for Incident {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x on lap {} at {:.0}% ({:?}), {}x total",
+            self.points, self.lap, self.lap_dist * 100.0, self.track_location, self.total)
+    }
+}
+
+// The part of the latest telemetry that incidents are placed with
+#[derive(Debug, Default)]
+struct LastTelemetry {
+    session_num: i32,
+    session_time: f64,
+    // Indexed by car
+    car_laps: Vec<i32>,
+    lap_dist_by_car: Vec<f32>,
+    car_track_locations: Vec<TrackLocation>,
+}
+
+impl LastTelemetry {
+    fn update(&mut self, telemetry: &Telemetry) {
+        self.session_num = telemetry.session_num;
+        self.session_time = telemetry.session_time;
+        // Reuses the allocations, as this happens for every telemetry update
+        self.car_laps.clone_from(&telemetry.car_laps);
+        self.lap_dist_by_car.clone_from(&telemetry.lap_dist_by_car);
+        self.car_track_locations.clone_from(&telemetry.car_track_locations);
+    }
+}
+
+// Where and when the incidents of a session were picked up. The player's come from the telemetry
+// as they happen, those of the other drivers from the session info, which the sim updates less often.
+#[derive(Default)]
+pub struct IncidentTracker {
+    player_car_idx: Option<usize>,
+    // Whoever drives the player's car as of the latest session info, a teammate in team events
+    car_driver: String,
+    // Indexed by car
+    names: Vec<String>,
+    counts: IncidentCounts,
+    driver_counts: Vec<i32>,
+    last_telemetry: Option<LastTelemetry>,
+
+    incidents: Vec<Incident>,
+    driver_incidents: Vec<Incident>,
+}
+
+impl IncidentTracker {
+    pub fn new() -> IncidentTracker {
+        IncidentTracker::default()
+    }
+
+    pub fn counts(&self) -> IncidentCounts {
+        self.counts
+    }
+
+    // The player's car, over all sessions
+    pub fn incidents(&self) -> &[Incident] {
+        &self.incidents
+    }
+
+    // The other cars, over all sessions
+    pub fn driver_incidents(&self) -> &[Incident] {
+        &self.driver_incidents
+    }
+
+    fn incident(&self, car_idx: usize, driver: String, points: i32, total: i32) -> Incident {
+        let telemetry = self.last_telemetry.as_ref();
+        Incident {
+            car_idx,
+            driver,
+            session_num: telemetry.map_or(0, |telemetry| telemetry.session_num),
+            session_time: telemetry.map_or(0.0, |telemetry| telemetry.session_time),
+            lap: telemetry.and_then(|telemetry| telemetry.car_laps.get(car_idx).copied()).unwrap_or(0),
+            lap_dist: telemetry.and_then(|telemetry| telemetry.lap_dist_by_car.get(car_idx).copied()).unwrap_or(-1.0),
+            track_location: telemetry.and_then(|telemetry| telemetry.car_track_locations.get(car_idx).copied())
+                .unwrap_or(TrackLocation::NotInWorld),
+            points,
+            total,
+        }
+    }
+
+    fn set_session(&mut self, session_info: &SessionInfo) {
+        self.player_car_idx = Some(session_info.driver.car_idx);
+        self.car_driver = session_info.driver(session_info.driver.car_idx)
+            .map_or_else(|| session_info.driver.username.clone(), |driver| driver.name.clone());
+
+        for driver in &session_info.drivers {
+            if driver.car_idx == session_info.driver.car_idx || driver.is_spectator || driver.is_pace_car {
+                continue;
+            }
+            if self.driver_counts.len() <= driver.car_idx {
+                self.driver_counts.resize(driver.car_idx + 1, 0);
+                self.names.resize(driver.car_idx + 1, String::new());
+            }
+
+            // The counts start over in a new session
+            let last_count = std::mem::replace(&mut self.driver_counts[driver.car_idx], driver.incidents);
+            if driver.incidents > last_count && self.names[driver.car_idx] == driver.name {
+                let incident = self.incident(driver.car_idx, driver.name.clone(), driver.incidents - last_count, driver.incidents);
+                debug!["{} picked up {}", driver.name, incident];
+                self.driver_incidents.push(incident);
+            }
+            self.names[driver.car_idx] = driver.name.clone();
+        }
+    }
+
+    fn update(&mut self, telemetry: &Telemetry) {
+        let car_idx = match self.player_car_idx {
+            Some(car_idx) => car_idx,
+            None => return,
+        };
+
+        let last_session_num = self.last_telemetry.as_ref().map(|last| last.session_num);
+        let counts = telemetry.incidents;
+        self.last_telemetry.get_or_insert_with(LastTelemetry::default).update(telemetry);
+
+        // The counts start over in a new session
+        if last_session_num == Some(telemetry.session_num) && counts.team > self.counts.team {
+            let incident = self.incident(car_idx, self.car_driver.clone(), counts.team - self.counts.team, counts.team);
+            info!["Incident: {}", incident];
+            self.incidents.push(incident);
+        }
+        self.counts = counts;
+    }
+
+    // Where and when the player picked up the incidents of a session
+    pub fn report(&self, session_num: i32) -> Vec<&Incident> {
+        self.incidents.iter().filter(|incident| incident.session_num == session_num).collect()
+    }
+
+    // The same for the other drivers
+    pub fn driver_report(&self, session_num: i32) -> Vec<&Incident> {
+        self.driver_incidents.iter().filter(|incident| incident.session_num == session_num).collect()
+    }
+
+    // The latest incidents of the player in the current session, oldest first
+    pub fn recent(&self, count: usize) -> Vec<Incident> {
+        let session_num = match &self.last_telemetry {
+            Some(telemetry) => telemetry.session_num,
+            None => return vec![],
+        };
+        let report = self.report(session_num);
+        let skip = report.len().saturating_sub(count);
+        report.into_iter().skip(skip).cloned().collect()
+    }
+
+    fn log_report(&self, session_num: i32) {
+        let report = self.report(session_num);
+        if !report.is_empty() {
+            info!["Incidents in session {}:", session_num];
+            for incident in report {
+                info!["  {}", incident];
+            }
+        }
+
+        let driver_report = self.driver_report(session_num);
+        if !driver_report.is_empty() {
+            info!["Incidents of the other drivers in session {}:", session_num];
+            for incident in driver_report {
+                info!["  {}: {}", incident.driver, incident];
+            }
+        }
+    }
+
+    // Returns whether anything changed
+    pub fn process(&mut self, update: &Update) -> bool {
+        match update {
+            Update::Session(session_info) => {
+                let incidents = self.driver_incidents.len();
+                self.set_session(session_info);
+                self.driver_incidents.len() != incidents
+            },
+            Update::Telemetry(telemetry) => {
+                let counts = self.counts;
+                self.update(telemetry);
+                self.counts != counts
+            },
+            Update::SessionEvent(SessionEvent::SessionChanged { from: Some(from), .. }) => {
+                self.log_report(from.num);
+                false
+            },
+            Update::Connection(ConnectionStatus::Disconnected) => {
+                if let Some(telemetry) = &self.last_telemetry {
+                    self.log_report(telemetry.session_num);
+                }
+                false
+            },
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    const DAYTONA_MULTICLASS_RACE: &str = include_str!("../tests/fixtures/session_info/daytona_multiclass_race.yaml");
+
+    // The player drives car 3, car 4 has 8 incidents
+    fn session_info() -> SessionInfo {
+        SessionInfo::try_from(&DAYTONA_MULTICLASS_RACE.to_string()).unwrap()
+    }
+
+    fn with_driver(session_info: &SessionInfo, car_idx: usize, name: &str, incidents: i32) -> SessionInfo {
+        let mut session_info = session_info.clone();
+        let driver = session_info.drivers.iter_mut().find(|driver| driver.car_idx == car_idx).unwrap();
+        driver.name = name.to_string();
+        driver.incidents = incidents;
+        session_info
+    }
+
+    fn telemetry(session_num: i32, session_time: f64, team: i32) -> Update {
+        Update::Telemetry(Telemetry {
+            session_num,
+            session_time,
+            car_laps: vec![0, 7, 7, 5, 6],
+            lap_dist_by_car: vec![-1.0, 0.5, 0.5, 0.25, 0.75],
+            car_track_locations: vec![TrackLocation::OnTrack; 5],
+            incidents: IncidentCounts { my: team, team, driver: team },
+            ..Telemetry::default()
+        })
+    }
+
+    #[test]
+    fn places_the_players_incidents() {
+        let mut tracker = IncidentTracker::new();
+        tracker.process(&Update::Session(session_info()));
+        assert!(!tracker.process(&telemetry(2, 100.0, 0)));
+        assert!(tracker.process(&telemetry(2, 101.0, 2)));
+
+        assert_eq!(tracker.incidents(), [Incident {
+            car_idx: 3,
+            driver: "Kasper Nielsen".to_string(),
+            session_num: 2,
+            session_time: 101.0,
+            lap: 5,
+            lap_dist: 0.25,
+            track_location: TrackLocation::OnTrack,
+            points: 2,
+            total: 2,
+        }]);
+        assert_eq!(tracker.counts().team, 2);
+        assert_eq!(tracker.recent(5).len(), 1);
+    }
+
+    #[test]
+    fn ignores_counts_of_a_new_session() {
+        let mut tracker = IncidentTracker::new();
+        tracker.process(&Update::Session(session_info()));
+        tracker.process(&telemetry(1, 100.0, 4));
+        tracker.process(&telemetry(2, 0.0, 5));
+        assert!(tracker.incidents().is_empty());
+
+        tracker.process(&telemetry(2, 1.0, 6));
+        assert_eq!(tracker.incidents().len(), 1);
+        assert_eq!(tracker.incidents()[0].points, 1);
+    }
+
+    #[test]
+    fn credits_team_incidents_to_the_current_driver() {
+        let mut tracker = IncidentTracker::new();
+        tracker.process(&Update::Session(with_driver(&session_info(), 3, "Teammate", 0)));
+        tracker.process(&telemetry(2, 100.0, 0));
+        tracker.process(&telemetry(2, 101.0, 4));
+        assert_eq!(tracker.incidents()[0].driver, "Teammate");
+    }
+
+    #[test]
+    fn places_incidents_of_other_drivers() {
+        let mut tracker = IncidentTracker::new();
+        let first = session_info();
+        // What the other drivers had before is not an incident yet
+        assert!(!tracker.process(&Update::Session(first.clone())));
+        tracker.process(&telemetry(2, 200.0, 0));
+
+        assert!(tracker.process(&Update::Session(with_driver(&first, 4, "*Lucky* Luca Bianchi", 12))));
+        assert_eq!(tracker.driver_incidents().len(), 1);
+        let incident = &tracker.driver_incidents()[0];
+        assert_eq!(incident.car_idx, 4);
+        assert_eq!(incident.driver, "*Lucky* Luca Bianchi");
+        assert_eq!((incident.points, incident.total), (4, 12));
+        assert_eq!((incident.lap, incident.lap_dist), (6, 0.75));
+        assert_eq!(tracker.driver_report(2).len(), 1);
+        assert!(tracker.incidents().is_empty());
+    }
+
+    #[test]
+    fn ignores_driver_swaps() {
+        let mut tracker = IncidentTracker::new();
+        let first = session_info();
+        tracker.process(&Update::Session(first.clone()));
+
+        // The new driver of car 4 brings their own count
+        assert!(!tracker.process(&Update::Session(with_driver(&first, 4, "Luca's Teammate", 9))));
+        assert!(tracker.driver_incidents().is_empty());
+
+        tracker.process(&Update::Session(with_driver(&first, 4, "Luca's Teammate", 10)));
+        assert_eq!(tracker.driver_incidents().len(), 1);
+        assert_eq!(tracker.driver_incidents()[0].driver, "Luca's Teammate");
+    }
+}
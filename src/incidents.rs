@@ -0,0 +1,147 @@
+use async_std::channel;
+use async_std::channel::{ Sender, Receiver };
+use skia_vulkan::winit::window::Window;
+
+use skia_vulkan::skia_safe;
+use skia_vulkan::skia_safe::{ Paint, Point };
+
+use crate::overlay::{ Overlay, Drawable, StateUpdater, StateTracker, WindowSpec, load_font };
+use crate::iracing::{ Update, IncidentCounts, ConnectionStatus };
+use crate::incident_tracker::{ Incident, IncidentTracker };
+
+use async_trait::async_trait;
+
+// Number of the latest incidents listed below the counter
+const RECENT: usize = 3;
+
+enum StateUpdate {
+    Incidents { counts: IncidentCounts, recent: Vec<Incident> },
+    Limit(Option<i32>),
+    WindowVisible(bool),
+}
+
+pub struct IncidentOverlay {
+    counts: IncidentCounts,
+    limit: Option<i32>,
+    recent: Vec<Incident>,
+    receiver: Receiver<StateUpdate>,
+
+    font: skia_safe::Font,
+    paint_text: Paint,
+    paint_warning: Paint,
+}
+
+pub struct IncidentOverlayState {
+    tracker: IncidentTracker,
+    sender: Sender<StateUpdate>,
+    is_visible: bool,
+    limit: Option<i32>,
+}
+
+impl IncidentOverlay {
+    pub fn new() -> (IncidentOverlay, IncidentOverlayState) {
+        let (sender, receiver) = channel::unbounded();
+
+        (
+            IncidentOverlay {
+                counts: IncidentCounts::default(),
+                limit: None,
+                recent: vec![],
+                receiver,
+
+                font: load_font(16.0),
+                paint_text: Paint::new(skia_safe::Color4f::new(1.0, 1.0, 1.0, 1.0), None),
+                paint_warning: Paint::new(skia_safe::Color4f::new(1.0, 0.3, 0.2, 1.0), None),
+            },
+            IncidentOverlayState {
+                tracker: IncidentTracker::new(),
+                sender,
+                is_visible: false,
+                limit: None,
+            },
+        )
+    }
+}
+
+impl Overlay for IncidentOverlay {
+    fn window_spec(&self) -> WindowSpec {
+        WindowSpec {
+            title: "Incidents".to_string(),
+            width: 220.0,
+            height: 110.0,
+        }
+    }
+}
+
+impl Drawable for IncidentOverlay {
+    fn draw(&mut self, canvas: &mut skia_safe::Canvas, _window_size: (u32, u32)) {
+        canvas.clear(skia_safe::Color::from_argb(150, 0, 0, 0));
+
+        // Within a few incidents of a disqualification
+        let close_to_limit = self.limit.map_or(false, |limit| limit - self.counts.team <= 4);
+        // The limit is on the count of the team, which is the player's own outside of team races
+        let counter = match self.limit {
+            Some(limit) => format!["{}x / {}x", self.counts.team, limit],
+            None => format!["{}x", self.counts.team],
+        };
+        let paint = if close_to_limit { &self.paint_warning } else { &self.paint_text };
+        canvas.draw_str(&counter, Point::new(10.0, 22.0), &self.font, paint);
+
+        if self.counts.team != self.counts.my {
+            canvas.draw_str(&format!["Own {}x", self.counts.my], Point::new(120.0, 22.0), &self.font, &self.paint_text);
+        }
+
+        for (i, incident) in self.recent.iter().rev().enumerate() {
+            let line = format!["L{} {:.0}% {}x", incident.lap, incident.lap_dist * 100.0, incident.points];
+            canvas.draw_str(&line, Point::new(10.0, 50.0 + 20.0 * i as f32), &self.font, &self.paint_text);
+        }
+    }
+}
+
+#[async_trait]
+impl StateTracker for IncidentOverlayState {
+    async fn process(&mut self, update: &Update) {
+        if self.tracker.process(update) {
+            let recent = self.tracker.recent(RECENT);
+            self.sender.send(StateUpdate::Incidents { counts: self.tracker.counts(), recent }).await.unwrap();
+        }
+
+        match update {
+            Update::Session(session_info) => {
+                if session_info.weekend.incident_limit != self.limit {
+                    self.limit = session_info.weekend.incident_limit;
+                    self.sender.send(StateUpdate::Limit(self.limit)).await.unwrap();
+                }
+            },
+            Update::Telemetry(telemetry) => {
+                if self.is_visible != telemetry.is_on_track {
+                    self.sender.send(StateUpdate::WindowVisible(telemetry.is_on_track)).await.unwrap();
+                    self.is_visible = telemetry.is_on_track;
+                }
+            },
+            Update::Connection(status) => {
+                let connected = matches!(status, ConnectionStatus::Connected { .. });
+                if !connected && self.is_visible {
+                    self.sender.send(StateUpdate::WindowVisible(false)).await.unwrap();
+                    self.is_visible = false;
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+impl StateUpdater for IncidentOverlay {
+    fn set_state(&mut self, window: &Window) {
+        while let Ok(update) = self.receiver.try_recv() {
+            match update {
+                StateUpdate::Incidents { counts, recent } => {
+                    self.counts = counts;
+                    self.recent = recent;
+                },
+                StateUpdate::Limit(limit) => self.limit = limit,
+                StateUpdate::WindowVisible(visible) => window.set_visible(visible),
+            }
+        }
+    }
+}
@@ -0,0 +1,398 @@
+#![allow(non_upper_case_globals)]
+
+use std::sync::Mutex;
+
+#[cfg(windows)]
+use windows::{
+    Win32::Foundation::*,
+    Win32::UI::WindowsAndMessaging::*,
+};
+
+use crate::iracing::data_collector::*;
+use crate::iracing::flags::CameraState;
+
+// Which car the camera should focus on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CameraTarget {
+    Incident,
+    Leader,
+    Exiting,
+    // A race position for `SimCommand::CameraSwitchPosition`, an encoded car number
+    // (see `car_number`) for `SimCommand::CameraSwitchNumber`
+    Car(i32),
+}
+
+impl CameraTarget {
+    fn encode(&self) -> i32 {
+        match self {
+            CameraTarget::Incident => irsdk_csMode_irsdk_csFocusAtIncident as i32,
+            CameraTarget::Leader => irsdk_csMode_irsdk_csFocusAtLeader as i32,
+            CameraTarget::Exiting => irsdk_csMode_irsdk_csFocusAtExiting as i32,
+            CameraTarget::Car(target) => irsdk_csMode_irsdk_csFocusAtDriver as i32 + target,
+        }
+    }
+}
+
+// Car numbers are strings in the sim, so leading zeros are encoded in the thousands
+// to tell e.g. "7", "07" and "007" apart. Mirrors `padCarNum` from the irsdk utils.
+pub fn car_number(number: &str) -> Option<i32> {
+    let value: i32 = number.parse().ok()?;
+    let leading_zeros = number.chars().take_while(|&c| c == '0').count() as i32;
+    let leading_zeros = if value == 0 { leading_zeros - 1 } else { leading_zeros };
+    if leading_zeros <= 0 {
+        return Some(value);
+    }
+
+    let digits = match value {
+        0..=9 => 1,
+        10..=99 => 2,
+        _ => 3,
+    };
+    Some(value + 1000 * (digits + leading_zeros))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayPosition {
+    Begin,
+    Current,
+    End,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySearch {
+    ToStart,
+    ToEnd,
+    PrevSession,
+    NextSession,
+    PrevLap,
+    NextLap,
+    PrevFrame,
+    NextFrame,
+    PrevIncident,
+    NextIncident,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChatCommand {
+    // Chat macros are numbered 1-15
+    Macro(u8),
+    BeginChat,
+    Reply,
+    Cancel,
+}
+
+// Pit commands only work while the driver is in the car. Fuel is in liters and tyre
+// pressures in kPa, `None` keeps the amount currently selected in the pit menu.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PitCommand {
+    Clear,
+    Windshield,
+    Fuel(Option<u32>),
+    LeftFront(Option<u32>),
+    RightFront(Option<u32>),
+    LeftRear(Option<u32>),
+    RightRear(Option<u32>),
+    ClearTires,
+    FastRepair,
+    ClearWindshield,
+    ClearFastRepair,
+    ClearFuel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TelemetryCommand {
+    Stop,
+    Start,
+    Restart,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VideoCapture {
+    TriggerScreenShot,
+    Start,
+    End,
+    Toggle,
+    ShowTimer,
+    HideTimer,
+}
+
+// Everything the sim can be remote controlled with, see `irsdk_BroadcastMsg`.
+// Camera and replay commands only work when out of the car.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SimCommand {
+    CameraSwitchPosition { target: CameraTarget, group: i16, camera: i16 },
+    CameraSwitchNumber { target: CameraTarget, group: i16, camera: i16 },
+    CameraSetState(CameraState),
+    // In slow motion the replay is played at 1 / speed
+    ReplaySetPlaySpeed { speed: i16, slow_motion: bool },
+    ReplaySetPlayPosition { position: ReplayPosition, frame: i32 },
+    ReplaySearch(ReplaySearch),
+    ReplayEraseTape,
+    ReplaySearchSessionTime { session_num: i16, session_time_ms: i32 },
+    ReloadAllTextures,
+    ReloadCarTextures { car_idx: i16 },
+    Chat(ChatCommand),
+    Pit(PitCommand),
+    Telemetry(TelemetryCommand),
+    // Maximum force in Nm when mapping steering torque to direct input units
+    FfbMaxForce(f32),
+    VideoCapture(VideoCapture),
+}
+
+// The raw message as sent to the sim, with `var2` holding either one 32 bit value or two 16 bit values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BroadcastMessage {
+    pub msg: irsdk_BroadcastMsg,
+    pub var1: i16,
+    pub var2: i32,
+}
+
+fn make_long(low: i16, high: i16) -> i32 {
+    ((low as u16 as u32) | ((high as u16 as u32) << 16)) as i32
+}
+
+impl SimCommand {
+    pub fn encode(&self) -> BroadcastMessage {
+        let (msg, var1, var2) = match *self {
+            SimCommand::CameraSwitchPosition { target, group, camera } =>
+                (irsdk_BroadcastMsg_irsdk_BroadcastCamSwitchPos, target.encode(), make_long(group, camera)),
+            SimCommand::CameraSwitchNumber { target, group, camera } =>
+                (irsdk_BroadcastMsg_irsdk_BroadcastCamSwitchNum, target.encode(), make_long(group, camera)),
+            SimCommand::CameraSetState(state) =>
+                (irsdk_BroadcastMsg_irsdk_BroadcastCamSetState, state.bits() as i32, 0),
+            SimCommand::ReplaySetPlaySpeed { speed, slow_motion } =>
+                (irsdk_BroadcastMsg_irsdk_BroadcastReplaySetPlaySpeed, speed as i32, make_long(slow_motion as i16, 0)),
+            SimCommand::ReplaySetPlayPosition { position, frame } => {
+                let mode = match position {
+                    ReplayPosition::Begin => irsdk_RpyPosMode_irsdk_RpyPos_Begin,
+                    ReplayPosition::Current => irsdk_RpyPosMode_irsdk_RpyPos_Current,
+                    ReplayPosition::End => irsdk_RpyPosMode_irsdk_RpyPos_End,
+                };
+                (irsdk_BroadcastMsg_irskd_BroadcastReplaySetPlayPosition, mode as i32, frame)
+            },
+            SimCommand::ReplaySearch(search) => {
+                let mode = match search {
+                    ReplaySearch::ToStart => irsdk_RpySrchMode_irsdk_RpySrch_ToStart,
+                    ReplaySearch::ToEnd => irsdk_RpySrchMode_irsdk_RpySrch_ToEnd,
+                    ReplaySearch::PrevSession => irsdk_RpySrchMode_irsdk_RpySrch_PrevSession,
+                    ReplaySearch::NextSession => irsdk_RpySrchMode_irsdk_RpySrch_NextSession,
+                    ReplaySearch::PrevLap => irsdk_RpySrchMode_irsdk_RpySrch_PrevLap,
+                    ReplaySearch::NextLap => irsdk_RpySrchMode_irsdk_RpySrch_NextLap,
+                    ReplaySearch::PrevFrame => irsdk_RpySrchMode_irsdk_RpySrch_PrevFrame,
+                    ReplaySearch::NextFrame => irsdk_RpySrchMode_irsdk_RpySrch_NextFrame,
+                    ReplaySearch::PrevIncident => irsdk_RpySrchMode_irsdk_RpySrch_PrevIncident,
+                    ReplaySearch::NextIncident => irsdk_RpySrchMode_irsdk_RpySrch_NextIncident,
+                };
+                (irsdk_BroadcastMsg_irsdk_BroadcastReplaySearch, mode as i32, 0)
+            },
+            SimCommand::ReplayEraseTape =>
+                (irsdk_BroadcastMsg_irsdk_BroadcastReplaySetState, irsdk_RpyStateMode_irsdk_RpyState_EraseTape as i32, 0),
+            SimCommand::ReplaySearchSessionTime { session_num, session_time_ms } =>
+                (irsdk_BroadcastMsg_irsdk_BroadcastReplaySearchSessionTime, session_num as i32, session_time_ms),
+            SimCommand::ReloadAllTextures =>
+                (irsdk_BroadcastMsg_irsdk_BroadcastReloadTextures, irsdk_ReloadTexturesMode_irsdk_ReloadTextures_All as i32, 0),
+            SimCommand::ReloadCarTextures { car_idx } =>
+                (irsdk_BroadcastMsg_irsdk_BroadcastReloadTextures, irsdk_ReloadTexturesMode_irsdk_ReloadTextures_CarIdx as i32, car_idx as i32),
+            SimCommand::Chat(command) => {
+                let (mode, sub_command) = match command {
+                    ChatCommand::Macro(number) => (irsdk_ChatCommandMode_irsdk_ChatCommand_Macro, number as i32),
+                    ChatCommand::BeginChat => (irsdk_ChatCommandMode_irsdk_ChatCommand_BeginChat, 0),
+                    ChatCommand::Reply => (irsdk_ChatCommandMode_irsdk_ChatCommand_Reply, 0),
+                    ChatCommand::Cancel => (irsdk_ChatCommandMode_irsdk_ChatCommand_Cancel, 0),
+                };
+                (irsdk_BroadcastMsg_irsdk_BroadcastChatComand, mode as i32, sub_command)
+            },
+            SimCommand::Pit(command) => {
+                let amount = |amount: Option<u32>| amount.unwrap_or(0) as i32;
+                let (mode, parameter) = match command {
+                    PitCommand::Clear => (irsdk_PitCommandMode_irsdk_PitCommand_Clear, 0),
+                    PitCommand::Windshield => (irsdk_PitCommandMode_irsdk_PitCommand_WS, 0),
+                    PitCommand::Fuel(liters) => (irsdk_PitCommandMode_irsdk_PitCommand_Fuel, amount(liters)),
+                    PitCommand::LeftFront(pressure) => (irsdk_PitCommandMode_irsdk_PitCommand_LF, amount(pressure)),
+                    PitCommand::RightFront(pressure) => (irsdk_PitCommandMode_irsdk_PitCommand_RF, amount(pressure)),
+                    PitCommand::LeftRear(pressure) => (irsdk_PitCommandMode_irsdk_PitCommand_LR, amount(pressure)),
+                    PitCommand::RightRear(pressure) => (irsdk_PitCommandMode_irsdk_PitCommand_RR, amount(pressure)),
+                    PitCommand::ClearTires => (irsdk_PitCommandMode_irsdk_PitCommand_ClearTires, 0),
+                    PitCommand::FastRepair => (irsdk_PitCommandMode_irsdk_PitCommand_FR, 0),
+                    PitCommand::ClearWindshield => (irsdk_PitCommandMode_irsdk_PitCommand_ClearWS, 0),
+                    PitCommand::ClearFastRepair => (irsdk_PitCommandMode_irsdk_PitCommand_ClearFR, 0),
+                    PitCommand::ClearFuel => (irsdk_PitCommandMode_irsdk_PitCommand_ClearFuel, 0),
+                };
+                (irsdk_BroadcastMsg_irsdk_BroadcastPitCommand, mode as i32, parameter)
+            },
+            SimCommand::Telemetry(command) => {
+                let mode = match command {
+                    TelemetryCommand::Stop => irsdk_TelemCommandMode_irsdk_TelemCommand_Stop,
+                    TelemetryCommand::Start => irsdk_TelemCommandMode_irsdk_TelemCommand_Start,
+                    TelemetryCommand::Restart => irsdk_TelemCommandMode_irsdk_TelemCommand_Restart,
+                };
+                (irsdk_BroadcastMsg_irsdk_BroadcastTelemCommand, mode as i32, 0)
+            },
+            // Floats are sent as 16.16 fixed point
+            SimCommand::FfbMaxForce(newton_meters) =>
+                (irsdk_BroadcastMsg_irsdk_BroadcastFFBCommand, irsdk_FFBCommandMode_irsdk_FFBCommand_MaxForce as i32, (newton_meters * 65536.0) as i32),
+            SimCommand::VideoCapture(capture) => {
+                let mode = match capture {
+                    VideoCapture::TriggerScreenShot => irsdk_VideoCaptureMode_irsdk_VideoCapture_TriggerScreenShot,
+                    VideoCapture::Start => irsdk_VideoCaptureMode_irsdk_VideoCaptuer_StartVideoCapture,
+                    VideoCapture::End => irsdk_VideoCaptureMode_irsdk_VideoCaptuer_EndVideoCapture,
+                    VideoCapture::Toggle => irsdk_VideoCaptureMode_irsdk_VideoCaptuer_ToggleVideoCapture,
+                    VideoCapture::ShowTimer => irsdk_VideoCaptureMode_irsdk_VideoCaptuer_ShowVideoTimer,
+                    VideoCapture::HideTimer => irsdk_VideoCaptureMode_irsdk_VideoCaptuer_HideVideoTimer,
+                };
+                (irsdk_BroadcastMsg_irsdk_BroadcastVideoCapture, mode as i32, 0)
+            },
+        };
+
+        BroadcastMessage { msg: msg as irsdk_BroadcastMsg, var1: var1 as i16, var2 }
+    }
+}
+
+// Something that can deliver commands to the sim. Shared between overlays and
+// hotkeys, so sending only requires a shared reference.
+pub trait CommandSink: Send + Sync {
+    fn send(&self, command: SimCommand) -> Result<(), String>;
+}
+
+#[cfg(windows)]
+const HWND_BROADCAST: HWND = HWND(0xffff);
+
+// Sends commands the same way as `irsdk_broadcastMsg`, as a window message to all top level windows
+#[cfg(windows)]
+pub struct WindowsCommandSink {
+    message_id: u32,
+}
+
+#[cfg(windows)]
+impl WindowsCommandSink {
+    pub fn new() -> Result<WindowsCommandSink, String> {
+        let message_name: Vec<u16> = "IRSDK_BROADCASTMSG".encode_utf16().chain(Some(0)).collect();
+        let message_id = unsafe { RegisterWindowMessageW(windows::core::PCWSTR(message_name.as_ptr())) };
+        if message_id == 0 {
+            return Err(format!["Failed to register the iRacing broadcast message: {:?}", unsafe { GetLastError() }]);
+        }
+
+        Ok(WindowsCommandSink { message_id })
+    }
+}
+
+#[cfg(windows)]
+impl CommandSink for WindowsCommandSink {
+    fn send(&self, command: SimCommand) -> Result<(), String> {
+        let message = command.encode();
+        let wparam = make_long(message.msg as i16, message.var1) as u32;
+
+        debug!["Sending {:?} as {:?}", command, message];
+        let sent = unsafe {
+            SendNotifyMessageW(HWND_BROADCAST, self.message_id, WPARAM(wparam as usize), LPARAM(message.var2 as isize))
+        };
+        if !sent.as_bool() {
+            return Err(format!["Failed to send {:?}: {:?}", command, unsafe { GetLastError() }]);
+        }
+        Ok(())
+    }
+}
+
+// Keeps the commands in memory instead of sending them, for tests and for sources without a sim
+#[derive(Default)]
+pub struct RecordingCommandSink {
+    commands: Mutex<Vec<SimCommand>>,
+}
+
+impl RecordingCommandSink {
+    pub fn new() -> RecordingCommandSink {
+        RecordingCommandSink::default()
+    }
+
+    pub fn commands(&self) -> Vec<SimCommand> {
+        self.commands.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        self.commands.lock().unwrap().clear();
+    }
+}
+
+impl CommandSink for RecordingCommandSink {
+    fn send(&self, command: SimCommand) -> Result<(), String> {
+        debug!["Recording {:?}", command];
+        self.commands.lock().unwrap().push(command);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_two_shorts_into_a_long() {
+        assert_eq!(make_long(0x1234, 0x5678), 0x5678_1234);
+        // The low half must not sign extend into the high half
+        assert_eq!(make_long(-1, 0), 0x0000_ffff);
+        assert_eq!(make_long(0, -1), 0xffff_0000u32 as i32);
+    }
+
+    #[test]
+    fn encodes_camera_commands() {
+        let message = SimCommand::CameraSwitchNumber { target: CameraTarget::Car(2007), group: 3, camera: 1 }.encode();
+        assert_eq!(message, BroadcastMessage { msg: irsdk_BroadcastMsg_irsdk_BroadcastCamSwitchNum, var1: 2007, var2: 0x0001_0003 });
+
+        let message = SimCommand::CameraSwitchPosition { target: CameraTarget::Leader, group: -1, camera: 0 }.encode();
+        assert_eq!(message.var1, -2);
+        assert_eq!(message.var2, 0x0000_ffff);
+
+        let message = SimCommand::CameraSetState(CameraState::UIHidden | CameraState::UseAutoShotSelection).encode();
+        assert_eq!(message.msg, irsdk_BroadcastMsg_irsdk_BroadcastCamSetState);
+        assert_eq!(message.var1 as u32, (CameraState::UIHidden | CameraState::UseAutoShotSelection).bits());
+    }
+
+    #[test]
+    fn encodes_replay_and_pit_commands() {
+        let message = SimCommand::ReplaySetPlaySpeed { speed: 4, slow_motion: true }.encode();
+        assert_eq!(message, BroadcastMessage { msg: irsdk_BroadcastMsg_irsdk_BroadcastReplaySetPlaySpeed, var1: 4, var2: 1 });
+
+        let message = SimCommand::ReplaySearchSessionTime { session_num: 2, session_time_ms: 1_234_567 }.encode();
+        assert_eq!(message.var1, 2);
+        assert_eq!(message.var2, 1_234_567);
+
+        let message = SimCommand::Pit(PitCommand::Fuel(Some(45))).encode();
+        assert_eq!(message, BroadcastMessage {
+            msg: irsdk_BroadcastMsg_irsdk_BroadcastPitCommand,
+            var1: irsdk_PitCommandMode_irsdk_PitCommand_Fuel as i16,
+            var2: 45,
+        });
+        assert_eq!(SimCommand::Pit(PitCommand::Fuel(None)).encode().var2, 0);
+    }
+
+    #[test]
+    fn encodes_ffb_force_as_fixed_point() {
+        let message = SimCommand::FfbMaxForce(12.5).encode();
+        assert_eq!(message.msg, irsdk_BroadcastMsg_irsdk_BroadcastFFBCommand);
+        assert_eq!(message.var1, irsdk_FFBCommandMode_irsdk_FFBCommand_MaxForce as i16);
+        assert_eq!(message.var2, (12 << 16) | 0x8000);
+    }
+
+    #[test]
+    fn encodes_leading_zeros_of_car_numbers() {
+        assert_eq!(car_number("7"), Some(7));
+        assert_eq!(car_number("07"), Some(2007));
+        assert_eq!(car_number("007"), Some(3007));
+        assert_eq!(car_number("42"), Some(42));
+        assert_eq!(car_number("042"), Some(3042));
+        assert_eq!(car_number("0"), Some(0));
+        assert_eq!(car_number("00"), Some(2000));
+        assert_eq!(car_number("A1"), None);
+    }
+
+    #[test]
+    fn records_commands() {
+        let sink = RecordingCommandSink::new();
+        sink.send(SimCommand::Pit(PitCommand::Clear)).unwrap();
+        sink.send(SimCommand::ReplaySearch(ReplaySearch::NextLap)).unwrap();
+        assert_eq!(sink.commands(), vec![SimCommand::Pit(PitCommand::Clear), SimCommand::ReplaySearch(ReplaySearch::NextLap)]);
+
+        sink.clear();
+        assert!(sink.commands().is_empty());
+    }
+}
@@ -0,0 +1,296 @@
+use std::fmt;
+
+use crate::iracing::session_info::Section;
+
+// A value from the garage screen, e.g. `152 kPa`, `54.0%`, `32C, 32C, 32C` or `Soft`
+#[derive(Debug, Clone, PartialEq)]
+pub enum SetupValue {
+    Quantity { value: f64, unit: String },
+    List(Vec<SetupValue>),
+    Text(String),
+}
+
+impl SetupValue {
+    pub fn parse(raw: &str) -> SetupValue {
+        let raw = raw.trim();
+        if raw.contains(", ") {
+            return SetupValue::List(raw.split(", ").map(SetupValue::parse).collect());
+        }
+
+        let number_len = raw.find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+            .unwrap_or(raw.len());
+        let unit = raw[number_len..].trim();
+        let is_unit = !unit.starts_with(|c: char| c.is_ascii_digit())
+            && unit.chars().all(|c| c.is_alphabetic() || "%/°^".contains(c));
+        match raw[..number_len].parse::<f64>() {
+            Ok(value) if is_unit => SetupValue::Quantity { value, unit: unit.to_string() },
+            _ => SetupValue::Text(raw.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for SetupValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SetupValue::Quantity { value, unit } if unit.is_empty() || unit == "%" => write!(f, "{}{}", value, unit),
+            SetupValue::Quantity { value, unit } => write!(f, "{} {}", value, unit),
+            SetupValue::List(values) => {
+                let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+                write!(f, "{}", values.join(", "))
+            },
+            SetupValue::Text(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SetupNode {
+    Value(SetupValue),
+    // In the order of the garage screen
+    Group(Vec<(String, SetupNode)>),
+}
+
+impl SetupNode {
+    // Values that are left empty are left out
+    fn parse(yaml: &Section) -> SetupNode {
+        SetupNode::Group(yaml.keys().into_iter()
+            .filter_map(|key| {
+                let node = if yaml.is_mapping(&key) {
+                    SetupNode::parse(&yaml.section(&key))
+                } else {
+                    let value = yaml.string(&key);
+                    if value.is_empty() {
+                        return None;
+                    }
+                    SetupNode::Value(SetupValue::parse(&value))
+                };
+                Some((key, node))
+            })
+            .collect())
+    }
+
+    fn flatten<'a>(&'a self, path: &str, values: &mut Vec<(String, &'a SetupValue)>) {
+        match self {
+            SetupNode::Value(value) => values.push((path.to_string(), value)),
+            SetupNode::Group(children) => {
+                for (key, child) in children {
+                    let child_path = if path.is_empty() { key.clone() } else { format!["{}.{}", path, key] };
+                    child.flatten(&child_path, values);
+                }
+            },
+        }
+    }
+}
+
+// The `CarSetup` section of the session info, which only covers the player's car
+#[derive(Debug, Clone, PartialEq)]
+pub struct CarSetup {
+    // Bumped by the sim whenever the setup is changed in the garage
+    pub update_count: i64,
+    pub root: SetupNode,
+}
+
+impl CarSetup {
+    // None when there is no setup, e.g. while spectating
+    pub fn parse(yaml: &Section) -> Option<CarSetup> {
+        if yaml.keys().is_empty() {
+            return None;
+        }
+        match SetupNode::parse(yaml) {
+            SetupNode::Group(children) => Some(CarSetup {
+                update_count: yaml.int("UpdateCount"),
+                root: SetupNode::Group(children.into_iter().filter(|(key, _)| key != "UpdateCount").collect()),
+            }),
+            SetupNode::Value(_) => None,
+        }
+    }
+
+    // All values keyed by their path, e.g. `Chassis.LeftFront.Camber`
+    pub fn values(&self) -> Vec<(String, &SetupValue)> {
+        let mut values = vec![];
+        self.root.flatten("", &mut values);
+        values
+    }
+
+    pub fn get(&self, path: &str) -> Option<&SetupValue> {
+        self.values().into_iter()
+            .find(|(value_path, _)| value_path == path)
+            .map(|(_, value)| value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetupChange {
+    pub path: String,
+    pub from: Option<SetupValue>,
+    pub to: Option<SetupValue>,
+}
+
+impl fmt::Display for SetupChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.from, &self.to) {
+            (Some(SetupValue::Quantity { value: from, unit: from_unit }), Some(SetupValue::Quantity { value: to, unit }))
+                if from_unit == unit => {
+                let delta = format_delta(to - from);
+                if unit.is_empty() || unit == "%" {
+                    write!(f, "{} {}{}", self.path, delta, unit)
+                } else {
+                    write!(f, "{} {} {}", self.path, delta, unit)
+                }
+            },
+            (Some(from), Some(to)) => write!(f, "{} {} -> {}", self.path, from, to),
+            (None, Some(to)) => write!(f, "{} added: {}", self.path, to),
+            (Some(from), None) => write!(f, "{} removed: {}", self.path, from),
+            (None, None) => write!(f, "{} unchanged", self.path),
+        }
+    }
+}
+
+// Signed, and without the noise of floating point subtraction, e.g. `+2` or `-0.5`
+fn format_delta(delta: f64) -> String {
+    let formatted = format!["{:+.3}", delta];
+    formatted.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+// Everything that differs between two setups, e.g. `TiresAero.AeroSettings.RearWingAngle +2 deg,
+// TiresAero.LeftFront.StartingPressure -3 kPa`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetupReport {
+    pub changes: Vec<SetupChange>,
+}
+
+impl SetupReport {
+    pub fn diff(from: &CarSetup, to: &CarSetup) -> SetupReport {
+        let from_values = from.values();
+        let to_values = to.values();
+
+        let mut changes = vec![];
+        for (path, to_value) in &to_values {
+            let from_value = from_values.iter().find(|(from_path, _)| from_path == path).map(|(_, value)| *value);
+            if from_value != Some(*to_value) {
+                changes.push(SetupChange { path: path.clone(), from: from_value.cloned(), to: Some((*to_value).clone()) });
+            }
+        }
+        for (path, from_value) in &from_values {
+            if !to_values.iter().any(|(to_path, _)| to_path == path) {
+                changes.push(SetupChange { path: path.clone(), from: Some((*from_value).clone()), to: None });
+            }
+        }
+
+        SetupReport { changes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    // Only changes made in the garage, not the tyre temperatures and wear reported after a stint
+    pub fn adjustments(&self) -> SetupReport {
+        SetupReport {
+            changes: self.changes.iter()
+                .filter(|change| !change.path.contains("LastHotPressure")
+                    && !change.path.contains("LastTemps")
+                    && !change.path.contains("TreadRemaining"))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for SetupReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let changes: Vec<String> = self.changes.iter().map(|change| change.to_string()).collect();
+        write!(f, "{}", changes.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use yaml_rust::YamlLoader;
+
+    const SETUP: &str = "
+UpdateCount: 3
+TiresAero:
+ LeftFront:
+  StartingPressure: 152 kPa
+  LastTempsOMI: 32C, 32C, 32C
+ AeroSettings:
+  RearWingAngle: 8 deg
+  TyreType: Soft
+Chassis:
+ Front:
+  BrakePressureBias: 54.0%
+  AbsSetting: 7 (ABS)
+  ArbBlades: 3
+  ToeIn: -2.0 mm
+  Unset:
+";
+
+    fn setup(yaml: &str) -> CarSetup {
+        let documents = YamlLoader::load_from_str(yaml).unwrap();
+        let errors = RefCell::new(vec![]);
+        let setup = CarSetup::parse(&Section::root(&documents[0], &errors)).unwrap();
+        assert!(errors.borrow().is_empty(), "{:?}", errors.borrow());
+        setup
+    }
+
+    fn quantity(value: f64, unit: &str) -> SetupValue {
+        SetupValue::Quantity { value, unit: unit.to_string() }
+    }
+
+    #[test]
+    fn parses_values() {
+        assert_eq!(SetupValue::parse("152 kPa"), quantity(152.0, "kPa"));
+        assert_eq!(SetupValue::parse("54.0%"), quantity(54.0, "%"));
+        assert_eq!(SetupValue::parse("-2.0 mm"), quantity(-2.0, "mm"));
+        assert_eq!(SetupValue::parse("3"), quantity(3.0, ""));
+        assert_eq!(SetupValue::parse("32C, 31C"), SetupValue::List(vec![quantity(32.0, "C"), quantity(31.0, "C")]));
+        assert_eq!(SetupValue::parse("Soft"), SetupValue::Text("Soft".to_string()));
+        assert_eq!(SetupValue::parse("7 (ABS)"), SetupValue::Text("7 (ABS)".to_string()));
+        assert_eq!(SetupValue::parse("2x2"), SetupValue::Text("2x2".to_string()));
+    }
+
+    #[test]
+    fn formats_deltas_without_noise() {
+        assert_eq!(format_delta(2.0), "+2");
+        assert_eq!(format_delta(-3.0), "-3");
+        assert_eq!(format_delta(54.5 - 54.0), "+0.5");
+        assert_eq!(format_delta(0.1 + 0.2 - 0.3), "+0");
+    }
+
+    #[test]
+    fn parses_the_setup_tree() {
+        let setup = setup(SETUP);
+        assert_eq!(setup.update_count, 3);
+        assert_eq!(setup.get("TiresAero.LeftFront.StartingPressure"), Some(&quantity(152.0, "kPa")));
+        assert_eq!(setup.get("Chassis.Front.ArbBlades"), Some(&quantity(3.0, "")));
+        // Neither the update count nor empty values are part of the setup
+        assert_eq!(setup.get("UpdateCount"), None);
+        assert_eq!(setup.get("Chassis.Front.Unset"), None);
+        assert_eq!(setup.values().len(), 8);
+    }
+
+    #[test]
+    fn reports_changes() {
+        let from = setup(SETUP);
+        let to = setup(&SETUP
+            .replace("RearWingAngle: 8 deg", "RearWingAngle: 10 deg")
+            .replace("StartingPressure: 152 kPa", "StartingPressure: 149 kPa")
+            .replace("LastTempsOMI: 32C, 32C, 32C", "LastTempsOMI: 80C, 84C, 86C")
+            .replace("TyreType: Soft", "TyreType: Medium")
+            .replace("  ArbBlades: 3\n", ""));
+
+        let report = SetupReport::diff(&from, &to);
+        assert_eq!(report.to_string(), "TiresAero.LeftFront.StartingPressure -3 kPa, \
+            TiresAero.LeftFront.LastTempsOMI 32 C, 32 C, 32 C -> 80 C, 84 C, 86 C, \
+            TiresAero.AeroSettings.RearWingAngle +2 deg, \
+            TiresAero.AeroSettings.TyreType Soft -> Medium, \
+            Chassis.Front.ArbBlades removed: 3");
+
+        let adjustments = report.adjustments();
+        assert_eq!(adjustments.changes.len(), 4);
+        assert!(SetupReport::diff(&from, &from).is_empty());
+    }
+}
@@ -4,31 +4,42 @@
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 
 
-use async_std::sync::Mutex;
-
+#[cfg(windows)]
 use windows::core::PCWSTR;
+#[cfg(windows)]
 use windows::{
     Win32::Foundation::*,
     Win32::System::Threading::*,
     Win32::System::Memory::*,
 };
+#[cfg(windows)]
 use std::ffi::OsStr;
-use std::ffi::CStr;
+#[cfg(windows)]
 use std::os::windows::ffi::OsStrExt;
-use std::sync::Arc;
-use std::time::Duration;
+use std::ffi::CStr;
+use std::fs::File;
+use std::path::Path;
+use std::time::{ Duration, Instant };
 use async_std::task;
 use async_std::task::{Context, Poll};
 use async_std::stream::Stream;
 use async_std::pin::Pin;
 
+use memmap2::Mmap;
+
+use crate::iracing::ConnectionStatus;
+use crate::iracing::snapshot::{ LineBuffer, Snapshot, VarKind };
+
+#[cfg(windows)]
 const SYNCHRONIZE: u32 = 0x00100000;
 // const FILE_MAP_READ: u32 = 0x4;
 
+#[cfg(windows)]
 struct PcwString {
     content: Vec<u16>,
 }
 
+#[cfg(windows)]
 impl PcwString {
     fn from(input: &str) -> PcwString {
         let mut copy: Vec<u16> = OsStr::new(input).encode_wide().chain(Some(0)).collect();
@@ -42,7 +53,7 @@ impl PcwString {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum IracingValue {
     Double(f64),
     DoubleVector(Vec<f64>),
@@ -51,6 +62,12 @@ pub enum IracingValue {
     Float(f32),
     FloatVector(Vec<f32>),
     Boolean(bool),
+    BooleanVector(Vec<bool>),
+    Char(u8),
+    CharVector(Vec<u8>),
+    // The raw bits, see `flags` for typed wrappers of the known bitfields
+    Bitfield(u32),
+    BitfieldVector(Vec<u32>),
 
     Unknown
 }
@@ -60,19 +77,39 @@ pub struct DataHeader {
     pub name: String,
     pub description: String,
     pub unit: String,
+
+    pub kind: Option<VarKind>,
+    pub offset: usize,
+    pub count: usize,
+}
+
+// Where the irsdk memory layout is mapped from, and thereby how we wait for new data
+enum Mapping {
+    // The sim's own named file mapping, signalling new data through a Win32 event
+    #[cfg(windows)]
+    Win32 { mem_file: HANDLE, event_file: HANDLE },
+    // A regular file or /dev/shm region with the same layout, e.g. exported from a
+    // sim running under Wine. Without an event to wait on, `varBuf[].tickCount` is polled.
+    // Whatever wrote it may not be done yet, so the header is checked against its length.
+    File { _mmap: Mmap, len: usize },
 }
 
 pub struct IracingConnection {
-    mem_file: HANDLE,
+    mapping: Mapping,
     header: *mut irsdk_header,
-    event_file: HANDLE,
 
     seen_tick_count: i32,
-    buffer: Vec<u8>,
+    last_data_at: Instant,
+    buffer: LineBuffer,
 
     session_info_seen_tick_count: i32,
 
-    should_disconnect: Arc<Mutex<bool>>,
+    // Number of copies that had to be retried because the sim overwrote the data while copying
+    torn_reads: u64,
+
+    // Whether `ConnectionStatus::Connected` was emitted, and whether the stream has ended
+    announced: bool,
+    ended: bool,
 }
 
 unsafe impl Send for IracingConnection {}
@@ -81,24 +118,59 @@ impl Drop for IracingConnection {
     fn drop(self: &mut IracingConnection) {
         info!("Dropping iRacing connection!");
 
-        unsafe {
-            CloseHandle(self.event_file);
-            UnmapViewOfFile(self.header as *mut std::ffi::c_void);
-            CloseHandle(self.mem_file);
+        match self.mapping {
+            #[cfg(windows)]
+            Mapping::Win32 { mem_file, event_file } => unsafe {
+                CloseHandle(event_file);
+                UnmapViewOfFile(self.header as *mut std::ffi::c_void);
+                CloseHandle(mem_file);
+            },
+            // The memory map is unmapped when dropped
+            Mapping::File { .. } => (),
+        }
+    }
+}
+
+// Errors on an established connection, after which the connection can still be used
+#[derive(Debug)]
+pub enum CollectorError {
+    // The sim kept overwriting the telemetry line while we were copying it
+    TornRead { attempts: u32 },
+    TornSessionInfo { attempts: u32 },
+}
+
+impl std::fmt::Display for CollectorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CollectorError::TornRead { attempts } =>
+                write!(f, "Telemetry line changed while copying it {} times", attempts),
+            CollectorError::TornSessionInfo { attempts } =>
+                write!(f, "Session info changed while copying it {} times", attempts),
         }
     }
 }
 
+#[derive(Debug)]
 pub enum IracingConnectionError {
     NotRunning,
+    // The source has no more data to offer, e.g. a replayed file reached its end
+    Exhausted,
+    Failed(String),
 }
 
 impl IracingConnection {
+    #[cfg(windows)]
     pub fn new() -> Result<IracingConnection, IracingConnectionError> {
         let mut mmap_filename = PcwString::from("Local\\IRSDKMemMapFileName");
         let mut event_filename = PcwString::from("Local\\IRSDKDataValidEvent");
 
-        let mem_file = unsafe { OpenFileMappingW(FILE_MAP_READ.0, false, mmap_filename.pcwstr()) }.unwrap();
+        let mem_file = match unsafe { OpenFileMappingW(FILE_MAP_READ.0, false, mmap_filename.pcwstr()) } {
+            Ok(mem_file) => mem_file,
+            Err(err) => {
+                debug!("Failed to open the iRacing memory map: {:?}", err);
+                return Err(IracingConnectionError::NotRunning);
+            }
+        };
         drop(mmap_filename);
 
         info!("Mmap file: {:?}, error: {:?}", mem_file, unsafe { GetLastError() });
@@ -113,72 +185,324 @@ impl IracingConnection {
 
         let header = mmap as *mut irsdk_header;
 
-        let event_file = unsafe { OpenEventW(SYNCHRONIZE, false, event_filename.pcwstr()) }.unwrap();
+        let event_file = match unsafe { OpenEventW(SYNCHRONIZE, false, event_filename.pcwstr()) } {
+            Ok(event_file) => event_file,
+            Err(err) => unsafe {
+                UnmapViewOfFile(mmap);
+                CloseHandle(mem_file);
+                return Err(IracingConnectionError::Failed(format!["Failed to open the iRacing data event: {:?}", err]));
+            }
+        };
         drop(event_filename);
         info!("Event handle: {:?}, error: {:?}", event_file, unsafe { GetLastError() });
 
-        Ok(IracingConnection {
-            mem_file, header, event_file,
+        IracingConnection::from_mapping(Mapping::Win32 { mem_file, event_file }, header).check_running()
+    }
+
+    #[cfg(not(windows))]
+    pub fn new() -> Result<IracingConnection, IracingConnectionError> {
+        Err(IracingConnectionError::Failed(
+            "The iRacing memory map can only be opened directly on Windows, specify a memory file instead".to_string()))
+    }
+
+    pub fn from_file(path: &Path) -> Result<IracingConnection, IracingConnectionError> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                info!("Memory file {} does not exist", path.display());
+                return Err(IracingConnectionError::NotRunning);
+            },
+            Err(err) => return Err(IracingConnectionError::Failed(format!["Failed to open {}: {}", path.display(), err])),
+        };
+
+        let mmap = unsafe { Mmap::map(&file) }
+            .map_err(|err| IracingConnectionError::Failed(format!["Failed to map {}: {}", path.display(), err]))?;
+        if mmap.len() < std::mem::size_of::<irsdk_header>() {
+            info!("Memory file {} does not contain an irsdk header yet", path.display());
+            return Err(IracingConnectionError::NotRunning);
+        }
+        info!("The memmap of {} is set up!", path.display());
+
+        let header = mmap.as_ptr() as *mut irsdk_header;
+        let len = mmap.len();
+        IracingConnection::from_mapping(Mapping::File { _mmap: mmap, len }, header).check_running()
+    }
+
+    fn from_mapping(mapping: Mapping, header: *mut irsdk_header) -> IracingConnection {
+        IracingConnection {
+            mapping, header,
             seen_tick_count: -1,
+            last_data_at: Instant::now(),
             session_info_seen_tick_count: -1,
-            buffer: vec![],
-            should_disconnect: Arc::new(Mutex::new(false)),
-        })
+            buffer: LineBuffer::default(),
+            torn_reads: 0,
+            announced: false,
+            ended: false,
+        }
+    }
+
+    // The memory map outlives the sessions of the sim, so it existing does not mean there is any data in it
+    fn check_running(self) -> Result<IracingConnection, IracingConnectionError> {
+        if self.version() == IRSDK_VER as i32 {
+            if !self.is_connected() {
+                info!("iRacing is not in a session");
+                return Err(IracingConnectionError::NotRunning);
+            }
+            if let Err(err) = self.check_layout() {
+                info!("The memory map is not complete yet: {}", err);
+                return Err(IracingConnectionError::NotRunning);
+            }
+        }
+        Ok(self)
+    }
+
+    // Whether `len` bytes from `offset` are within the mapping. The size of the sim's own
+    // mapping is not known, but it is trusted to be laid out as its header says.
+    fn fits(&self, offset: i32, len: i32) -> bool {
+        match self.mapping {
+            #[cfg(windows)]
+            Mapping::Win32 { .. } => true,
+            Mapping::File { len: mapping_len, .. } => offset >= 0 && len >= 0
+                && (offset as usize).checked_add(len as usize).map_or(false, |end| end <= mapping_len),
+        }
+    }
+
+    // Checks that everything the header points to is within the mapping
+    fn check_layout(&self) -> Result<(), String> {
+        let header = unsafe { &*self.header };
+        if header.numBuf < 1 || header.numBuf as usize > header.varBuf.len() {
+            return Err(format!["{} telemetry buffers", header.numBuf]);
+        }
+        for buf in &header.varBuf[..header.numBuf as usize] {
+            if !self.fits(buf.bufOffset, header.bufLen) {
+                return Err(format!["Telemetry line of {} bytes at {} is out of bounds", header.bufLen, buf.bufOffset]);
+            }
+        }
+        if !self.fits(header.sessionInfoOffset, header.sessionInfoLen) {
+            return Err(format!["Session info of {} bytes at {} is out of bounds", header.sessionInfoLen, header.sessionInfoOffset]);
+        }
+        let var_headers_len = header.numVars.checked_mul(std::mem::size_of::<irsdk_varHeader>() as i32).unwrap_or(-1);
+        if !self.fits(header.varHeaderOffset, var_headers_len) {
+            return Err(format!["{} variable headers at {} are out of bounds", header.numVars, header.varHeaderOffset]);
+        }
+        Ok(())
+    }
+
+    fn version(&self) -> i32 {
+        unsafe { (*self.header).ver }
+    }
+
+    fn is_connected(&self) -> bool {
+        unsafe {
+            (*self.header).status & irsdk_StatusField_irsdk_stConnected as i32 != 0 && (*self.header).tickRate > 0
+        }
+    }
+
+    // Checks the health of the connection, returning the event to emit if it changed
+    fn connection_event(&mut self) -> Option<ConnectionStatus> {
+        if self.version() != IRSDK_VER as i32 {
+            warn!["iRacing uses irsdk version {}, but we only understand version {}", self.version(), IRSDK_VER];
+            self.ended = true;
+            return Some(ConnectionStatus::VersionMismatch { expected: IRSDK_VER as i32, found: self.version() });
+        }
+
+        if !self.is_connected() {
+            info!["iRacing reports it is no longer connected"];
+            self.ended = true;
+            return Some(ConnectionStatus::Disconnected);
+        }
+
+        if !self.announced {
+            self.announced = true;
+            return Some(ConnectionStatus::Connected { tick_rate: self.tick_rate() });
+        }
+
+        None
+    }
+
+    // The sim writes a new line every tick, so checking a few times per tick is plenty
+    fn poll_interval(&self) -> Duration {
+        let tick_rate = unsafe { (*self.header).tickRate };
+        if tick_rate > 0 {
+            Duration::from_secs_f64(1.0 / (2 * tick_rate) as f64)
+        } else {
+            Duration::from_millis(8)
+        }
+    }
+
+    pub fn tick_rate(&self) -> i32 {
+        unsafe { (*self.header).tickRate }
+    }
+
+    pub fn snapshot(&self) -> Snapshot<'_> {
+        self.buffer.snapshot()
+    }
+
+    pub fn torn_reads(&self) -> u64 {
+        self.torn_reads
     }
 
-    pub fn headers(&self) -> Vec<DataHeader> {
+    fn newest_buffer(&self) -> usize {
         unsafe {
-            let num_headers = (*self.header).numVars as isize;
-            let mut headers = Vec::with_capacity(num_headers as usize);
+            let var_bufs = &(*self.header).varBuf;
+            let num_buffers = ((*self.header).numBuf.max(1) as usize).min(var_bufs.len());
+            var_bufs[..num_buffers].iter().enumerate()
+                .max_by_key(|(_, buf)| buf.tickCount)
+                .unwrap().0
+        }
+    }
+
+    // Copies the newest telemetry line into our buffer, returning its tick count.
+    // The sim rotates between its buffers, so if the one we copy from is overwritten
+    // meanwhile we start over from whichever buffer is the newest at that point.
+    // Err when the header points outside of the mapping.
+    fn copy_line(&mut self) -> Result<Option<i32>, String> {
+        for _ in 0..MAX_COPY_ATTEMPTS {
+            unsafe {
+                let idx = self.newest_buffer();
+                let tick_count_before = (*self.header).varBuf[idx].tickCount;
+
+                // Read once, so what is copied is what was checked
+                let buffer_length = (*self.header).bufLen;
+                let buffer_offset = (*self.header).varBuf[idx].bufOffset;
+                if !self.fits(buffer_offset, buffer_length) {
+                    return Err(format!["Telemetry line of {} bytes at {} is out of bounds", buffer_length, buffer_offset]);
+                }
+                let new_buffer_length = buffer_length as usize;
+                if self.buffer.len() != new_buffer_length {
+                    self.buffer.resize(new_buffer_length);
+                }
+
+                let values_ptr = (self.header as *const u8).offset(buffer_offset as isize);
+                self.buffer.as_bytes_mut().copy_from_slice(std::slice::from_raw_parts(values_ptr, new_buffer_length));
+                if (*self.header).varBuf[idx].tickCount == tick_count_before {
+                    return Ok(Some(tick_count_before));
+                }
+            }
 
-            let var_headers = (self.header as *const u8).offset((*self.header).varHeaderOffset as isize) as *mut irsdk_varHeader;
+            self.torn_reads += 1;
+            debug!["Telemetry line changed while copying, retrying"];
+        }
 
-            for i in 0..num_headers {
-                let var_header = *((var_headers.offset(i)) as *const irsdk_varHeader) as irsdk_varHeader;
+        warn!["Telemetry line kept changing while copying, giving up after {} attempts", MAX_COPY_ATTEMPTS];
+        Ok(None)
+    }
 
-                let name = String::from(CStr::from_ptr(var_header.name.as_ptr()).to_str().unwrap());
-                let description = String::from(CStr::from_ptr(var_header.desc.as_ptr()).to_str().unwrap());
-                let unit = String::from(CStr::from_ptr(var_header.unit.as_ptr()).to_str().unwrap());
+    fn copy_session_info(&mut self) -> Result<Option<(i32, String)>, String> {
+        for _ in 0..MAX_COPY_ATTEMPTS {
+            unsafe {
+                let tick_count = (*self.header).sessionInfoUpdate;
+                let len = (*self.header).sessionInfoLen;
+                let offset = (*self.header).sessionInfoOffset;
+                if !self.fits(offset, len) {
+                    return Err(format!["Session info of {} bytes at {} is out of bounds", len, offset]);
+                }
 
-                headers.push(DataHeader { name, description, unit });
+                let session_info_ptr = (self.header as *const u8).offset(offset as isize);
+                let buffer = std::slice::from_raw_parts(session_info_ptr, len as usize).to_vec();
+                if (*self.header).sessionInfoUpdate == tick_count {
+                    // The string is null padded in the memory map
+                    let len = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+                    return Ok(Some((tick_count, latin1_to_string(&buffer[..len]))));
+                }
             }
 
-            headers
+            self.torn_reads += 1;
+            debug!["Session info changed while copying, retrying"];
+        }
+
+        warn!["Session info kept changing while copying, giving up after {} attempts", MAX_COPY_ATTEMPTS];
+        Ok(None)
+    }
+
+    // The connection can not be used any further once the header points outside of the mapping
+    fn layout_broken(&mut self, err: String) -> Poll<Option<Result<Update, CollectorError>>> {
+        warn!["Disconnecting from the memory map: {}", err];
+        self.ended = true;
+        Poll::Ready(Some(Ok(Update::Connection(ConnectionStatus::Disconnected))))
+    }
+
+    // The buffer is only filled by the first copy, so this comes from the header
+    pub fn line_len(&self) -> usize {
+        unsafe { (*self.header).bufLen.max(0) as usize }
+    }
+
+    // Empty when they do not fit in the mapping
+    pub fn var_headers(&self) -> &[irsdk_varHeader] {
+        unsafe {
+            let num_vars = (*self.header).numVars;
+            let var_headers_len = num_vars.checked_mul(std::mem::size_of::<irsdk_varHeader>() as i32).unwrap_or(-1);
+            if num_vars <= 0 || !self.fits((*self.header).varHeaderOffset, var_headers_len) {
+                return &[];
+            }
+            let num_headers = num_vars as usize;
+            let var_headers = (self.header as *const u8).offset((*self.header).varHeaderOffset as isize) as *const irsdk_varHeader;
+            std::slice::from_raw_parts(var_headers, num_headers)
         }
     }
 }
 
-fn latin1_to_string(buffer: &[u8]) -> String {
+impl From<&irsdk_varHeader> for DataHeader {
+    fn from(var_header: &irsdk_varHeader) -> DataHeader {
+        unsafe {
+            let name = String::from(CStr::from_ptr(var_header.name.as_ptr()).to_str().unwrap());
+            let description = String::from(CStr::from_ptr(var_header.desc.as_ptr()).to_str().unwrap());
+            let unit = String::from(CStr::from_ptr(var_header.unit.as_ptr()).to_str().unwrap());
+
+            DataHeader {
+                name, description, unit,
+                kind: VarKind::from_raw(var_header.type_ as i32),
+                offset: var_header.offset.max(0) as usize,
+                count: var_header.count.max(0) as usize,
+            }
+        }
+    }
+}
+
+// Size in bytes of all entries of a variable in a telemetry line
+pub fn var_size(var_header: &irsdk_varHeader) -> usize {
+    let type_size = VarKind::from_raw(var_header.type_ as i32)
+        .map(|kind| kind.size())
+        .unwrap_or(1);
+    type_size * var_header.count.max(0) as usize
+}
+
+pub fn latin1_to_string(buffer: &[u8]) -> String {
     buffer.iter().map(|&c| c as char).collect()
 }
 
 pub enum Update {
-    Telemetry(Vec<IracingValue>),
+    // A new telemetry line is available through the `snapshot()` of the connection
+    Telemetry,
     SessionInfo(String),
+    Connection(ConnectionStatus),
 }
 
+// How long to wait for the data event before checking the status of the sim again
 const EVENT_TIMEOUT: Duration = Duration::from_secs(2);
+// Nothing clears the status of the mapping if the sim or whatever exports the memory file goes away,
+// e.g. when it crashes, so stale data counts as a disconnect
+const STALE_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_COPY_ATTEMPTS: u32 = 3;
 
 impl Stream for IracingConnection {
-    type Item = Update;
+    type Item = Result<Update, CollectorError>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        if let Some(should_disconnect) =  self.should_disconnect.try_lock() {
-            if *should_disconnect {
-                return Poll::Ready(None);
-            }
+        if self.ended {
+            return Poll::Ready(None);
+        }
+
+        if let Some(event) = self.connection_event() {
+            return Poll::Ready(Some(Ok(Update::Connection(event))));
         }
 
         unsafe {
-            // TODO(knielsen): We could be timed here, but for now :shrug:
-            let idx = (*self.header).varBuf.iter().enumerate().max_by_key(|(_, buf)| buf.tickCount).unwrap().0;
-            let tick_count_before = (*self.header).varBuf[idx].tickCount;
             let session_info_tick_count = (*self.header).sessionInfoUpdate;
 
             // Check for session info updates
             if session_info_tick_count > self.session_info_seen_tick_count {
-                self.session_info_seen_tick_count = session_info_tick_count;
-                debug!["Updating session info at tick {}", self.session_info_seen_tick_count];
+                debug!["Updating session info at tick {}", session_info_tick_count];
 
                 let len = (*self.header).sessionInfoLen as usize;
                 if len == 0 {
@@ -188,93 +512,184 @@ impl Stream for IracingConnection {
                     return Poll::Ready(None);
                 }
 
-                let offset = (*self.header).sessionInfoOffset as isize;
-                let mut buffer = vec![0u8; len];
-
-                let session_info_ptr = (self.header as *const u8).offset(offset);
-                buffer.copy_from_slice(std::slice::from_raw_parts(session_info_ptr, len));
-                if (*self.header).sessionInfoUpdate != session_info_tick_count {
-                    panic!("Session info was changed while copying!");
-                }
-
-                return Poll::Ready(Some(Update::SessionInfo(latin1_to_string(&buffer))))
+                return match self.copy_session_info() {
+                    Ok(Some((tick_count, session_info))) => {
+                        self.session_info_seen_tick_count = tick_count;
+                        Poll::Ready(Some(Ok(Update::SessionInfo(session_info))))
+                    },
+                    // The session info is picked up again on the next poll
+                    Ok(None) => Poll::Ready(Some(Err(CollectorError::TornSessionInfo { attempts: MAX_COPY_ATTEMPTS }))),
+                    Err(err) => self.layout_broken(err),
+                };
             }
 
+            let idx = self.newest_buffer();
+            let tick_count_before = (*self.header).varBuf[idx].tickCount;
+
             // Check for ordinary Telemetry updates
             if tick_count_before <= self.seen_tick_count {
+                if self.last_data_at.elapsed() > STALE_TIMEOUT {
+                    info!["Disconnected from iRacing after no new data for {:?}", STALE_TIMEOUT];
+                    self.ended = true;
+                    return Poll::Ready(Some(Ok(Update::Connection(ConnectionStatus::Disconnected))));
+                }
+
                 // Wait for a new element
                 let waiter = cx.waker().clone();
-                let disconnect_setter = self.should_disconnect.clone();
-                let event_file_clone = self.event_file;
-                task::spawn(async move {
-                    match WaitForSingleObject(event_file_clone, EVENT_TIMEOUT.as_millis().try_into().unwrap()) {
-                        0x0 => waiter.wake(),
-                        0x102 => {
-                            info!["Disconnected from iRacing after event timeout"];
-                            *disconnect_setter.lock().await = true;
-                            waiter.wake()
-                        },
-                        err => panic!("Some other failure: {}, detailed: {:?}", err, GetLastError())
-                    };
-                });
+                match self.mapping {
+                    #[cfg(windows)]
+                    Mapping::Win32 { event_file, .. } => {
+                        task::spawn(async move {
+                            match WaitForSingleObject(event_file, EVENT_TIMEOUT.as_millis().try_into().unwrap()) {
+                                // On a timeout the status is checked again on the next poll
+                                0x0 | 0x102 => waiter.wake(),
+                                err => panic!("Some other failure: {}, detailed: {:?}", err, GetLastError())
+                            };
+                        });
+                    },
+                    Mapping::File { .. } => {
+                        let poll_interval = self.poll_interval();
+                        task::spawn(async move {
+                            task::sleep(poll_interval).await;
+                            waiter.wake();
+                        });
+                    },
+                }
 
                 Poll::Pending
             } else {
-                self.seen_tick_count = tick_count_before;
-
-                let new_buffer_length = (*self.header).bufLen as usize;
-                if self.buffer.len() != new_buffer_length {
-                    self.buffer = vec![0u8; new_buffer_length];
+                match self.copy_line() {
+                    Ok(Some(tick_count)) => {
+                        self.seen_tick_count = tick_count;
+                        self.last_data_at = Instant::now();
+                        Poll::Ready(Some(Ok(Update::Telemetry)))
+                    },
+                    Ok(None) => Poll::Ready(Some(Err(CollectorError::TornRead { attempts: MAX_COPY_ATTEMPTS }))),
+                    Err(err) => self.layout_broken(err),
                 }
+            }
+        }
+    }
+}
 
-                let values_ptr = (self.header as *const u8).offset((*self.header).varBuf[idx].bufOffset as isize);
-                self.buffer.copy_from_slice(std::slice::from_raw_parts(values_ptr, new_buffer_length));
-                if (*self.header).varBuf[idx].tickCount != tick_count_before {
-                    panic!("Data changed while copying! This can't be good!");
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::path::PathBuf;
 
-                let num_headers = (*self.header).numVars as isize;
-                let mut values = Vec::with_capacity(num_headers as usize);
-                let var_headers = (self.header as *const u8).offset((*self.header).varHeaderOffset as isize) as *mut irsdk_varHeader;
-                for i in 0..num_headers {
-                    let var_header = *((var_headers.offset(i)) as *const irsdk_varHeader) as irsdk_varHeader;
-
-                    let value_ptr = self.buffer.as_ptr().offset(var_header.offset as isize);
-                    let count = var_header.count as usize;
-                    let value = match (var_header.type_, count) {
-                        (irsdk_VarType_irsdk_double, 1) => IracingValue::Double(*(value_ptr as *const f64)),
-                        (irsdk_VarType_irsdk_double, _) => {
-                            let mut values = Vec::with_capacity(count);
-                            for j in 0..count {
-                                values.push(*(value_ptr as *const f64).add(j));
-                            }
-                            IracingValue::DoubleVector(values)
-                        },
-                        (irsdk_VarType_irsdk_int, 1) => IracingValue::Int(*(value_ptr as *const i32)),
-                        (irsdk_VarType_irsdk_int, _) => {
-                            let mut values = Vec::with_capacity(count);
-                            for j in 0..count {
-                                values.push(*(value_ptr as *const i32).add(j));
-                            }
-                            IracingValue::IntVector(values)
-                        },
-                        (irsdk_VarType_irsdk_float, 1) => IracingValue::Float(*(value_ptr as *const f32)),
-                        (irsdk_VarType_irsdk_float, _) => {
-                            let mut values = Vec::with_capacity(count);
-                            for j in 0..count {
-                                values.push(*(value_ptr as *const f32).add(j));
-                            }
-                            IracingValue::FloatVector(values)
-                        },
-                        (irsdk_VarType_irsdk_bool, 1) => IracingValue::Boolean(*(value_ptr as *const u8) != 0),
-                        _ => IracingValue::Unknown
-                    };
-
-                    values.push(value);
-                }
+    const VAR_HEADERS_AT: usize = 256;
+    const SESSION_INFO_AT: usize = 512;
+    const SESSION_INFO: &[u8] = b"WeekendInfo:\n TrackName: spa\n";
+    const LINE_AT: usize = 1024;
+    const LINE_LEN: usize = 16;
 
-                Poll::Ready(Some(Update::Telemetry(values)))
-            }
+    fn as_bytes<T>(value: &T) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(value as *const T as *const u8, std::mem::size_of::<T>()) }
+    }
+
+    // A memory file of a sim in a session, with a single int variable
+    fn memory_file() -> Vec<u8> {
+        let mut header: irsdk_header = unsafe { std::mem::zeroed() };
+        header.ver = IRSDK_VER as i32;
+        header.status = irsdk_StatusField_irsdk_stConnected as i32;
+        header.tickRate = 60;
+        header.sessionInfoUpdate = 1;
+        header.sessionInfoLen = SESSION_INFO.len() as i32;
+        header.sessionInfoOffset = SESSION_INFO_AT as i32;
+        header.numVars = 1;
+        header.varHeaderOffset = VAR_HEADERS_AT as i32;
+        header.numBuf = 1;
+        header.bufLen = LINE_LEN as i32;
+        header.varBuf[0].tickCount = 5;
+        header.varBuf[0].bufOffset = LINE_AT as i32;
+
+        let mut var_header: irsdk_varHeader = unsafe { std::mem::zeroed() };
+        var_header.type_ = irsdk_VarType_irsdk_int as i32;
+        var_header.count = 1;
+        for (to, &from) in var_header.name.iter_mut().zip(b"Lap") {
+            *to = from as _;
         }
+
+        let mut file = vec![0; LINE_AT + LINE_LEN];
+        file[..std::mem::size_of::<irsdk_header>()].copy_from_slice(as_bytes(&header));
+        file[VAR_HEADERS_AT..VAR_HEADERS_AT + std::mem::size_of::<irsdk_varHeader>()].copy_from_slice(as_bytes(&var_header));
+        file[SESSION_INFO_AT..SESSION_INFO_AT + SESSION_INFO.len()].copy_from_slice(SESSION_INFO);
+        file[LINE_AT..LINE_AT + 4].copy_from_slice(&7i32.to_le_bytes());
+        file
+    }
+
+    fn write(name: &str, content: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!["uberlays-{}-{}", std::process::id(), name]);
+        File::create(&path).unwrap().write_all(content).unwrap();
+        path
+    }
+
+    fn connect(name: &str, content: &[u8]) -> Result<IracingConnection, IracingConnectionError> {
+        let path = write(name, content);
+        let connection = IracingConnection::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        connection
+    }
+
+    // Header fields are 4 bytes each, in the order of `irsdk_header`
+    fn set_field(file: &mut [u8], offset: usize, value: i32) {
+        file[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn reads_a_complete_file() {
+        let mut connection = connect("complete", &memory_file()).unwrap();
+        assert_eq!(connection.var_headers().len(), 1);
+        assert_eq!(DataHeader::from(&connection.var_headers()[0]).name, "Lap");
+
+        let (tick_count, session_info) = connection.copy_session_info().unwrap().unwrap();
+        assert_eq!(tick_count, 1);
+        assert_eq!(session_info.as_bytes(), SESSION_INFO);
+        assert_eq!(connection.copy_line().unwrap(), Some(5));
+        assert_eq!(&connection.buffer.as_bytes_mut()[..4], &7i32.to_le_bytes());
+    }
+
+    #[test]
+    fn waits_for_a_truncated_file() {
+        let file = memory_file();
+        // Cut off in the telemetry line, the session info and the variable headers
+        for len in [LINE_AT + LINE_LEN - 1, SESSION_INFO_AT + 4, VAR_HEADERS_AT + 4, std::mem::size_of::<irsdk_header>() - 1] {
+            assert!(matches!(connect("truncated", &file[..len]), Err(IracingConnectionError::NotRunning)), "Cut at {}", len);
+        }
+    }
+
+    #[test]
+    fn rejects_a_malformed_header() {
+        let mut file = memory_file();
+        // More buffers than the header has room for
+        set_field(&mut file, 32, 5);
+        assert!(matches!(connect("buffers", &file), Err(IracingConnectionError::NotRunning)));
+
+        // A line reaching past the end of the file, and session info of a negative length
+        let mut file = memory_file();
+        set_field(&mut file, 36, LINE_LEN as i32 + 1);
+        assert!(matches!(connect("line", &file), Err(IracingConnectionError::NotRunning)));
+        let mut file = memory_file();
+        set_field(&mut file, 16, -1);
+        assert!(matches!(connect("session-info", &file), Err(IracingConnectionError::NotRunning)));
+    }
+
+    #[test]
+    fn stops_copying_when_the_header_changes_out_of_bounds() {
+        let mut file = memory_file();
+        let path = write("changed", &file);
+        let mut connection = IracingConnection::from_file(&path).unwrap();
+
+        // The writer changes the header after connecting, which shows through the shared mapping
+        set_field(&mut file, 36, LINE_LEN as i32 * 2);
+        set_field(&mut file, 16, 4096);
+        set_field(&mut file, 24, 1000);
+        std::fs::OpenOptions::new().write(true).open(&path).unwrap().write_all(&file[..64]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(connection.copy_line().is_err());
+        assert!(connection.copy_session_info().is_err());
+        assert!(connection.var_headers().is_empty());
     }
 }
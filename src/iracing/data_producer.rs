@@ -1,186 +1,322 @@
+use std::sync::Arc;
 use std::time::{ Instant, Duration };
 
 use async_std::task;
-use async_std::channel::Sender;
+use async_std::channel;
+use async_std::channel::{ Sender, Receiver };
+use async_std::sync::Mutex;
 use async_std::stream::StreamExt;
 
-use crate::iracing::{ Update, Telemetry, SessionInfo, TrackSpec, DriverInfo };
+use crate::iracing::{ Update, Telemetry, SessionInfo, ConnectionStatus, IncidentCounts };
 use crate::iracing::data_collector;
-use crate::iracing::data_collector::IracingConnection;
+use crate::iracing::session_diff::SessionDiff;
+use crate::iracing::lap_timer::LapTimer;
+use crate::iracing::delta::{ DeltaEngine, Deltas };
+use crate::iracing::gaps::GapCalculator;
 use crate::iracing::data_collector::IracingConnectionError;
-use crate::iracing::data_collector::IracingValue;
 use crate::iracing::data_collector::DataHeader;
+use crate::iracing::flags::{ Flags, EngineWarnings, PitSvFlags, PaceFlags, PitServiceStatus, TrackLocation };
+use crate::iracing::tyres::{ Corner, TyreCorner, TyreState };
+use crate::iracing::snapshot::{ Snapshot, VarHandle };
+use crate::iracing::telemetry_source::{ TelemetrySource, TelemetryConnection };
 
-fn extract_value<T>(telemetry: &[IracingValue], header: Option<(usize, &DataHeader)>, extractor: Box<dyn Fn(&IracingValue) -> T>) -> T {
-    if let Some((idx, _)) = header {
-        (extractor)(&telemetry[idx])
-    } else {
-        (extractor)(&IracingValue::Unknown)
+// The telemetry variables we use, resolved once per connection
+struct Channels {
+    session_time: Option<VarHandle<f64>>,
+    session_num: Option<VarHandle<i32>>,
+    throttle: Option<VarHandle<f32>>,
+    brake: Option<VarHandle<f32>>,
+    fuel_level: Option<VarHandle<f32>>,
+    fuel_use_per_hour: Option<VarHandle<f32>>,
+    session_flags: Option<VarHandle<Flags>>,
+    engine_warnings: Option<VarHandle<EngineWarnings>>,
+    lap_dist_by_car: Option<VarHandle<[f32]>>,
+    car_positions: Option<VarHandle<[i32]>>,
+    car_class_positions: Option<VarHandle<[i32]>>,
+    car_laps: Option<VarHandle<[i32]>>,
+    car_laps_completed: Option<VarHandle<[i32]>>,
+    car_est_times: Option<VarHandle<[f32]>>,
+    car_track_surfaces: Option<VarHandle<[i32]>>,
+    car_on_pit_road: Option<VarHandle<[bool]>>,
+    car_session_flags: Option<VarHandle<[Flags]>>,
+    // The sim reports these as ints rather than as a bitfield
+    car_pace_flags: Option<VarHandle<[i32]>>,
+    is_on_track: Option<VarHandle<bool>>,
+
+    track_surface: Option<VarHandle<i32>>,
+    on_pit_road: Option<VarHandle<bool>>,
+    pit_service_flags: Option<VarHandle<PitSvFlags>>,
+    pit_service_fuel: Option<VarHandle<f32>>,
+    pit_service_status: Option<VarHandle<i32>>,
+
+    my_incidents: Option<VarHandle<i32>>,
+    team_incidents: Option<VarHandle<i32>>,
+    driver_incidents: Option<VarHandle<i32>>,
+
+    tyres: [CornerChannels; 4],
+}
+
+struct CornerChannels {
+    temps: [Option<VarHandle<f32>>; 3],
+    wear: [Option<VarHandle<f32>>; 3],
+    pressure: Option<VarHandle<f32>>,
+    cold_pressure: Option<VarHandle<f32>>,
+    ride_height: Option<VarHandle<f32>>,
+}
+
+impl CornerChannels {
+    fn resolve(headers: &[DataHeader], line_len: usize, corner: Corner) -> CornerChannels {
+        let resolve = |name: &str| VarHandle::resolve(headers, line_len, &format!["{}{}", corner.prefix(), name]);
+        CornerChannels {
+            temps: ["tempL", "tempM", "tempR"].map(resolve),
+            wear: ["wearL", "wearM", "wearR"].map(resolve),
+            pressure: resolve("press"),
+            cold_pressure: resolve("coldPressure"),
+            ride_height: resolve("rideHeight"),
+        }
+    }
+
+    fn read(&self, snapshot: Snapshot) -> TyreCorner {
+        let read = |handle: &Option<VarHandle<f32>>| handle.map_or(0.0, |handle| snapshot.get(&handle));
+        TyreCorner {
+            temps: self.temps.each_ref().map(read),
+            wear: self.wear.each_ref().map(read),
+            pressure: read(&self.pressure),
+            cold_pressure: read(&self.cold_pressure),
+            ride_height: read(&self.ride_height),
+        }
+    }
+}
+
+impl Channels {
+    fn resolve(headers: &[DataHeader], line_len: usize) -> Channels {
+        Channels {
+            session_time: VarHandle::resolve(headers, line_len, "SessionTime"),
+            session_num: VarHandle::resolve(headers, line_len, "SessionNum"),
+            throttle: VarHandle::resolve(headers, line_len, "Throttle"),
+            brake: VarHandle::resolve(headers, line_len, "Brake"),
+            fuel_level: VarHandle::resolve(headers, line_len, "FuelLevel"),
+            fuel_use_per_hour: VarHandle::resolve(headers, line_len, "FuelUsePerHour"),
+            session_flags: VarHandle::resolve(headers, line_len, "SessionFlags"),
+            engine_warnings: VarHandle::resolve(headers, line_len, "EngineWarnings"),
+            lap_dist_by_car: VarHandle::resolve(headers, line_len, "CarIdxLapDistPct"),
+            car_positions: VarHandle::resolve(headers, line_len, "CarIdxPosition"),
+            car_class_positions: VarHandle::resolve(headers, line_len, "CarIdxClassPosition"),
+            car_laps: VarHandle::resolve(headers, line_len, "CarIdxLap"),
+            car_laps_completed: VarHandle::resolve(headers, line_len, "CarIdxLapCompleted"),
+            car_est_times: VarHandle::resolve(headers, line_len, "CarIdxEstTime"),
+            car_track_surfaces: VarHandle::resolve(headers, line_len, "CarIdxTrackSurface"),
+            car_on_pit_road: VarHandle::resolve(headers, line_len, "CarIdxOnPitRoad"),
+            car_session_flags: VarHandle::resolve(headers, line_len, "CarIdxSessionFlags"),
+            car_pace_flags: VarHandle::resolve(headers, line_len, "CarIdxPaceFlags"),
+            is_on_track: VarHandle::resolve(headers, line_len, "IsOnTrack"),
+
+            track_surface: VarHandle::resolve(headers, line_len, "PlayerTrackSurface"),
+            on_pit_road: VarHandle::resolve(headers, line_len, "OnPitRoad"),
+            pit_service_flags: VarHandle::resolve(headers, line_len, "PitSvFlags"),
+            pit_service_fuel: VarHandle::resolve(headers, line_len, "PitSvFuel"),
+            pit_service_status: VarHandle::resolve(headers, line_len, "PlayerCarPitSvStatus"),
+
+            my_incidents: VarHandle::resolve(headers, line_len, "PlayerCarMyIncidentCount"),
+            team_incidents: VarHandle::resolve(headers, line_len, "PlayerCarTeamIncidentCount"),
+            driver_incidents: VarHandle::resolve(headers, line_len, "PlayerCarDriverIncidentCount"),
+
+            tyres: Corner::ALL.map(|corner| CornerChannels::resolve(headers, line_len, corner)),
+        }
+    }
+
+    fn telemetry(&self, snapshot: Snapshot) -> Telemetry {
+        Telemetry {
+            timestamp: Instant::now(),
+            session_time: self.session_time.map_or(0.0, |session_time| snapshot.get(&session_time)),
+            session_num: self.session_num.map_or(0, |session_num| snapshot.get(&session_num)),
+            throttle: self.throttle.map_or(0.0, |throttle| snapshot.get(&throttle)),
+            brake: self.brake.map_or(0.0, |brake| snapshot.get(&brake)),
+            gear: 1,
+            velocity: 0.0,
+            fuel_level: self.fuel_level.map_or(0.0, |fuel_level| snapshot.get(&fuel_level)),
+            fuel_use_per_hour: self.fuel_use_per_hour.map_or(0.0, |fuel_use_per_hour| snapshot.get(&fuel_use_per_hour)),
+            session_flags: self.session_flags.map_or(Flags::default(), |flags| snapshot.get(&flags)),
+            engine_warnings: self.engine_warnings.map_or(EngineWarnings::default(), |warnings| snapshot.get(&warnings)),
+            deltas: Deltas::default(),
+            lap_dist_by_car: self.lap_dist_by_car.map_or(vec![], |lap_dist_by_car| snapshot.slice(&lap_dist_by_car).to_vec()),
+            car_positions: self.car_positions.map_or(vec![], |car_positions| snapshot.slice(&car_positions).to_vec()),
+            car_class_positions: self.car_class_positions.map_or(vec![], |car_class_positions| snapshot.slice(&car_class_positions).to_vec()),
+            car_laps: self.car_laps.map_or(vec![], |car_laps| snapshot.slice(&car_laps).to_vec()),
+            car_laps_completed: self.car_laps_completed.map_or(vec![], |car_laps_completed| snapshot.slice(&car_laps_completed).to_vec()),
+            lap_timing: vec![],
+            car_est_times: self.car_est_times.map_or(vec![], |car_est_times| snapshot.slice(&car_est_times).to_vec()),
+            gaps: vec![],
+            car_track_locations: self.car_track_surfaces.map_or(vec![], |car_track_surfaces| snapshot.iter(&car_track_surfaces)
+                .map(TrackLocation::from_raw)
+                .collect()),
+            car_on_pit_road: self.car_on_pit_road.map_or(vec![], |car_on_pit_road| snapshot.iter(&car_on_pit_road).collect()),
+            car_session_flags: self.car_session_flags.map_or(vec![], |car_session_flags| snapshot.slice(&car_session_flags).to_vec()),
+            car_pace_flags: self.car_pace_flags.map_or(vec![], |car_pace_flags| snapshot.iter(&car_pace_flags)
+                .map(|bits| PaceFlags(bits as u32))
+                .collect()),
+            is_on_track: self.is_on_track.map_or(false, |is_on_track| snapshot.get(&is_on_track)),
+
+            track_location: TrackLocation::from_raw(self.track_surface.map_or(-1, |track_surface| snapshot.get(&track_surface))),
+            on_pit_road: self.on_pit_road.map_or(false, |on_pit_road| snapshot.get(&on_pit_road)),
+            pit_service_flags: self.pit_service_flags.map_or(PitSvFlags::default(), |flags| snapshot.get(&flags)),
+            pit_service_fuel: self.pit_service_fuel.map_or(0.0, |fuel| snapshot.get(&fuel)),
+            pit_service_status: PitServiceStatus::from_raw(self.pit_service_status.map_or(0, |status| snapshot.get(&status))),
+
+            tyres: TyreState { corners: self.tyres.each_ref().map(|corner| corner.read(snapshot)) },
+            incidents: IncidentCounts {
+                my: self.my_incidents.map_or(0, |incidents| snapshot.get(&incidents)),
+                team: self.team_incidents.map_or(0, |incidents| snapshot.get(&incidents)),
+                driver: self.driver_incidents.map_or(0, |incidents| snapshot.get(&incidents)),
+            },
+        }
+    }
+}
+
+async fn set_status(sender: &Sender<Update>, last_status: &mut ConnectionStatus, status: ConnectionStatus) {
+    if status != *last_status {
+        info!["Connection status changed to {:?}", status];
+        *last_status = status;
+        sender.send(Update::Connection(status)).await.unwrap();
+    }
+}
+
+// Parses the session info strings of a connection one at a time, in the order the sim sent them,
+// so the diff and everything set up from the session info never goes back to an older one
+async fn process_session_infos(
+    receiver: Receiver<String>,
+    sender: Sender<Update>,
+    lap_timer: Arc<Mutex<LapTimer>>,
+    delta_engine: Arc<Mutex<DeltaEngine>>,
+    gap_calculator: Arc<Mutex<GapCalculator>>,
+) {
+    let mut session_diff = SessionDiff::new();
+    while let Ok(session_info_str) = receiver.recv().await {
+        let session_info = match SessionInfo::try_from(&session_info_str) {
+            Ok(session_info) => session_info,
+            Err(err) => {
+                error!["Failed to parse session info: {}", err];
+                continue;
+            },
+        };
+        for err in &session_info.errors {
+            warn!["Ignoring session info field {}", err];
+        }
+
+        lap_timer.lock().await.set_sectors(&session_info.split_time_info);
+        let reference_path = delta_engine.lock().await.set_session(&session_info);
+        if let Some(path) = reference_path {
+            // Read without holding the lock, the telemetry keeps being updated meanwhile
+            if let Some(reference) = DeltaEngine::load_reference(&path).await {
+                delta_engine.lock().await.set_reference(&path, reference);
+            }
+        }
+        gap_calculator.lock().await.set_session(&session_info);
+
+        let events = session_diff.update(&session_info);
+        sender.send(Update::Session(session_info)).await.unwrap();
+        for event in events {
+            debug!["Session event: {:?}", event];
+            sender.send(Update::SessionEvent(event)).await.unwrap();
+        }
     }
 }
 
-pub struct IracingTask {
+pub struct IracingTask<S: TelemetrySource> {
     sender: Sender<Update>,
+    source: S,
 }
 
-impl IracingTask {
-    pub fn new(sender: Sender<Update>) -> IracingTask {
-        IracingTask { sender }
+impl<S: TelemetrySource> IracingTask<S> {
+    pub fn new(sender: Sender<Update>, source: S) -> IracingTask<S> {
+        IracingTask { sender, source }
     }
 
-    pub async fn execute(self) {
+    pub async fn execute(mut self) {
+        // Only changes are passed on, so e.g. retrying a sim of the wrong version does not spam the overlays
+        let mut last_status = ConnectionStatus::Disconnected;
+
         loop {
-            let mut maybe_connection: Option<IracingConnection> = None;
-            loop {
-                match IracingConnection::new() {
-                    Ok(new_connection) => {
-                        maybe_connection = Some(new_connection);
-                        break;
-                    },
+            let mut connection = loop {
+                match self.source.connect() {
+                    Ok(new_connection) => break new_connection,
                     Err(IracingConnectionError::NotRunning) => {
                         info!("iRacing not detected. Retrying!");
-                        std::thread::sleep(Duration::from_secs(1));
-                    }
+                    },
+                    Err(IracingConnectionError::Exhausted) => {
+                        info!("Telemetry source has no more data");
+                        return;
+                    },
+                    Err(IracingConnectionError::Failed(err)) => {
+                        error!("Failed to connect to telemetry source: {}. Retrying!", err);
+                    },
                 }
-            }
-            let mut connection = maybe_connection.unwrap();
+                std::thread::sleep(Duration::from_secs(1));
+            };
 
             info!("Established connection to iRacing");
 
             let headers = connection.headers();
             // info!["Headers: {:?}", headers];
-            let throttle_header = headers.iter().enumerate()
-                    .find(|(_, header)| header.name == "Throttle");
-            let brake_header = headers.iter().enumerate()
-                    .find(|(_, header)| header.name == "Brake");
-            let lap_dist_by_car_header = headers.iter().enumerate()
-                    .find(|(_, header)| header.name == "CarIdxLapDistPct");
-            let car_positions_header = headers.iter().enumerate()
-                    .find(|(_, header)| header.name == "CarIdxPosition");
-            let is_on_track_header = headers.iter().enumerate()
-                    .find(|(_, header)| header.name == "IsOnTrack");
-
-            /*
-            let rr_temp_l_header = headers.iter().enumerate()
-                    .find(|(_, header)| header.name == "RRtempL");
-            let rr_temp_m_header = headers.iter().enumerate()
-                    .find(|(_, header)| header.name == "RRtempM");
-            let rr_temp_r_header = headers.iter().enumerate()
-                    .find(|(_, header)| header.name == "RRtempR");
-            let rr_wear_l_header = headers.iter().enumerate()
-                    .find(|(_, header)| header.name == "RRwearL");
-            let rr_wear_m_header = headers.iter().enumerate()
-                    .find(|(_, header)| header.name == "RRwearM");
-            let rr_wear_r_header = headers.iter().enumerate()
-                    .find(|(_, header)| header.name == "RRwearR");
-            let rr_ride_height_header = headers.iter().enumerate()
-                    .find(|(_, header)| header.name == "RRrideHeight"); */
+            let channels = Channels::resolve(&headers, connection.line_len());
+            // Shared with the session info parsing, which sets up the sectors
+            let lap_timer = Arc::new(Mutex::new(LapTimer::new()));
+            let delta_engine = Arc::new(Mutex::new(DeltaEngine::default()));
+            let gap_calculator = Arc::new(Mutex::new(GapCalculator::new()));
+            // Parsing takes a while for big sessions, so it is kept out of the telemetry loop.
+            // The task ends with the connection, when the sender is dropped.
+            let (session_info_sender, session_info_receiver) = channel::unbounded();
+            task::spawn(process_session_infos(
+                session_info_receiver,
+                self.sender.clone(),
+                lap_timer.clone(),
+                delta_engine.clone(),
+                gap_calculator.clone(),
+            ));
 
             let mut packages = 0;
             while let Some(package) = connection.next().await {
+                let package = match package {
+                    Ok(package) => package,
+                    Err(err) => {
+                        warn!["Skipping telemetry update: {}", err];
+                        continue;
+                    }
+                };
+
                 packages += 1;
                 if packages % 60 == 0 {
-                    info!["Package count: {}", packages];
+                    info!["Package count: {}, torn reads: {}", packages, connection.torn_reads()];
                 }
 
                 match package {
-                    data_collector::Update::Telemetry(telemetry) => {
-                        let throttle = extract_value(&telemetry, throttle_header, Box::new(|val| match val {
-                            IracingValue::Float(throttle) => *throttle,
-                            _ => 0.0
-                        }));
-                        let brake = extract_value(&telemetry, brake_header, Box::new(|val| match val {
-                            IracingValue::Float(brake) => *brake,
-                            _ => 0.0
-                        }));
-                        let lap_dist_by_car = extract_value(&telemetry, lap_dist_by_car_header, Box::new(|val| match val {
-                            IracingValue::FloatVector(lap_dist_by_car) => lap_dist_by_car.clone(),
-                            _ => vec![]
-                        }));
-                        let car_positions = extract_value(&telemetry, car_positions_header, Box::new(|val| match val {
-                            IracingValue::IntVector(car_positions) => car_positions.clone(),
-                            _ => vec![]
-                        }));
-
-                        let is_on_track = extract_value(&telemetry, is_on_track_header, Box::new(|val| match val {
-                            IracingValue::Boolean(is_on_track) => *is_on_track,
-                            _ => false,
-                        }));
-
-                        let timestamp = Instant::now();
-                        self.sender.send(Update::Telemetry(Telemetry {
-                            timestamp,
-                            throttle,
-                            brake,
-                            gear: 1,
-                            velocity: 0.0,
-                            deltas: vec![],
-                            lap_dist_by_car,
-                            car_positions,
-                            is_on_track,
-                        })).await.unwrap();
+                    data_collector::Update::Telemetry => {
+                        let mut telemetry = channels.telemetry(connection.snapshot());
+                        let laps = lap_timer.lock().await.update(&mut telemetry);
+                        delta_engine.lock().await.update(&mut telemetry, &laps);
+                        gap_calculator.lock().await.update(&mut telemetry);
+                        self.sender.send(Update::Telemetry(telemetry)).await.unwrap();
+                        for lap in laps {
+                            self.sender.send(Update::LapCompleted(lap)).await.unwrap();
+                        }
+                    },
+                    data_collector::Update::Connection(status) => {
+                        set_status(&self.sender, &mut last_status, status).await;
                     },
                     data_collector::Update::SessionInfo(session_info_str) => {
                         info!["Session info: {}", session_info_str];
-
-                        let session_info_sender = self.sender.clone();
-                        task::spawn(async move {
-                            match SessionInfo::try_from(&session_info_str) {
-                                Ok(session_info) => session_info_sender.send(Update::Session(session_info)).await.unwrap(),
-                                Err(err) => error!["Failed to parse session info: {}", err],
-                            }
-                        });
+                        session_info_sender.send(session_info_str).await.unwrap();
                     }
                 }
             }
 
-            // iRacing disconnected, wait 1 seconds before attempting to re-connect
-            std::thread::sleep(Duration::from_secs(1));
-        }
-    }
-}
-
-pub struct TestTask {
-    sender: Sender<Update>,
-}
-
-impl TestTask {
-    pub fn new(sender: Sender<Update>) -> TestTask {
-        TestTask { sender }
-    }
-
-    pub async fn execute(self) {
-        self.sender.send(Update::Session(SessionInfo {
-            track: TrackSpec {
-                name: "monza full".to_string(),
-                configuration: "Grand Prix".to_string(),
-            },
-            driver: DriverInfo {
-                car_idx: 1,
-                username: "Test Driver".to_string(),
-                irating: 1,
-                license_string: "R 0.01".to_string(),
+            // Sources that simply end their stream are disconnected as well
+            if let ConnectionStatus::Connected { .. } = last_status {
+                set_status(&self.sender, &mut last_status, ConnectionStatus::Disconnected).await;
             }
-        })).await.unwrap();
 
-        let mut position = 0.0;
-        let mut brake = 0.0;
-        loop {
-            position = (position + 0.001) % 1.0;
-            brake = (brake + 0.05) % 1.05;
-            self.sender.send(Update::Telemetry(Telemetry {
-                timestamp: Instant::now(),
-                throttle: 0.0,
-                brake,
-                gear: 1,
-                velocity: 0.0,
-                deltas: vec![0.364, 14.340, -2.423, -23.42],
-                lap_dist_by_car: vec![0.0, position, 0.75],
-                car_positions: vec![0, 1, 2],
-                is_on_track: true,
-            })).await.unwrap();
-
-            std::thread::sleep(std::time::Duration::from_millis(50));
+            // iRacing disconnected, wait 1 seconds before attempting to re-connect
+            std::thread::sleep(Duration::from_secs(1));
         }
     }
 }
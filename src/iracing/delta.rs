@@ -0,0 +1,479 @@
+use std::path::{ Path, PathBuf };
+
+use async_std::fs;
+use async_std::task;
+use async_std::task::JoinHandle;
+use prost::Message;
+
+use crate::iracing::{ Telemetry, SessionInfo };
+use crate::iracing::lap_timer::Lap;
+
+mod proto {
+    include!(concat!(env!("OUT_DIR"), "/overlay.delta.rs"));
+}
+pub use proto::LapReference;
+
+// Where the reference laps are kept by default, next to the track maps
+const DIRECTORY: &str = "media/deltas";
+
+// Number of stretches laps are divided into for comparing them
+const SEGMENTS: usize = 1000;
+// Laps where the telemetry skipped more of the lap than this, e.g. due to a hitch, are not used as a reference
+const MAX_SAMPLE_GAP: f32 = 0.02;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Deltas {
+    // Seconds compared to the reference lap at the same point of the lap, negative when faster.
+    // None until there is a reference.
+    pub best: Option<f32>,
+    pub session_best: Option<f32>,
+    pub optimal: Option<f32>,
+}
+
+// The time into a lap at each of the `SEGMENTS + 1` evenly spaced points along it
+#[derive(Debug, Clone, PartialEq)]
+struct Trace {
+    times: Vec<f64>,
+}
+
+impl Trace {
+    // Resamples the lap distance and lap time samples of a lap, None if they do not cover the lap
+    fn from_samples(samples: &[(f32, f64)], lap_time: f64) -> Option<Trace> {
+        let mut points = vec![(0.0f32, 0.0f64)];
+        points.extend(samples.iter().copied());
+        points.push((1.0, lap_time));
+
+        if points.windows(2).any(|pair| pair[1].0 - pair[0].0 > MAX_SAMPLE_GAP) {
+            return None;
+        }
+
+        let mut times = Vec::with_capacity(SEGMENTS + 1);
+        let mut next = 1;
+        for segment in 0..=SEGMENTS {
+            let dist = segment as f32 / SEGMENTS as f32;
+            while next < points.len() - 1 && points[next].0 < dist {
+                next += 1;
+            }
+            let (from_dist, from_time) = points[next - 1];
+            let (to_dist, to_time) = points[next];
+            let time = if to_dist > from_dist {
+                from_time + (to_time - from_time) * ((dist - from_dist) / (to_dist - from_dist)) as f64
+            } else {
+                to_time
+            };
+            times.push(time);
+        }
+        Some(Trace { times })
+    }
+
+    fn from_segments(segments: &[f64]) -> Trace {
+        let mut times = Vec::with_capacity(segments.len() + 1);
+        times.push(0.0);
+        for segment in segments {
+            times.push(times.last().unwrap() + segment);
+        }
+        Trace { times }
+    }
+
+    fn lap_time(&self) -> f64 {
+        *self.times.last().unwrap()
+    }
+
+    fn segments(&self) -> Vec<f64> {
+        self.times.windows(2).map(|pair| pair[1] - pair[0]).collect()
+    }
+
+    fn time_at(&self, dist: f32) -> f64 {
+        let position = dist.clamp(0.0, 1.0) as f64 * SEGMENTS as f64;
+        let segment = (position as usize).min(SEGMENTS - 1);
+        let fraction = position - segment as f64;
+        self.times[segment] + (self.times[segment + 1] - self.times[segment]) * fraction
+    }
+}
+
+// Compares the lap in progress of the player to their best laps. The best lap and the best
+// segments are stored per car and track, so they are there from the first lap of a session.
+pub struct DeltaEngine {
+    directory: PathBuf,
+    reference_path: Option<PathBuf>,
+    player_car_idx: Option<usize>,
+
+    best: Option<Trace>,
+    session_best: Option<Trace>,
+    best_segments: Option<Vec<f64>>,
+    optimal: Option<Trace>,
+
+    // Lap distance and lap time of the lap in progress
+    samples: Vec<(f32, f64)>,
+    // Whether the lap in progress was driven from the line without going through the pits
+    lap_is_clean: bool,
+    session_num: i32,
+    // Writing the reference laps, which the next save waits for so they land in order
+    saving: Option<JoinHandle<()>>,
+}
+
+impl DeltaEngine {
+    pub fn new(directory: PathBuf) -> DeltaEngine {
+        DeltaEngine {
+            directory,
+            reference_path: None,
+            player_car_idx: None,
+            best: None,
+            session_best: None,
+            best_segments: None,
+            optimal: None,
+            samples: vec![],
+            lap_is_clean: false,
+            session_num: -1,
+            saving: None,
+        }
+    }
+
+    // Returns where the reference laps for the car and track of the session are when they changed,
+    // which are then read with `load_reference` and handed back with `set_reference`. The reading
+    // is left to the caller, so it does not hold up the telemetry.
+    pub fn set_session(&mut self, session_info: &SessionInfo) -> Option<PathBuf> {
+        self.player_car_idx = Some(session_info.driver.car_idx);
+
+        let car = match session_info.driver(session_info.driver.car_idx) {
+            Some(driver) if !driver.car_path.is_empty() => driver.car_path.clone(),
+            _ => return None,
+        };
+        let path = self.directory
+            .join(&session_info.track.name)
+            .join(&session_info.track.configuration)
+            .join(format!["{}.dat", car]);
+        if self.reference_path.as_ref() == Some(&path) {
+            return None;
+        }
+
+        self.best = None;
+        self.best_segments = None;
+        self.optimal = None;
+        self.session_best = None;
+        self.reference_path = Some(path.clone());
+        Some(path)
+    }
+
+    pub async fn load_reference(path: &Path) -> Option<LapReference> {
+        match fs::read(path).await {
+            Ok(content) => match LapReference::decode(&*content) {
+                Ok(reference) => {
+                    info!["Loaded reference laps from {}", path.display()];
+                    Some(reference)
+                },
+                Err(err) => {
+                    warn!["Ignoring reference laps in {}: {}", path.display(), err];
+                    None
+                },
+            },
+            Err(_) => {
+                info!["No reference laps for {} yet", path.display()];
+                None
+            },
+        }
+    }
+
+    pub fn set_reference(&mut self, path: &Path, reference: LapReference) {
+        // The session moved on to another car or track while reading
+        if self.reference_path.as_deref() != Some(path) {
+            return;
+        }
+        // Laps driven while reading are kept when they are better
+        if reference.best_lap.len() == SEGMENTS + 1 {
+            let best = Trace { times: reference.best_lap };
+            if self.best.as_ref().map_or(true, |current| best.lap_time() < current.lap_time()) {
+                self.best = Some(best);
+            }
+        }
+        if reference.best_segments.len() == SEGMENTS {
+            let best_segments = self.best_segments.get_or_insert_with(|| vec![f64::INFINITY; SEGMENTS]);
+            for (best_segment, segment) in best_segments.iter_mut().zip(reference.best_segments) {
+                *best_segment = best_segment.min(segment);
+            }
+            self.optimal = Some(Trace::from_segments(best_segments));
+        }
+    }
+
+    // Fills in the deltas of the telemetry, which must already have its lap timing filled in
+    pub fn update(&mut self, telemetry: &mut Telemetry, completed: &[Lap]) {
+        let player_car_idx = match self.player_car_idx {
+            Some(player_car_idx) => player_car_idx,
+            None => return,
+        };
+
+        if telemetry.session_num != self.session_num {
+            self.session_num = telemetry.session_num;
+            self.session_best = None;
+        }
+
+        if let Some(lap) = completed.iter().find(|lap| lap.car_idx == player_car_idx) {
+            self.complete_lap(lap);
+        }
+
+        let dist = telemetry.lap_dist_by_car.get(player_car_idx).copied().unwrap_or(-1.0);
+        let current = telemetry.lap_timing.get(player_car_idx).and_then(|timing| timing.current);
+        let current = match current {
+            Some(current) if dist >= 0.0 => current.as_secs_f64(),
+            _ => {
+                self.samples.clear();
+                self.lap_is_clean = false;
+                return;
+            },
+        };
+
+        if self.samples.is_empty() {
+            self.lap_is_clean = true;
+        }
+        if self.samples.last().map_or(true, |(last_dist, _)| dist > *last_dist) {
+            self.samples.push((dist, current));
+        }
+        if telemetry.on_pit_road {
+            self.lap_is_clean = false;
+        }
+
+        let delta = |reference: &Option<Trace>| reference.as_ref()
+            .map(|reference| (current - reference.time_at(dist)) as f32);
+        telemetry.deltas = Deltas {
+            best: delta(&self.best),
+            session_best: delta(&self.session_best),
+            optimal: delta(&self.optimal),
+        };
+    }
+
+    fn complete_lap(&mut self, lap: &Lap) {
+        let samples = std::mem::take(&mut self.samples);
+        if !self.lap_is_clean {
+            return;
+        }
+        let trace = match Trace::from_samples(&samples, lap.time.as_secs_f64()) {
+            Some(trace) => trace,
+            None => {
+                debug!["Lap {} has gaps in the telemetry, not using it as a reference", lap.lap];
+                return;
+            },
+        };
+
+        let mut changed = false;
+        if self.session_best.as_ref().map_or(true, |best| trace.lap_time() < best.lap_time()) {
+            self.session_best = Some(trace.clone());
+        }
+        if self.best.as_ref().map_or(true, |best| trace.lap_time() < best.lap_time()) {
+            info!["New best lap of {:.3}s", trace.lap_time()];
+            self.best = Some(trace.clone());
+            changed = true;
+        }
+
+        let segments = trace.segments();
+        let best_segments = self.best_segments.get_or_insert_with(|| vec![f64::INFINITY; SEGMENTS]);
+        for (best_segment, segment) in best_segments.iter_mut().zip(segments) {
+            if segment < *best_segment {
+                *best_segment = segment;
+                changed = true;
+            }
+        }
+        self.optimal = Some(Trace::from_segments(best_segments));
+
+        if changed {
+            self.save();
+        }
+    }
+
+    // Writes the reference laps in the background, as this runs for every telemetry update
+    fn save(&mut self) {
+        let path = match &self.reference_path {
+            Some(path) => path.clone(),
+            None => return,
+        };
+        let reference = LapReference {
+            best_lap: self.best.as_ref().map_or(vec![], |best| best.times.clone()),
+            best_segments: self.best_segments.clone().unwrap_or_default(),
+        };
+
+        let previous = self.saving.take();
+        self.saving = Some(task::spawn(async move {
+            if let Some(previous) = previous {
+                previous.await;
+            }
+            if let Some(parent) = path.parent() {
+                if let Err(err) = fs::create_dir_all(parent).await {
+                    error!["Failed to create {}: {}", parent.display(), err];
+                    return;
+                }
+            }
+            if let Err(err) = fs::write(&path, reference.encode_to_vec()).await {
+                error!["Failed to save reference laps to {}: {}", path.display(), err];
+            }
+        }));
+    }
+}
+
+impl Default for DeltaEngine {
+    fn default() -> DeltaEngine {
+        DeltaEngine::new(PathBuf::from(DIRECTORY))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::iracing::lap_timer::LapTiming;
+
+    // Samples of a lap at every hundredth of the lap, with the lap time at each
+    fn samples(time_at: impl Fn(f64) -> f64) -> Vec<(f32, f64)> {
+        (1..100).map(|i| (i as f32 / 100.0, time_at(i as f64 / 100.0))).collect()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-3, "{} is not {}", actual, expected);
+    }
+
+    fn assert_delta(actual: Option<f32>, expected: f32) {
+        let actual = actual.unwrap_or_else(|| panic!("No delta, expected {}", expected));
+        assert!((actual - expected).abs() < 1e-3, "{} is not {}", actual, expected);
+    }
+
+    fn player_engine() -> DeltaEngine {
+        // Without a reference path nothing is saved
+        DeltaEngine {
+            player_car_idx: Some(0),
+            ..DeltaEngine::new(PathBuf::new())
+        }
+    }
+
+    fn sample(engine: &mut DeltaEngine, session_num: i32, dist: f32, current: f64, on_pit_road: bool, completed: &[Lap]) -> Deltas {
+        let mut telemetry = Telemetry {
+            session_num,
+            lap_dist_by_car: vec![dist],
+            lap_timing: vec![LapTiming { current: Some(Duration::from_secs_f64(current)), ..LapTiming::default() }],
+            on_pit_road,
+            ..Telemetry::default()
+        };
+        engine.update(&mut telemetry, completed);
+        telemetry.deltas
+    }
+
+    // Drives a lap from the line to the line, completing it on the line
+    fn drive_lap(engine: &mut DeltaEngine, session_num: i32, time_at: impl Fn(f64) -> f64, on_pit_road: bool) {
+        for (dist, current) in samples(&time_at) {
+            sample(engine, session_num, dist, current, on_pit_road, &[]);
+        }
+        let lap = Lap {
+            car_idx: 0,
+            lap: 1,
+            time: Duration::from_secs_f64(time_at(1.0)),
+            sectors: vec![],
+            completed_at: 0.0,
+            is_personal_best: true,
+            is_session_best: true,
+        };
+        sample(engine, session_num, 0.0, 0.0, false, &[lap]);
+    }
+
+    // Takes `first` seconds to halfway and `second` for the rest
+    fn split_lap(first: f64, second: f64) -> impl Fn(f64) -> f64 {
+        move |dist| if dist <= 0.5 { first * dist / 0.5 } else { first + second * (dist - 0.5) / 0.5 }
+    }
+
+    #[test]
+    fn resamples_a_lap() {
+        let trace = Trace::from_samples(&samples(split_lap(40.0, 60.0)), 100.0).unwrap();
+        assert_eq!(trace.times.len(), SEGMENTS + 1);
+        assert_close(trace.lap_time(), 100.0);
+        assert_close(trace.time_at(0.0), 0.0);
+        assert_close(trace.time_at(0.25), 20.0);
+        assert_close(trace.time_at(0.5), 40.0);
+        assert_close(trace.time_at(0.75), 70.0);
+        // In between the segments
+        assert_close(trace.time_at(0.7505), 70.06);
+        assert_close(trace.time_at(1.0), 100.0);
+        assert_close(trace.time_at(1.5), 100.0);
+        assert_close(trace.time_at(-0.5), 0.0);
+
+        let segments = trace.segments();
+        assert_eq!(segments.len(), SEGMENTS);
+        assert_close(Trace::from_segments(&segments).time_at(0.75), 70.0);
+    }
+
+    #[test]
+    fn skips_laps_with_gaps() {
+        let mut gap = samples(|dist| dist * 100.0);
+        gap.retain(|&(dist, _)| !(0.4..0.5).contains(&dist));
+        assert!(Trace::from_samples(&gap, 100.0).is_none());
+        // Nothing after the last sample
+        assert!(Trace::from_samples(&samples(|dist| dist * 100.0)[..90], 100.0).is_none());
+    }
+
+    #[test]
+    fn picks_the_reference_laps() {
+        // No deltas until there is a reference
+        assert_eq!(sample(&mut player_engine(), 0, 0.5, 50.0, false, &[]), Deltas::default());
+
+        let mut engine = player_engine();
+        drive_lap(&mut engine, 0, split_lap(50.0, 50.0), false);
+        // Faster to halfway but slower overall
+        drive_lap(&mut engine, 0, split_lap(45.0, 60.0), false);
+        assert_close(engine.best.as_ref().unwrap().lap_time(), 100.0);
+        assert_close(engine.session_best.as_ref().unwrap().lap_time(), 100.0);
+        assert_close(engine.optimal.as_ref().unwrap().lap_time(), 95.0);
+
+        let deltas = sample(&mut engine, 0, 0.5, 47.0, false, &[]);
+        assert_delta(deltas.best, -3.0);
+        assert_delta(deltas.session_best, -3.0);
+        assert_delta(deltas.optimal, 2.0);
+
+        // A new session starts over with the session best only
+        let deltas = sample(&mut engine, 1, 0.5, 47.0, false, &[]);
+        assert_eq!(deltas.session_best, None);
+        assert_delta(deltas.best, -3.0);
+        // Back to the garage, then a slower lap
+        sample(&mut engine, 1, -1.0, 0.0, false, &[]);
+        drive_lap(&mut engine, 1, split_lap(55.0, 55.0), false);
+        assert_close(engine.session_best.as_ref().unwrap().lap_time(), 110.0);
+        assert_close(engine.best.as_ref().unwrap().lap_time(), 100.0);
+    }
+
+    #[test]
+    fn ignores_laps_through_the_pits() {
+        let mut engine = player_engine();
+        drive_lap(&mut engine, 0, split_lap(50.0, 50.0), true);
+        assert!(engine.best.is_none() && engine.session_best.is_none() && engine.optimal.is_none());
+
+        // Nor the lap the car was first seen halfway through
+        let mut engine = player_engine();
+        sample(&mut engine, 0, 0.5, 50.0, false, &[]);
+        drive_lap(&mut engine, 0, split_lap(50.0, 50.0), false);
+        assert!(engine.best.is_none());
+    }
+
+    #[test]
+    fn keeps_better_laps_than_the_loaded_reference() {
+        let reference = |best_lap: f64, best_segment: f64| LapReference {
+            best_lap: Trace::from_samples(&samples(|dist| dist * best_lap), best_lap).unwrap().times,
+            best_segments: vec![best_segment; SEGMENTS],
+        };
+        let path = PathBuf::from("track/config/car.dat");
+
+        let mut engine = player_engine();
+        engine.reference_path = Some(path.clone());
+        engine.set_reference(&path, reference(100.0, 0.095));
+        assert_close(engine.best.as_ref().unwrap().lap_time(), 100.0);
+        assert_close(engine.optimal.as_ref().unwrap().lap_time(), 95.0);
+
+        // A lap driven while the reference was read
+        let mut engine = player_engine();
+        engine.reference_path = Some(path.clone());
+        engine.best = Some(Trace::from_samples(&samples(|dist| dist * 90.0), 90.0).unwrap());
+        engine.best_segments = Some(vec![0.1; SEGMENTS]);
+        engine.set_reference(&path, reference(100.0, 0.095));
+        assert_close(engine.best.as_ref().unwrap().lap_time(), 90.0);
+        assert_close(engine.optimal.as_ref().unwrap().lap_time(), 95.0);
+
+        // The reference of a car or track that is no longer driven
+        let mut engine = player_engine();
+        engine.reference_path = Some(PathBuf::from("track/config/other.dat"));
+        engine.set_reference(&path, reference(100.0, 0.095));
+        assert!(engine.best.is_none() && engine.optimal.is_none());
+    }
+}
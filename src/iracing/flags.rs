@@ -0,0 +1,255 @@
+#![allow(non_upper_case_globals)]
+
+use std::fmt;
+use std::ops::BitOr;
+
+use crate::iracing::data_collector::*;
+use crate::iracing::snapshot::{ PodVarType, VarKind, VarShape, VarType };
+
+// Typed wrappers around the irsdk bitfield enums, so e.g. `SessionFlags` can be read
+// with a `VarHandle<Flags>` and checked with `flags.contains(Flags::Yellow)`
+macro_rules! bitfield {
+    ($name:ident { $($flag:ident = $value:expr,)* }) => {
+        #[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+        #[repr(transparent)]
+        pub struct $name(pub u32);
+
+        impl $name {
+            $(pub const $flag: $name = $name($value as u32);)*
+
+            const NAMES: &'static [(&'static str, $name)] = &[$((stringify!($flag), $name::$flag),)*];
+
+            pub fn bits(&self) -> u32 {
+                self.0
+            }
+
+            pub fn contains(&self, other: $name) -> bool {
+                self.0 & other.0 == other.0
+            }
+
+            pub fn intersects(&self, other: $name) -> bool {
+                self.0 & other.0 != 0
+            }
+
+            pub fn is_empty(&self) -> bool {
+                self.0 == 0
+            }
+        }
+
+        impl BitOr for $name {
+            type Output = $name;
+
+            fn bitor(self, other: $name) -> $name {
+                $name(self.0 | other.0)
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let names: Vec<&str> = $name::NAMES.iter()
+                    .filter(|(_, flag)| self.contains(*flag))
+                    .map(|(name, _)| *name)
+                    .collect();
+                write!(f, "{}({})", stringify!($name), names.join(" | "))
+            }
+        }
+
+        impl VarType for $name {
+            const KIND: VarKind = VarKind::BitField;
+
+            fn read(bytes: &[u8]) -> Self {
+                $name(u32::read(bytes))
+            }
+        }
+
+        unsafe impl PodVarType for $name {}
+
+        impl VarShape for $name {
+            type Element = $name;
+            const IS_ARRAY: bool = false;
+        }
+    };
+}
+
+bitfield!(Flags {
+    Checkered = irsdk_Flags_irsdk_checkered,
+    White = irsdk_Flags_irsdk_white,
+    Green = irsdk_Flags_irsdk_green,
+    Yellow = irsdk_Flags_irsdk_yellow,
+    Red = irsdk_Flags_irsdk_red,
+    Blue = irsdk_Flags_irsdk_blue,
+    Debris = irsdk_Flags_irsdk_debris,
+    Crossed = irsdk_Flags_irsdk_crossed,
+    YellowWaving = irsdk_Flags_irsdk_yellowWaving,
+    OneLapToGreen = irsdk_Flags_irsdk_oneLapToGreen,
+    GreenHeld = irsdk_Flags_irsdk_greenHeld,
+    TenToGo = irsdk_Flags_irsdk_tenToGo,
+    FiveToGo = irsdk_Flags_irsdk_fiveToGo,
+    RandomWaving = irsdk_Flags_irsdk_randomWaving,
+    Caution = irsdk_Flags_irsdk_caution,
+    CautionWaving = irsdk_Flags_irsdk_cautionWaving,
+
+    Black = irsdk_Flags_irsdk_black,
+    Disqualify = irsdk_Flags_irsdk_disqualify,
+    Servicible = irsdk_Flags_irsdk_servicible,
+    Furled = irsdk_Flags_irsdk_furled,
+    Repair = irsdk_Flags_irsdk_repair,
+
+    StartHidden = irsdk_Flags_irsdk_startHidden,
+    StartReady = irsdk_Flags_irsdk_startReady,
+    StartSet = irsdk_Flags_irsdk_startSet,
+    StartGo = irsdk_Flags_irsdk_startGo,
+});
+
+bitfield!(EngineWarnings {
+    WaterTempWarning = irsdk_EngineWarnings_irsdk_waterTempWarning,
+    FuelPressureWarning = irsdk_EngineWarnings_irsdk_fuelPressureWarning,
+    OilPressureWarning = irsdk_EngineWarnings_irsdk_oilPressureWarning,
+    EngineStalled = irsdk_EngineWarnings_irsdk_engineStalled,
+    PitSpeedLimiter = irsdk_EngineWarnings_irsdk_pitSpeedLimiter,
+    RevLimiterActive = irsdk_EngineWarnings_irsdk_revLimiterActive,
+    OilTempWarning = irsdk_EngineWarnings_irsdk_oilTempWarning,
+});
+
+bitfield!(PitSvFlags {
+    LFTireChange = irsdk_PitSvFlags_irsdk_LFTireChange,
+    RFTireChange = irsdk_PitSvFlags_irsdk_RFTireChange,
+    LRTireChange = irsdk_PitSvFlags_irsdk_LRTireChange,
+    RRTireChange = irsdk_PitSvFlags_irsdk_RRTireChange,
+    FuelFill = irsdk_PitSvFlags_irsdk_FuelFill,
+    WindshieldTearoff = irsdk_PitSvFlags_irsdk_WindshieldTearoff,
+    FastRepair = irsdk_PitSvFlags_irsdk_FastRepair,
+});
+
+bitfield!(PaceFlags {
+    EndOfLine = irsdk_PaceFlags_irsdk_PaceFlagsEndOfLine,
+    FreePass = irsdk_PaceFlags_irsdk_PaceFlagsFreePass,
+    WavedAround = irsdk_PaceFlags_irsdk_PaceFlagsWavedAround,
+});
+
+bitfield!(CameraState {
+    IsSessionScreen = irsdk_CameraState_irsdk_IsSessionScreen,
+    IsScenicActive = irsdk_CameraState_irsdk_IsScenicActive,
+    CamToolActive = irsdk_CameraState_irsdk_CamToolActive,
+    UIHidden = irsdk_CameraState_irsdk_UIHidden,
+    UseAutoShotSelection = irsdk_CameraState_irsdk_UseAutoShotSelection,
+    UseTemporaryEdits = irsdk_CameraState_irsdk_UseTemporaryEdits,
+    UseKeyAcceleration = irsdk_CameraState_irsdk_UseKeyAcceleration,
+    UseKey10xAcceleration = irsdk_CameraState_irsdk_UseKey10xAcceleration,
+    UseMouseAimMode = irsdk_CameraState_irsdk_UseMouseAimMode,
+});
+
+// Where a car is relative to the track, as reported in `PlayerTrackSurface` and `CarIdxTrackSurface`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackLocation {
+    NotInWorld,
+    OffTrack,
+    InPitStall,
+    ApproachingPits,
+    OnTrack,
+}
+
+impl TrackLocation {
+    pub fn from_raw(raw: i32) -> TrackLocation {
+        match raw as irsdk_TrkLoc {
+            irsdk_TrkLoc_irsdk_OffTrack => TrackLocation::OffTrack,
+            irsdk_TrkLoc_irsdk_InPitStall => TrackLocation::InPitStall,
+            irsdk_TrkLoc_irsdk_AproachingPits => TrackLocation::ApproachingPits,
+            irsdk_TrkLoc_irsdk_OnTrack => TrackLocation::OnTrack,
+            _ => TrackLocation::NotInWorld,
+        }
+    }
+}
+
+// Progress of the pit service of the player's car, from `PlayerCarPitSvStatus`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PitServiceStatus {
+    None,
+    InProgress,
+    Complete,
+
+    TooFarLeft,
+    TooFarRight,
+    TooFarForward,
+    TooFarBack,
+    BadAngle,
+    CantFixThat,
+    Unknown(i32),
+}
+
+impl PitServiceStatus {
+    pub fn from_raw(raw: i32) -> PitServiceStatus {
+        match raw as irsdk_PitSvStatus {
+            irsdk_PitSvStatus_irsdk_PitSvNone => PitServiceStatus::None,
+            irsdk_PitSvStatus_irsdk_PitSvInProgress => PitServiceStatus::InProgress,
+            irsdk_PitSvStatus_irsdk_PitSvComplete => PitServiceStatus::Complete,
+            irsdk_PitSvStatus_irsdk_PitSvTooFarLeft => PitServiceStatus::TooFarLeft,
+            irsdk_PitSvStatus_irsdk_PitSvTooFarRight => PitServiceStatus::TooFarRight,
+            irsdk_PitSvStatus_irsdk_PitSvTooFarForward => PitServiceStatus::TooFarForward,
+            irsdk_PitSvStatus_irsdk_PitSvTooFarBack => PitServiceStatus::TooFarBack,
+            irsdk_PitSvStatus_irsdk_PitSvBadAngle => PitServiceStatus::BadAngle,
+            irsdk_PitSvStatus_irsdk_PitSvCantFixThat => PitServiceStatus::CantFixThat,
+            _ => PitServiceStatus::Unknown(raw),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        !matches!(self, PitServiceStatus::None | PitServiceStatus::InProgress | PitServiceStatus::Complete)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iracing::data_collector::DataHeader;
+    use crate::iracing::snapshot::{ LineBuffer, VarHandle };
+
+    fn header(name: &str, offset: usize, count: usize) -> DataHeader {
+        DataHeader {
+            name: name.to_string(),
+            description: String::new(),
+            unit: String::new(),
+            kind: Some(VarKind::BitField),
+            offset,
+            count,
+        }
+    }
+
+    #[test]
+    fn checks_flags() {
+        let flags = Flags::Yellow | Flags::Blue;
+        assert!(flags.contains(Flags::Yellow));
+        assert!(flags.contains(Flags::Yellow | Flags::Blue));
+        assert!(!flags.contains(Flags::Yellow | Flags::Red));
+        assert!(flags.intersects(Flags::Yellow | Flags::Red));
+        assert!(!flags.intersects(Flags::Red | Flags::Black));
+        assert!(Flags::default().is_empty());
+        assert_eq!(format!["{:?}", flags], "Flags(Yellow | Blue)");
+    }
+
+    #[test]
+    fn reads_flags_from_a_line() {
+        let headers = [header("SessionFlags", 0, 1), header("EngineWarnings", 4, 1), header("CarIdxSessionFlags", 8, 2)];
+        let mut buffer = LineBuffer::new(16);
+        let bytes = buffer.as_bytes_mut();
+        bytes[0..4].copy_from_slice(&(Flags::Green | Flags::OneLapToGreen).bits().to_ne_bytes());
+        bytes[4..8].copy_from_slice(&EngineWarnings::PitSpeedLimiter.bits().to_ne_bytes());
+        bytes[8..12].copy_from_slice(&Flags::Black.bits().to_ne_bytes());
+        bytes[12..16].copy_from_slice(&(Flags::Blue | Flags::Repair).bits().to_ne_bytes());
+        let snapshot = buffer.snapshot();
+
+        let session_flags = snapshot.get(&VarHandle::<Flags>::resolve(&headers, buffer.len(), "SessionFlags").unwrap());
+        assert!(session_flags.contains(Flags::Green));
+        assert!(session_flags.intersects(Flags::OneLapToGreen | Flags::Yellow));
+        assert!(!session_flags.intersects(Flags::Yellow | Flags::Caution));
+
+        let engine_warnings = snapshot.get(&VarHandle::<EngineWarnings>::resolve(&headers, buffer.len(), "EngineWarnings").unwrap());
+        assert_eq!(engine_warnings, EngineWarnings::PitSpeedLimiter);
+        assert!(!engine_warnings.contains(EngineWarnings::EngineStalled));
+
+        let car_flags = snapshot.slice(&VarHandle::<[Flags]>::resolve(&headers, buffer.len(), "CarIdxSessionFlags").unwrap());
+        assert!(car_flags[0].contains(Flags::Black));
+        assert!(car_flags[1].contains(Flags::Blue | Flags::Repair));
+        assert!(!car_flags[1].intersects(Flags::Black));
+    }
+}
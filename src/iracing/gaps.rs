@@ -0,0 +1,380 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::iracing::{ Telemetry, SessionInfo };
+
+// Number of stretches the lap is divided into for keeping the speed history
+const SEGMENTS: usize = 100;
+// How much a new traversal of a segment counts, against the history of the previous ones
+const SMOOTHING: f64 = 0.3;
+// Below this a car is considered stopped, and its speed useless for estimating gaps
+const MIN_SPEED: f64 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gap {
+    // The other car, ahead or behind
+    pub car_idx: usize,
+    // Time until the car behind reaches where the car ahead is now, not counting full laps
+    pub seconds: f32,
+    // Full laps between the cars on the race distance
+    pub laps: i32,
+}
+
+// As in the standings, e.g. `+1.4` or `+2L`
+impl fmt::Display for Gap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.laps > 0 {
+            write!(f, "+{}L", self.laps)
+        } else {
+            write!(f, "+{:.1}", self.seconds)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CarGaps {
+    // The closest cars on track, regardless of lap or race position
+    pub ahead: Option<Gap>,
+    pub behind: Option<Gap>,
+    // By race position, as in the standings
+    pub to_leader: Option<Gap>,
+    pub interval: Option<Gap>,
+    // The same, within the class of the car
+    pub class_to_leader: Option<Gap>,
+    pub class_interval: Option<Gap>,
+}
+
+#[derive(Debug, Clone)]
+struct CarHistory {
+    // Seconds the car takes through each segment, smoothed over its recent laps
+    segment_times: Vec<Option<f64>>,
+    // Segment the car is in, and the session time it entered it unless it appeared halfway
+    entered: Option<(usize, Option<f64>)>,
+    last_sample: Option<(f32, f64)>,
+    // Meters per second
+    speed: Option<f64>,
+}
+
+impl CarHistory {
+    fn new() -> CarHistory {
+        CarHistory {
+            segment_times: vec![None; SEGMENTS],
+            entered: None,
+            last_sample: None,
+            speed: None,
+        }
+    }
+
+    fn update(&mut self, dist: f32, now: f64, track_length: Option<f64>) {
+        if dist < 0.0 {
+            self.entered = None;
+            self.last_sample = None;
+            self.speed = None;
+            return;
+        }
+
+        if let (Some((last_dist, last_time)), Some(track_length)) = (self.last_sample, track_length) {
+            let elapsed = now - last_time;
+            if elapsed > 0.0 {
+                let speed = (dist - last_dist).rem_euclid(1.0) as f64 * track_length / elapsed;
+                self.speed = Some(self.speed.map_or(speed, |last_speed| last_speed + (speed - last_speed) * SMOOTHING));
+            }
+        }
+        self.last_sample = Some((dist, now));
+
+        let segment = segment_of(dist);
+        match self.entered {
+            Some((entered_segment, _)) if entered_segment == segment => (),
+            Some((entered_segment, entered_at)) => {
+                let next = segment == (entered_segment + 1) % SEGMENTS;
+                // Only segments driven from start to end tell how long they take
+                if let (true, Some(entered_at)) = (next, entered_at) {
+                    let time = now - entered_at;
+                    let history = &mut self.segment_times[entered_segment];
+                    *history = Some(history.map_or(time, |history| history + (time - history) * SMOOTHING));
+                }
+                self.entered = Some((segment, if next { Some(now) } else { None }));
+            },
+            None => self.entered = Some((segment, None)),
+        }
+    }
+}
+
+fn segment_of(dist: f32) -> usize {
+    ((dist.clamp(0.0, 1.0) * SEGMENTS as f32) as usize).min(SEGMENTS - 1)
+}
+
+// Turns the distance between cars into time. Uses how long the chasing car took through each
+// part of the track on previous laps, and falls back to the estimates of the sim or the current
+// speed of the car for parts it has not driven yet.
+#[derive(Default)]
+pub struct GapCalculator {
+    track_length: Option<f64>,
+    // Cars that are not part of the race, like the pace car
+    ignored: HashSet<usize>,
+    // The estimated lap time of the class of each car, from the session info
+    est_lap_times: Vec<Option<f64>>,
+    // The class id of each car
+    classes: Vec<Option<i64>>,
+    cars: Vec<CarHistory>,
+    last_session_time: f64,
+}
+
+impl GapCalculator {
+    pub fn new() -> GapCalculator {
+        GapCalculator::default()
+    }
+
+    pub fn set_session(&mut self, session_info: &SessionInfo) {
+        let track_length = Some(session_info.weekend.track_length_km as f64 * 1000.0).filter(|&length| length > 0.0);
+        if track_length != self.track_length {
+            self.track_length = track_length;
+            self.cars.clear();
+        }
+
+        self.ignored = session_info.drivers.iter()
+            .filter(|driver| driver.is_pace_car || driver.is_spectator)
+            .map(|driver| driver.car_idx)
+            .collect();
+
+        self.est_lap_times.clear();
+        self.classes.clear();
+        for driver in &session_info.drivers {
+            if self.est_lap_times.len() <= driver.car_idx {
+                self.est_lap_times.resize(driver.car_idx + 1, None);
+                self.classes.resize(driver.car_idx + 1, None);
+            }
+            self.est_lap_times[driver.car_idx] = Some(driver.car_class_est_lap_time as f64).filter(|&time| time > 0.0);
+            self.classes[driver.car_idx] = session_info.class_of(driver.car_idx).map(|class| class.id);
+        }
+    }
+
+    // The time the chasing car needs to cover the distance from where it is to `to`, going forward
+    fn time_between(&self, telemetry: &Telemetry, chaser: usize, to: usize) -> Option<f64> {
+        let from_dist = *telemetry.lap_dist_by_car.get(chaser)?;
+        let to_dist = *telemetry.lap_dist_by_car.get(to)?;
+        let distance = (to_dist - from_dist).rem_euclid(1.0);
+        let history = self.cars.get(chaser)?;
+
+        let mut time = 0.0;
+        let mut unknown = 0.0;
+        let mut segment = segment_of(from_dist);
+        let mut dist = from_dist;
+        let mut remaining = distance;
+        for _ in 0..=SEGMENTS {
+            if remaining <= 0.0 {
+                break;
+            }
+            let segment_end = (segment + 1) as f32 / SEGMENTS as f32;
+            let covered = (segment_end - dist).max(0.0).min(remaining);
+            match history.segment_times[segment] {
+                Some(segment_time) => time += segment_time * (covered * SEGMENTS as f32) as f64,
+                None => unknown += covered,
+            }
+
+            remaining -= covered;
+            segment = (segment + 1) % SEGMENTS;
+            dist = segment as f32 / SEGMENTS as f32;
+        }
+
+        if unknown > 0.0 {
+            time += self.estimate(telemetry, chaser, to, unknown, distance)?;
+        }
+        Some(time)
+    }
+
+    // Time for the part of `distance` that the chasing car has no history for
+    fn estimate(&self, telemetry: &Telemetry, chaser: usize, to: usize, unknown: f32, distance: f32) -> Option<f64> {
+        let est_lap_time = self.est_lap_times.get(chaser).copied().flatten();
+        let to_est_lap_time = self.est_lap_times.get(to).copied().flatten();
+
+        // The sim estimates how long the class of each car takes to where it is now. For a car of
+        // another class that is first turned into how long the class of the chasing car takes there.
+        if let (Some(&chaser_est), Some(&to_est), Some(est_lap_time), Some(to_est_lap_time)) =
+            (telemetry.car_est_times.get(chaser), telemetry.car_est_times.get(to), est_lap_time, to_est_lap_time) {
+            let to_est = to_est as f64 / to_est_lap_time * est_lap_time;
+            let between = (to_est - chaser_est as f64).rem_euclid(est_lap_time);
+            return Some(between * (unknown / distance) as f64);
+        }
+
+        match (self.cars[chaser].speed, self.track_length) {
+            (Some(speed), Some(track_length)) if speed > MIN_SPEED => Some(unknown as f64 * track_length / speed),
+            _ => est_lap_time.map(|est_lap_time| unknown as f64 * est_lap_time),
+        }
+    }
+
+    fn gap(&self, telemetry: &Telemetry, chaser: usize, to: usize, laps: i32) -> Option<Gap> {
+        Some(Gap {
+            car_idx: to,
+            seconds: self.time_between(telemetry, chaser, to)? as f32,
+            laps,
+        })
+    }
+
+    // Fills in the gaps of the telemetry
+    pub fn update(&mut self, telemetry: &mut Telemetry) {
+        if telemetry.session_time < self.last_session_time {
+            self.cars.clear();
+        }
+        self.last_session_time = telemetry.session_time;
+
+        if self.cars.len() < telemetry.lap_dist_by_car.len() {
+            self.cars.resize_with(telemetry.lap_dist_by_car.len(), CarHistory::new);
+        }
+        for (car, &dist) in self.cars.iter_mut().zip(&telemetry.lap_dist_by_car) {
+            car.update(dist, telemetry.session_time, self.track_length);
+        }
+
+        let racing: Vec<usize> = telemetry.lap_dist_by_car.iter().enumerate()
+            .filter(|(car_idx, &dist)| dist >= 0.0 && !self.ignored.contains(car_idx))
+            .map(|(car_idx, _)| car_idx)
+            .collect();
+        let dist = |car_idx: usize| telemetry.lap_dist_by_car[car_idx];
+        // Laps and lap distance combined, to compare cars on the race distance
+        let progress = |car_idx: usize| telemetry.car_laps_completed.get(car_idx)
+            .map_or(0.0, |&laps| laps.max(0) as f64) + dist(car_idx) as f64;
+        let position = |car_idx: usize| telemetry.car_positions.get(car_idx).copied().unwrap_or(0);
+        let class_position = |car_idx: usize| telemetry.car_class_positions.get(car_idx).copied().unwrap_or(0);
+        let class = |car_idx: usize| self.classes.get(car_idx).copied().flatten();
+
+        let mut gaps = vec![CarGaps::default(); telemetry.lap_dist_by_car.len()];
+        for &car_idx in &racing {
+            let closest = |ahead: bool| racing.iter()
+                .filter(|&&other| other != car_idx)
+                .min_by(|&&a, &&b| {
+                    let distance = |other: usize| if ahead {
+                        (dist(other) - dist(car_idx)).rem_euclid(1.0)
+                    } else {
+                        (dist(car_idx) - dist(other)).rem_euclid(1.0)
+                    };
+                    distance(a).total_cmp(&distance(b))
+                })
+                .copied();
+
+            let gaps = &mut gaps[car_idx];
+            gaps.ahead = closest(true).and_then(|ahead| self.gap(telemetry, car_idx, ahead, 0));
+            gaps.behind = closest(false)
+                .and_then(|behind| self.gap(telemetry, behind, car_idx, 0).map(|gap| Gap { car_idx: behind, ..gap }));
+
+            let in_position = |wanted: i32| racing.iter().copied().find(|&other| position(other) == wanted);
+            let race_gap = |to: usize| {
+                let laps = (progress(to) - progress(car_idx)).floor().max(0.0) as i32;
+                self.gap(telemetry, car_idx, to, laps)
+            };
+            if position(car_idx) > 1 {
+                gaps.to_leader = in_position(1).and_then(race_gap);
+                gaps.interval = in_position(position(car_idx) - 1).and_then(race_gap);
+            }
+
+            let in_class_position = |wanted: i32| racing.iter().copied()
+                .find(|&other| class_position(other) == wanted && class(other) == class(car_idx));
+            if class_position(car_idx) > 1 && class(car_idx).is_some() {
+                gaps.class_to_leader = in_class_position(1).and_then(race_gap);
+                gaps.class_interval = in_class_position(class_position(car_idx) - 1).and_then(race_gap);
+            }
+        }
+
+        telemetry.gaps = gaps;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.unwrap_or_else(|| panic!("No time, expected {}", expected));
+        assert!((actual - expected).abs() < 1e-3, "{} is not {}", actual, expected);
+    }
+
+    // Cars of a 100 second class on a 5 km track, that have been seen once
+    fn calculator(cars: usize) -> GapCalculator {
+        let mut calculator = GapCalculator {
+            track_length: Some(5000.0),
+            est_lap_times: vec![Some(100.0); cars],
+            ..GapCalculator::default()
+        };
+        calculator.cars = vec![CarHistory::new(); cars];
+        calculator
+    }
+
+    fn telemetry(lap_dist_by_car: Vec<f32>) -> Telemetry {
+        Telemetry {
+            lap_dist_by_car,
+            ..Telemetry::default()
+        }
+    }
+
+    #[test]
+    fn times_across_the_line() {
+        let mut calculator = calculator(2);
+        calculator.cars[0].segment_times = vec![Some(1.0); SEGMENTS];
+        let telemetry = telemetry(vec![0.955, 0.055]);
+        assert_close(calculator.time_between(&telemetry, 0, 1), 10.0);
+        // Going forward, so all the way around the other way
+        calculator.cars[1].segment_times = vec![Some(1.0); SEGMENTS];
+        assert_close(calculator.time_between(&telemetry, 1, 0), 90.0);
+    }
+
+    #[test]
+    fn estimates_unknown_segments() {
+        let mut calculator = calculator(2);
+        // Half of the distance has been driven at a second per segment
+        for segment in 20..30 {
+            calculator.cars[0].segment_times[segment] = Some(1.0);
+        }
+        let mut telemetry = telemetry(vec![0.2, 0.4]);
+
+        // Nothing to go on for the rest, but the estimated lap time of the class
+        assert_close(calculator.time_between(&telemetry, 0, 1), 10.0 + 10.0);
+
+        // The current speed of the car
+        calculator.cars[0].speed = Some(25.0);
+        assert_close(calculator.time_between(&telemetry, 0, 1), 10.0 + 20.0);
+
+        // The estimates of the sim, of which the part that is not known counts
+        telemetry.car_est_times = vec![18.0, 42.0];
+        assert_close(calculator.time_between(&telemetry, 0, 1), 10.0 + 12.0);
+        telemetry.car_est_times = vec![98.0, 6.0];
+        assert_close(calculator.time_between(&telemetry, 0, 1), 10.0 + 4.0);
+    }
+
+    #[test]
+    fn estimates_cars_of_another_class() {
+        let mut calculator = calculator(2);
+        // The car ahead is of a class that laps in 80 seconds
+        calculator.est_lap_times[1] = Some(80.0);
+        let mut telemetry = telemetry(vec![0.25, 0.5]);
+        telemetry.car_est_times = vec![25.0, 40.0];
+        // Halfway for the faster class is 50 seconds into the lap for the chasing car
+        assert_close(calculator.time_between(&telemetry, 0, 1), 25.0);
+        // And the other way around
+        telemetry.car_est_times = vec![25.0, 40.0];
+        assert_close(calculator.time_between(&telemetry, 1, 0), 60.0);
+    }
+
+    #[test]
+    fn counts_laps_by_race_position() {
+        let mut calculator = calculator(4);
+        calculator.ignored.insert(3);
+        let mut telemetry = telemetry(vec![0.2, 0.5, 0.1, 0.15]);
+        telemetry.car_laps_completed = vec![10, 9, 8, 0];
+        telemetry.car_positions = vec![1, 2, 3, 0];
+        calculator.update(&mut telemetry);
+
+        let gaps = &telemetry.gaps;
+        assert_eq!(gaps[0].to_leader, None);
+        assert_eq!(gaps[0].interval, None);
+        assert_eq!(gaps[1].to_leader.map(|gap| (gap.car_idx, gap.laps)), Some((0, 0)));
+        assert_eq!(gaps[1].to_leader.unwrap().to_string(), "+70.0");
+        assert_eq!(gaps[2].to_leader.map(|gap| (gap.car_idx, gap.laps)), Some((0, 2)));
+        assert_eq!(gaps[2].to_leader.unwrap().to_string(), "+2L");
+        assert_eq!(gaps[2].interval.map(|gap| (gap.car_idx, gap.laps)), Some((1, 1)));
+        assert_close(gaps[2].interval.map(|gap| gap.seconds as f64), 40.0);
+
+        // The closest cars on track, leaving out the pace car
+        assert_eq!(gaps[2].ahead.map(|gap| gap.car_idx), Some(0));
+        assert_eq!(gaps[2].behind.map(|gap| gap.car_idx), Some(1));
+        assert_eq!(gaps[3], CarGaps::default());
+    }
+}
@@ -0,0 +1,165 @@
+use std::fs::File;
+use std::io::{ BufReader, Read, Seek, SeekFrom };
+use std::mem::size_of;
+use std::path::Path;
+use std::time::{ Duration, Instant };
+
+use async_std::task;
+use async_std::task::{Context, Poll};
+use async_std::stream::Stream;
+use async_std::pin::Pin;
+
+use crate::iracing::data_collector::{
+    irsdk_header, irsdk_diskSubHeader, irsdk_varHeader,
+    Update, CollectorError,
+    latin1_to_string,
+};
+use crate::iracing::ConnectionStatus;
+use crate::iracing::snapshot::{ LineBuffer, Snapshot };
+
+// An .ibt file mirrors the live memory layout: an irsdk_header followed by an
+// irsdk_diskSubHeader, after which the offsets in the header point to the
+// session info string, the var headers and the rows of telemetry.
+pub struct IbtReader {
+    reader: BufReader<File>,
+    header: irsdk_header,
+    disk_header: irsdk_diskSubHeader,
+    var_headers: Vec<irsdk_varHeader>,
+
+    announced: bool,
+    session_info: Option<String>,
+    buffer: LineBuffer,
+    records_read: usize,
+
+    // When set, rows are emitted at the tick rate they were recorded with
+    tick_interval: Option<Duration>,
+    next_tick: Instant,
+}
+
+fn read_struct<T: Copy>(reader: &mut impl Read) -> Result<T, String> {
+    let mut bytes = vec![0u8; size_of::<T>()];
+    reader.read_exact(&mut bytes)
+        .map_err(|err| format!["Failed to read {}: {}", std::any::type_name::<T>(), err])?;
+    Ok(unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+}
+
+impl IbtReader {
+    pub fn open(path: &Path, real_time: bool) -> Result<IbtReader, String> {
+        let file = File::open(path)
+            .map_err(|err| format!["Could not open ibt file {}: {}", path.display(), err])?;
+        let mut reader = BufReader::new(file);
+
+        let header: irsdk_header = read_struct(&mut reader)?;
+        let disk_header: irsdk_diskSubHeader = read_struct(&mut reader)?;
+        if header.numVars < 0 || header.bufLen <= 0 || header.numBuf < 1 {
+            return Err(format!["{} does not look like an ibt file", path.display()]);
+        }
+
+        reader.seek(SeekFrom::Start(header.varHeaderOffset as u64))
+            .map_err(|err| format!["Failed to seek to var headers: {}", err])?;
+        let mut var_headers = Vec::with_capacity(header.numVars as usize);
+        for _ in 0..header.numVars {
+            var_headers.push(read_struct::<irsdk_varHeader>(&mut reader)?);
+        }
+
+        reader.seek(SeekFrom::Start(header.sessionInfoOffset as u64))
+            .map_err(|err| format!["Failed to seek to session info: {}", err])?;
+        let mut session_info = vec![0u8; header.sessionInfoLen as usize];
+        reader.read_exact(&mut session_info)
+            .map_err(|err| format!["Failed to read session info: {}", err])?;
+        // The session info string is null padded on disk
+        let session_info_len = session_info.iter().position(|&c| c == 0).unwrap_or(session_info.len());
+
+        reader.seek(SeekFrom::Start(header.varBuf[0].bufOffset as u64))
+            .map_err(|err| format!["Failed to seek to telemetry rows: {}", err])?;
+
+        info!["Opened ibt file {} with {} records of {} variables",
+            path.display(), disk_header.sessionRecordCount, header.numVars];
+
+        let tick_interval = if real_time && header.tickRate > 0 {
+            Some(Duration::from_secs_f64(1.0 / header.tickRate as f64))
+        } else {
+            None
+        };
+
+        Ok(IbtReader {
+            reader,
+            buffer: LineBuffer::new(header.bufLen as usize),
+            header,
+            disk_header,
+            var_headers,
+            announced: false,
+            session_info: Some(latin1_to_string(&session_info[..session_info_len])),
+            records_read: 0,
+            tick_interval,
+            next_tick: Instant::now(),
+        })
+    }
+
+    pub fn var_headers(&self) -> &[irsdk_varHeader] {
+        &self.var_headers
+    }
+
+    pub fn snapshot(&self) -> Snapshot<'_> {
+        self.buffer.snapshot()
+    }
+
+    pub fn line_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn tick_rate(&self) -> i32 {
+        self.header.tickRate
+    }
+
+    pub fn record_count(&self) -> usize {
+        self.disk_header.sessionRecordCount.max(0) as usize
+    }
+}
+
+impl Stream for IbtReader {
+    type Item = Result<Update, CollectorError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if !self.announced {
+            self.announced = true;
+            return Poll::Ready(Some(Ok(Update::Connection(ConnectionStatus::Connected { tick_rate: self.tick_rate() }))));
+        }
+
+        if let Some(session_info) = self.session_info.take() {
+            return Poll::Ready(Some(Ok(Update::SessionInfo(session_info))));
+        }
+
+        // Files that were not closed properly have no record count, so read those until EOF
+        if self.record_count() > 0 && self.records_read >= self.record_count() {
+            return Poll::Ready(None);
+        }
+
+        if let Some(tick_interval) = self.tick_interval {
+            let now = Instant::now();
+            if now < self.next_tick {
+                let waiter = cx.waker().clone();
+                let wait = self.next_tick - now;
+                task::spawn(async move {
+                    task::sleep(wait).await;
+                    waiter.wake();
+                });
+                return Poll::Pending;
+            }
+            self.next_tick = now.max(self.next_tick) + tick_interval;
+        }
+
+        let this = &mut *self;
+        match this.reader.read_exact(this.buffer.as_bytes_mut()) {
+            Ok(()) => (),
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Poll::Ready(None),
+            Err(err) => {
+                error!["Failed to read ibt record {}: {}", this.records_read, err];
+                return Poll::Ready(None);
+            }
+        }
+        this.records_read += 1;
+
+        Poll::Ready(Some(Ok(Update::Telemetry)))
+    }
+}
@@ -0,0 +1,425 @@
+use std::fs::{ File, OpenOptions };
+use std::io::{ BufWriter, ErrorKind, Seek, SeekFrom, Write };
+use std::mem::size_of;
+use std::path::{ Path, PathBuf };
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use async_std::task::{Context, Poll};
+use async_std::stream::Stream;
+use async_std::pin::Pin;
+
+use crate::iracing::data_collector::{
+    irsdk_header, irsdk_diskSubHeader, irsdk_varHeader,
+    irsdk_StatusField_irsdk_stConnected, IRSDK_VER,
+    irsdk_VarType, irsdk_VarType_irsdk_double, irsdk_VarType_irsdk_int,
+    IracingConnectionError, CollectorError, Update,
+    var_size,
+};
+use crate::iracing::snapshot::Snapshot;
+use crate::iracing::telemetry_source::{ TelemetrySource, TelemetryConnection };
+
+fn struct_bytes<T: Copy>(value: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+fn var_name(var_header: &irsdk_varHeader) -> String {
+    var_header.name.iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| c as u8 as char)
+        .collect()
+}
+
+// Writes telemetry lines in the same layout as iRacing's own disk telemetry.
+// The session info is only known for sure once recording stops, so it is placed
+// after the telemetry rows, which is fine as readers follow `sessionInfoOffset`.
+// Like in the sim's own files only the final session info is kept, earlier versions
+// of it are not recorded.
+pub struct IbtWriter {
+    file: BufWriter<File>,
+    path: PathBuf,
+
+    header: irsdk_header,
+    disk_header: irsdk_diskSubHeader,
+    session_info: String,
+
+    session_time_var: Option<usize>,
+    lap_var: Option<usize>,
+    first_lap: Option<i32>,
+    last_lap: Option<i32>,
+
+    finished: bool,
+}
+
+impl IbtWriter {
+    // Never overwrites an existing file, `ErrorKind::AlreadyExists` is returned for those
+    pub fn create(path: &Path, var_headers: &[irsdk_varHeader], tick_rate: i32) -> Result<IbtWriter, std::io::Error> {
+        let file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        IbtWriter::new(file, path, var_headers, tick_rate)
+    }
+
+    fn new(file: File, path: &Path, var_headers: &[irsdk_varHeader], tick_rate: i32) -> Result<IbtWriter, std::io::Error> {
+        let mut file = BufWriter::new(file);
+
+        let var_header_offset = size_of::<irsdk_header>() + size_of::<irsdk_diskSubHeader>();
+        let buf_offset = var_header_offset + var_headers.len() * size_of::<irsdk_varHeader>();
+        let buf_len = var_headers.iter()
+            .map(|var_header| var_header.offset as usize + var_size(var_header))
+            .max()
+            .unwrap_or(0);
+
+        let mut header: irsdk_header = unsafe { std::mem::zeroed() };
+        header.ver = IRSDK_VER as _;
+        header.status = irsdk_StatusField_irsdk_stConnected as _;
+        header.tickRate = tick_rate;
+        header.numVars = var_headers.len() as i32;
+        header.varHeaderOffset = var_header_offset as i32;
+        header.numBuf = 1;
+        header.bufLen = buf_len as i32;
+        header.varBuf[0].bufOffset = buf_offset as i32;
+
+        let mut disk_header: irsdk_diskSubHeader = unsafe { std::mem::zeroed() };
+        disk_header.sessionStartDate = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs())
+            .unwrap_or(0) as _;
+
+        // Reserve room for the headers, they are rewritten when the recording is finished
+        file.write_all(struct_bytes(&header))?;
+        file.write_all(struct_bytes(&disk_header))?;
+        for var_header in var_headers {
+            file.write_all(struct_bytes(var_header))?;
+        }
+
+        // The offset of a single value, when it is of the expected type
+        let find_var = |name: &str, var_type: irsdk_VarType, size: usize| var_headers.iter()
+            .find(|var_header| var_name(var_header) == name)
+            .filter(|var_header| var_header.type_ == var_type as i32 && var_header.count == 1)
+            .filter(|var_header| var_header.offset >= 0 && var_header.offset as usize + size <= buf_len)
+            .map(|var_header| var_header.offset as usize);
+
+        info!["Recording telemetry to {}", path.display()];
+
+        Ok(IbtWriter {
+            file,
+            path: path.to_path_buf(),
+            header,
+            disk_header,
+            session_info: String::new(),
+            session_time_var: find_var("SessionTime", irsdk_VarType_irsdk_double, size_of::<f64>()),
+            lap_var: find_var("Lap", irsdk_VarType_irsdk_int, size_of::<i32>()),
+            first_lap: None,
+            last_lap: None,
+            finished: false,
+        })
+    }
+
+    // Replaces the session info set before
+    pub fn set_session_info(&mut self, session_info: &str) {
+        self.session_info = session_info.to_string();
+    }
+
+    pub fn write_line(&mut self, line: &[u8]) -> Result<(), String> {
+        let buf_len = self.header.bufLen as usize;
+        if line.len() < buf_len {
+            return Err(format!["Telemetry line of {} bytes is shorter than the expected {}", line.len(), buf_len]);
+        }
+        self.file.write_all(&line[..buf_len])
+            .map_err(|err| format!["Failed to write {}: {}", self.path.display(), err])?;
+
+        if let Some(offset) = self.session_time_var {
+            let session_time = unsafe { std::ptr::read_unaligned(line.as_ptr().add(offset) as *const f64) };
+            if self.disk_header.sessionRecordCount == 0 {
+                self.disk_header.sessionStartTime = session_time;
+            }
+            self.disk_header.sessionEndTime = session_time;
+        }
+        if let Some(offset) = self.lap_var {
+            let lap = unsafe { std::ptr::read_unaligned(line.as_ptr().add(offset) as *const i32) };
+            self.first_lap.get_or_insert(lap);
+            self.last_lap = Some(lap);
+        }
+        self.disk_header.sessionRecordCount += 1;
+
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<(), String> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        let write_err = |err: std::io::Error| format!["Failed to finish {}: {}", self.path.display(), err];
+
+        // The session info string is Latin-1 encoded, like in the memory map
+        let session_info: Vec<u8> = self.session_info.chars()
+            .map(|c| if (c as u32) < 0x100 { c as u8 } else { b'?' })
+            .chain(std::iter::once(0))
+            .collect();
+        let session_info_offset = self.file.stream_position().map_err(write_err)?;
+        self.file.write_all(&session_info).map_err(write_err)?;
+
+        self.header.sessionInfoUpdate = 1;
+        self.header.sessionInfoLen = session_info.len() as i32;
+        self.header.sessionInfoOffset = session_info_offset as i32;
+        self.disk_header.sessionLapCount = match (self.first_lap, self.last_lap) {
+            (Some(first), Some(last)) => last - first + 1,
+            _ => 0,
+        };
+
+        self.file.seek(SeekFrom::Start(0)).map_err(write_err)?;
+        self.file.write_all(struct_bytes(&self.header)).map_err(write_err)?;
+        self.file.write_all(struct_bytes(&self.disk_header)).map_err(write_err)?;
+        self.file.flush().map_err(write_err)?;
+
+        info!["Finished recording {} records to {}", self.disk_header.sessionRecordCount, self.path.display()];
+        Ok(())
+    }
+}
+
+impl Drop for IbtWriter {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            error!["{}", err];
+        }
+    }
+}
+
+// Passes everything through from the wrapped connection, while teeing it into an .ibt file
+pub struct Recorder<C: TelemetryConnection> {
+    connection: C,
+    writer: Option<IbtWriter>,
+}
+
+// Reconnecting within the same second would otherwise reuse the name of the previous recording
+const MAX_NAME_ATTEMPTS: u32 = 100;
+
+impl<C: TelemetryConnection> Recorder<C> {
+    pub fn new(connection: C, directory: &Path) -> Recorder<C> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs())
+            .unwrap_or(0);
+
+        let mut writer = None;
+        for attempt in 0..MAX_NAME_ATTEMPTS {
+            let name = match attempt {
+                0 => format!["uberlays_{}.ibt", timestamp],
+                attempt => format!["uberlays_{}_{}.ibt", timestamp, attempt],
+            };
+            let path = directory.join(name);
+            match IbtWriter::create(&path, connection.var_headers(), connection.tick_rate()) {
+                Ok(created) => {
+                    writer = Some(created);
+                    break;
+                },
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+                Err(err) => {
+                    error!["Not recording telemetry to {}: {}", path.display(), err];
+                    break;
+                },
+            }
+        }
+
+        Recorder { connection, writer }
+    }
+
+    fn record(&mut self, update: &Update) {
+        if let Some(writer) = &mut self.writer {
+            let result = match update {
+                Update::Telemetry => writer.write_line(self.connection.snapshot().as_bytes()),
+                Update::SessionInfo(session_info) => {
+                    writer.set_session_info(session_info);
+                    Ok(())
+                },
+                Update::Connection(_) => Ok(()),
+            };
+
+            if let Err(err) = result {
+                error!["Stopped recording telemetry: {}", err];
+                self.writer = None;
+            }
+        }
+    }
+}
+
+impl<C: TelemetryConnection> TelemetryConnection for Recorder<C> {
+    fn var_headers(&self) -> &[irsdk_varHeader] {
+        self.connection.var_headers()
+    }
+
+    fn snapshot(&self) -> Snapshot<'_> {
+        self.connection.snapshot()
+    }
+
+    fn line_len(&self) -> usize {
+        self.connection.line_len()
+    }
+
+    fn tick_rate(&self) -> i32 {
+        self.connection.tick_rate()
+    }
+
+    fn torn_reads(&self) -> u64 {
+        self.connection.torn_reads()
+    }
+}
+
+impl<C: TelemetryConnection> Stream for Recorder<C> {
+    type Item = Result<Update, CollectorError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let polled = Pin::new(&mut self.connection).poll_next(cx);
+        match &polled {
+            Poll::Ready(Some(Ok(update))) => self.record(update),
+            Poll::Ready(Some(Err(_))) => (),
+            Poll::Ready(None) => {
+                // Finishing happens on drop, but there is no reason to wait for it
+                if let Some(mut writer) = self.writer.take() {
+                    if let Err(err) = writer.finish() {
+                        error!["{}", err];
+                    }
+                }
+            },
+            Poll::Pending => (),
+        }
+        polled
+    }
+}
+
+pub struct RecordingSource<S: TelemetrySource> {
+    source: S,
+    directory: PathBuf,
+}
+
+impl<S: TelemetrySource> RecordingSource<S> {
+    pub fn new(source: S, directory: PathBuf) -> RecordingSource<S> {
+        RecordingSource { source, directory }
+    }
+}
+
+impl<S: TelemetrySource> TelemetrySource for RecordingSource<S> {
+    type Connection = Recorder<S::Connection>;
+
+    fn connect(&mut self) -> Result<Self::Connection, IracingConnectionError> {
+        let connection = self.source.connect()?;
+        Ok(Recorder::new(connection, &self.directory))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::stream::StreamExt;
+    use crate::iracing::ConnectionStatus;
+    use crate::iracing::data_collector::irsdk_VarType_irsdk_float;
+    use crate::iracing::ibt_reader::IbtReader;
+
+    fn var_header(name: &str, var_type: irsdk_VarType, offset: i32) -> irsdk_varHeader {
+        let mut var_header: irsdk_varHeader = unsafe { std::mem::zeroed() };
+        var_header.type_ = var_type as _;
+        var_header.offset = offset;
+        var_header.count = 1;
+        for (c, &b) in var_header.name.iter_mut().zip(name.as_bytes()) {
+            *c = b as _;
+        }
+        var_header
+    }
+
+    fn line(session_time: f64, lap: i32) -> Vec<u8> {
+        let mut line = session_time.to_ne_bytes().to_vec();
+        line.extend_from_slice(&lap.to_ne_bytes());
+        line
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!["uberlays-{}-{}.ibt", std::process::id(), name]);
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn disk_header(path: &Path) -> irsdk_diskSubHeader {
+        let bytes = std::fs::read(path).unwrap();
+        unsafe { std::ptr::read_unaligned(bytes[size_of::<irsdk_header>()..].as_ptr() as *const irsdk_diskSubHeader) }
+    }
+
+    #[test]
+    fn reads_back_a_recording() {
+        let path = temp_path("round-trip");
+        let var_headers = [
+            var_header("SessionTime", irsdk_VarType_irsdk_double, 0),
+            var_header("Lap", irsdk_VarType_irsdk_int, 8),
+        ];
+        let lines = [line(10.0, 3), line(10.5, 3), line(11.0, 4), line(11.5, 5)];
+
+        let mut writer = IbtWriter::create(&path, &var_headers, 60).unwrap();
+        writer.set_session_info("WeekendInfo:\n TrackName: old\n");
+        for line in &lines {
+            writer.write_line(line).unwrap();
+        }
+        writer.set_session_info("DriverInfo:\n Drivers:\n - UserName: Jürgen\n");
+        writer.finish().unwrap();
+
+        let disk_header = disk_header(&path);
+        assert_eq!(disk_header.sessionRecordCount, 4);
+        assert_eq!(disk_header.sessionLapCount, 3);
+        assert_eq!(disk_header.sessionStartTime, 10.0);
+        assert_eq!(disk_header.sessionEndTime, 11.5);
+
+        let mut reader = IbtReader::open(&path, false).unwrap();
+        assert_eq!(reader.tick_rate(), 60);
+        assert_eq!(reader.record_count(), 4);
+        assert_eq!(reader.line_len(), 12);
+        let names: Vec<String> = reader.var_headers().iter().map(var_name).collect();
+        assert_eq!(names, ["SessionTime", "Lap"]);
+        assert_eq!(reader.var_headers()[1].offset, 8);
+
+        async_std::task::block_on(async {
+            assert!(matches![reader.next().await, Some(Ok(Update::Connection(ConnectionStatus::Connected { tick_rate: 60 })))]);
+            // Only the session info set last is kept
+            match reader.next().await {
+                Some(Ok(Update::SessionInfo(session_info))) =>
+                    assert_eq!(session_info, "DriverInfo:\n Drivers:\n - UserName: Jürgen\n"),
+                _ => panic!["Expected the session info"],
+            }
+            for line in &lines {
+                assert!(matches![reader.next().await, Some(Ok(Update::Telemetry))]);
+                assert_eq!(reader.snapshot().as_bytes(), &line[..]);
+            }
+            assert!(reader.next().await.is_none());
+        });
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ignores_laps_of_the_wrong_type() {
+        let path = temp_path("lap-type");
+        let var_headers = [
+            var_header("SessionTime", irsdk_VarType_irsdk_double, 0),
+            var_header("Lap", irsdk_VarType_irsdk_float, 8),
+        ];
+
+        let mut writer = IbtWriter::create(&path, &var_headers, 60).unwrap();
+        writer.write_line(&line(10.0, 3)).unwrap();
+        writer.write_line(&line(11.0, 5)).unwrap();
+        writer.finish().unwrap();
+
+        let disk_header = disk_header(&path);
+        assert_eq!(disk_header.sessionRecordCount, 2);
+        assert_eq!(disk_header.sessionLapCount, 0);
+        assert_eq!(disk_header.sessionEndTime, 11.0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn never_overwrites_a_recording() {
+        let path = temp_path("existing");
+        std::fs::write(&path, b"recording").unwrap();
+
+        let err = IbtWriter::create(&path, &[], 60).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read(&path).unwrap(), b"recording");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+use crate::iracing::{ Telemetry, SessionInfo };
+
+// From the formula the community worked out from the results iRacing publishes. It is not
+// official, but usually within a point or two of the real change.
+pub(crate) const BR: f64 = 1600.0 / std::f64::consts::LN_2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Entry {
+    pub car_idx: usize,
+    pub irating: i32,
+    // 1 for the winner, None for drivers that did not start. Cars that retired keep the position
+    // they were classified in.
+    pub position: Option<i32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IratingChange {
+    pub car_idx: usize,
+    pub irating: i32,
+    pub change: i32,
+}
+
+// The chance that a driver rated `a` finishes ahead of a driver rated `b`
+fn chance(a: f64, b: f64) -> f64 {
+    let (exp_a, exp_b) = ((-a / BR).exp(), (-b / BR).exp());
+    ((1.0 - exp_a) * exp_b) / ((1.0 - exp_b) * exp_a + (1.0 - exp_a) * exp_b)
+}
+
+// The change of every entry of a single class, in the same order
+pub fn estimate(entries: &[Entry]) -> Vec<IratingChange> {
+    let registrations = entries.len() as f64;
+    let non_starters = entries.iter().filter(|entry| entry.position.is_none()).count() as f64;
+    let starters = registrations - non_starters;
+    if starters == 0.0 {
+        return entries.iter()
+            .map(|entry| IratingChange { car_idx: entry.car_idx, irating: entry.irating, change: 0 })
+            .collect();
+    }
+
+    // A rating of 0 would make the chances undefined
+    let rating = |entry: &Entry| entry.irating.max(1) as f64;
+    // Includes the chance of beating yourself, which is a half
+    let expected: Vec<f64> = entries.iter()
+        .map(|entry| entries.iter().map(|other| chance(rating(entry), rating(other))).sum::<f64>() - 0.5)
+        .collect();
+
+    let mut changes: Vec<f64> = entries.iter().zip(&expected)
+        .map(|(entry, expected)| match entry.position {
+            Some(position) => {
+                let fudge = ((registrations - non_starters / 2.0) / 2.0 - position as f64) / 100.0;
+                (registrations - position as f64 - expected - fudge) * 200.0 / starters
+            },
+            None => 0.0,
+        })
+        .collect();
+
+    // What the starters gained together is taken from the non-starters, the higher rated losing more
+    if non_starters > 0.0 {
+        let gained: f64 = changes.iter().sum();
+        let non_starter_expected: Vec<f64> = entries.iter().zip(&expected)
+            .filter(|(entry, _)| entry.position.is_none())
+            .map(|(_, &expected)| expected)
+            .collect();
+        let average_expected = non_starter_expected.iter().sum::<f64>() / non_starters;
+        for ((entry, expected), change) in entries.iter().zip(&expected).zip(changes.iter_mut()) {
+            if entry.position.is_none() {
+                *change = -gained / non_starters * expected / average_expected;
+            }
+        }
+    }
+
+    entries.iter().zip(changes)
+        .map(|(entry, change)| IratingChange { car_idx: entry.car_idx, irating: entry.irating, change: change.round() as i32 })
+        .collect()
+}
+
+// The changes of the whole field if the race finished now. Every class is rated on its own,
+// by the positions within the class.
+pub fn estimate_field(session_info: &SessionInfo, telemetry: &Telemetry) -> Vec<IratingChange> {
+    let mut changes = vec![];
+    for class in &session_info.classes {
+        let entries: Vec<Entry> = session_info.drivers.iter()
+            .filter(|driver| !driver.is_ai && session_info.class_of(driver.car_idx).map(|class| class.id) == Some(class.id))
+            .map(|driver| {
+                let irating = if driver.car_idx == session_info.driver.car_idx {
+                    session_info.driver.irating
+                } else {
+                    driver.irating
+                };
+                Entry {
+                    car_idx: driver.car_idx,
+                    irating,
+                    position: telemetry.car_class_positions.get(driver.car_idx).copied().filter(|&position| position > 0),
+                }
+            })
+            .collect();
+        changes.extend(estimate(&entries));
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(field: &[(i32, Option<i32>)]) -> Vec<Entry> {
+        field.iter().enumerate()
+            .map(|(car_idx, &(irating, position))| Entry { car_idx, irating, position })
+            .collect()
+    }
+
+    fn changes(field: &[(i32, Option<i32>)]) -> Vec<i32> {
+        estimate(&entries(field)).iter().map(|change| change.change).collect()
+    }
+
+    #[test]
+    fn equal_ratings() {
+        // Both expected half a win, the winner gets (2 - 1 - 0.5 - 0) * 200 / 2 and the other
+        // (2 - 2 - 0.5 + 0.01) * 200 / 2, the fudge factor making up the difference
+        assert_eq!(changes(&[(1500, Some(1)), (1500, Some(2))]), [50, -49]);
+    }
+
+    #[test]
+    fn mixed_field() {
+        // Worked out by hand from the formula
+        assert_eq!(changes(&[(3000, Some(1)), (2000, Some(2)), (1500, Some(3)), (1000, Some(4))]), [38, 17, -14, -40]);
+        // The upset costs the favourite more than it gained for winning
+        assert_eq!(changes(&[(1000, Some(1)), (1500, Some(2)), (2000, Some(3)), (3000, Some(4))]), [108, 36, -32, -111]);
+
+        let estimated = estimate(&entries(&[(3000, Some(2)), (2000, Some(1))]));
+        assert_eq!(estimated[0], IratingChange { car_idx: 0, irating: 3000, change: -65 });
+        assert_eq!(estimated[1], IratingChange { car_idx: 1, irating: 2000, change: 66 });
+    }
+
+    #[test]
+    fn only_non_starters() {
+        assert_eq!(changes(&[(2000, None), (1500, None)]), [0, 0]);
+        assert!(changes(&[]).is_empty());
+    }
+
+    #[test]
+    fn single_starter() {
+        // Only the fudge factor is left
+        assert_eq!(changes(&[(2000, Some(1))]), [1]);
+        // What the starter gains is taken from the non-starters, the higher rated losing more
+        let with_non_starters = changes(&[(2000, Some(1)), (3000, None), (1000, None)]);
+        assert_eq!(with_non_starters, [188, -145, -44]);
+        assert!(with_non_starters.iter().sum::<i32>().abs() <= 1);
+    }
+
+    #[test]
+    fn zero_irating() {
+        // Rated as 1, as the chances are undefined for 0
+        assert_eq!(changes(&[(0, Some(1)), (0, Some(2))]), [50, -49]);
+        assert_eq!(changes(&[(0, Some(1)), (1350, Some(2)), (0, Some(3))]), [100, -66, -32]);
+    }
+}
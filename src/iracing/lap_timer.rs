@@ -0,0 +1,437 @@
+use std::time::Duration;
+
+use crate::iracing::Telemetry;
+use crate::iracing::session_info::SplitTimeInfo;
+
+// Moving further than this between two samples is not driving, but the car wrapping around
+// the lap, being towed or the replay jumping
+const WRAP_MARGIN: f32 = 0.5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lap {
+    pub car_idx: usize,
+    // The number of the lap that was completed
+    pub lap: i32,
+    pub time: Duration,
+    // Empty when the session info does not define any sectors
+    pub sectors: Vec<Duration>,
+    // Session time at which the car crossed the line
+    pub completed_at: f64,
+    pub is_personal_best: bool,
+    pub is_session_best: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LapTiming {
+    // The lap being driven
+    pub lap: i32,
+    // None until the car has been seen crossing the line
+    pub current: Option<Duration>,
+    pub last: Option<Duration>,
+    pub best: Option<Duration>,
+    // The sectors completed so far in the current lap, only kept by the `LapTimer` itself
+    pub sectors: Vec<Duration>,
+    pub best_sectors: Vec<Option<Duration>>,
+}
+
+impl LapTiming {
+    // A copy that does not allocate, for passing along with every tick of telemetry
+    pub fn without_sectors(&self) -> LapTiming {
+        LapTiming {
+            lap: self.lap,
+            current: self.current,
+            last: self.last,
+            best: self.best,
+            sectors: vec![],
+            best_sectors: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct CarState {
+    timing: LapTiming,
+    // Lap distance and session time of the previous sample, None while not in the world
+    last_sample: Option<(f32, f64)>,
+    lap_started_at: Option<f64>,
+    sector_started_at: Option<f64>,
+    laps_completed: Option<i32>,
+}
+
+impl CarState {
+    fn update(&mut self, car_idx: usize, sector_starts: &[f32], dist: f32, now: f64, lap: Option<i32>, laps_completed: Option<i32>) -> Option<Lap> {
+        if dist < 0.0 {
+            // In the garage or disconnected, so whatever lap was going on does not count
+            self.last_sample = None;
+            self.abandon_lap();
+            return None;
+        }
+
+        if self.laps_completed.is_none() {
+            self.laps_completed = laps_completed;
+        }
+        let (last_dist, last_time) = match self.last_sample.replace((dist, now)) {
+            Some(last_sample) => last_sample,
+            None => {
+                self.timing.lap = lap.unwrap_or(self.timing.lap);
+                return None;
+            },
+        };
+
+        let wrapped = last_dist - dist > WRAP_MARGIN;
+        if dist - last_dist > WRAP_MARGIN {
+            // Reversed back over the line
+            self.abandon_lap();
+            return None;
+        }
+
+        // Sector boundaries and the line passed since the previous sample, in order. Positions past
+        // the line are counted from 1.0, so they sort after it.
+        let end = if wrapped { dist + 1.0 } else { dist };
+        let mut passed: Vec<(f32, Option<usize>)> = vec![];
+        for (sector, &start) in sector_starts.iter().enumerate() {
+            for at in [start, start + 1.0] {
+                if at > last_dist && at <= end {
+                    passed.push((at, Some(sector)));
+                }
+            }
+        }
+        if wrapped {
+            passed.push((1.0, None));
+        }
+        passed.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+        let mut completed = None;
+        for (at, sector) in passed {
+            // The car crossed somewhere in between the two samples
+            let crossed_at = last_time + (now - last_time) * ((at - last_dist) / (end - last_dist)) as f64;
+            match sector {
+                Some(sector) => self.finish_sector(sector, crossed_at),
+                None => {
+                    if !sector_starts.is_empty() {
+                        self.finish_sector(sector_starts.len(), crossed_at);
+                    }
+                    completed = self.finish_lap(car_idx, crossed_at, laps_completed);
+                },
+            }
+        }
+
+        self.timing.lap = lap.unwrap_or(self.laps_completed.unwrap_or(0) + 1);
+        self.timing.current = self.lap_started_at.map(|started| Duration::from_secs_f64((now - started).max(0.0)));
+        completed
+    }
+
+    fn finish_sector(&mut self, sector: usize, at: f64) {
+        // Sectors are only timed from the start of a lap, so they line up with the sector numbers
+        if let Some(started) = self.sector_started_at {
+            if self.timing.sectors.len() == sector {
+                let time = Duration::from_secs_f64((at - started).max(0.0));
+                self.timing.sectors.push(time);
+
+                if self.timing.best_sectors.len() <= sector {
+                    self.timing.best_sectors.resize(sector + 1, None);
+                }
+                let best = &mut self.timing.best_sectors[sector];
+                if best.map_or(true, |best| time < best) {
+                    *best = Some(time);
+                }
+            }
+        }
+        self.sector_started_at = Some(at);
+    }
+
+    fn finish_lap(&mut self, car_idx: usize, at: f64, laps_completed: Option<i32>) -> Option<Lap> {
+        // The sim may count the lap a sample before or after the car is seen crossing
+        let lap = match (laps_completed, self.laps_completed) {
+            (Some(reported), Some(counted)) if reported > counted => reported,
+            (_, counted) => counted.unwrap_or(0) + 1,
+        };
+        self.laps_completed = Some(lap);
+
+        let sectors = std::mem::take(&mut self.timing.sectors);
+        let started = self.lap_started_at.replace(at);
+        self.sector_started_at = Some(at);
+
+        let time = Duration::from_secs_f64((at - started?).max(0.0));
+        let is_personal_best = self.timing.best.map_or(true, |best| time < best);
+        self.timing.last = Some(time);
+        if is_personal_best {
+            self.timing.best = Some(time);
+        }
+
+        Some(Lap {
+            car_idx,
+            lap,
+            time,
+            sectors,
+            completed_at: at,
+            is_personal_best,
+            is_session_best: false,
+        })
+    }
+
+    fn abandon_lap(&mut self) {
+        self.lap_started_at = None;
+        self.sector_started_at = None;
+        self.timing.current = None;
+        self.timing.sectors.clear();
+    }
+}
+
+// Times the laps of every car in the field from their lap distance, as the sim only reports
+// lap times for the player's car
+#[derive(Default)]
+pub struct LapTimer {
+    // Where each sector after the first starts, as a fraction of the lap
+    sector_starts: Vec<f32>,
+    cars: Vec<CarState>,
+    session_best: Option<Duration>,
+    last_session_time: f64,
+}
+
+impl LapTimer {
+    pub fn new() -> LapTimer {
+        LapTimer::default()
+    }
+
+    pub fn set_sectors(&mut self, split_time_info: &SplitTimeInfo) {
+        let mut sector_starts: Vec<f32> = split_time_info.sectors.iter()
+            .map(|sector| sector.start_pct)
+            .filter(|&start| start > 0.0 && start < 1.0)
+            .collect();
+        sector_starts.sort_by(|a, b| a.total_cmp(b));
+
+        if sector_starts != self.sector_starts {
+            self.sector_starts = sector_starts;
+            // Splits taken with the old sectors do not compare to the new ones
+            for car in &mut self.cars {
+                car.sector_started_at = None;
+                car.timing.sectors.clear();
+                car.timing.best_sectors.clear();
+            }
+        }
+    }
+
+    pub fn session_best(&self) -> Option<Duration> {
+        self.session_best
+    }
+
+    pub fn timing(&self, car_idx: usize) -> Option<&LapTiming> {
+        self.cars.get(car_idx).map(|car| &car.timing)
+    }
+
+    pub fn reset(&mut self) {
+        self.cars.clear();
+        self.session_best = None;
+    }
+
+    // Fills in the lap timing of the telemetry, and returns the laps completed since the previous one
+    pub fn update(&mut self, telemetry: &mut Telemetry) -> Vec<Lap> {
+        // The session time only goes backwards for a new session or when the replay is rewound
+        if telemetry.session_time < self.last_session_time {
+            self.reset();
+        }
+        self.last_session_time = telemetry.session_time;
+
+        if self.cars.len() < telemetry.lap_dist_by_car.len() {
+            self.cars.resize_with(telemetry.lap_dist_by_car.len(), CarState::default);
+        }
+
+        let mut laps = vec![];
+        for (car_idx, &dist) in telemetry.lap_dist_by_car.iter().enumerate() {
+            let lap = telemetry.car_laps.get(car_idx).copied();
+            let laps_completed = telemetry.car_laps_completed.get(car_idx).copied();
+            let completed = self.cars[car_idx].update(car_idx, &self.sector_starts, dist, telemetry.session_time, lap, laps_completed);
+
+            if let Some(mut completed) = completed {
+                completed.is_session_best = self.session_best.map_or(true, |best| completed.time < best);
+                if completed.is_session_best {
+                    self.session_best = Some(completed.time);
+                }
+                laps.push(completed);
+            }
+        }
+
+        telemetry.lap_timing = self.cars.iter().map(|car| car.timing.without_sectors()).collect();
+        laps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iracing::session_info::Sector;
+
+    fn secs(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    // One sample of a single car
+    fn sample(timer: &mut LapTimer, session_time: f64, dist: f32) -> Vec<Lap> {
+        let mut telemetry = Telemetry {
+            session_time,
+            lap_dist_by_car: vec![dist],
+            ..Telemetry::default()
+        };
+        timer.update(&mut telemetry)
+    }
+
+    fn drive(timer: &mut LapTimer, samples: &[(f64, f32)]) -> Vec<Lap> {
+        samples.iter()
+            .flat_map(|&(session_time, dist)| sample(timer, session_time, dist))
+            .collect()
+    }
+
+    #[test]
+    fn interpolates_the_line_crossing() {
+        let mut timer = LapTimer::default();
+        // Timing starts where the car is first seen crossing the line, halfway between the samples
+        assert!(drive(&mut timer, &[(0.0, 0.875), (1.0, 0.125)]).is_empty());
+        assert_eq!(timer.timing(0).unwrap().current, Some(secs(0.5)));
+
+        let laps = drive(&mut timer, &[(5.0, 0.5), (10.0, 0.9375), (11.0, 0.1875)]);
+        assert_eq!(laps.len(), 1);
+        assert_eq!(laps[0].time, secs(9.75));
+        assert_eq!(laps[0].completed_at, 10.25);
+        assert!(laps[0].is_personal_best && laps[0].is_session_best);
+        assert!(laps[0].sectors.is_empty());
+
+        let timing = timer.timing(0).unwrap();
+        assert_eq!(timing.last, Some(secs(9.75)));
+        assert_eq!(timing.best, Some(secs(9.75)));
+        assert_eq!(timing.current, Some(secs(0.75)));
+
+        // A slower lap is neither a personal nor a session best
+        let laps = drive(&mut timer, &[(15.0, 0.5), (20.0, 0.875), (21.0, 0.125)]);
+        assert_eq!(laps[0].time, secs(10.25));
+        assert!(!laps[0].is_personal_best && !laps[0].is_session_best);
+        assert_eq!(timer.timing(0).unwrap().best, Some(secs(9.75)));
+        assert_eq!(timer.session_best(), Some(secs(9.75)));
+    }
+
+    #[test]
+    fn counts_laps_from_the_sim() {
+        let mut timer = LapTimer::default();
+        let mut telemetry = Telemetry {
+            lap_dist_by_car: vec![0.875],
+            car_laps_completed: vec![2],
+            ..Telemetry::default()
+        };
+        timer.update(&mut telemetry);
+        telemetry.session_time = 1.0;
+        telemetry.lap_dist_by_car = vec![0.125];
+        // The sim has not counted the lap yet
+        timer.update(&mut telemetry);
+        assert_eq!(timer.timing(0).unwrap().lap, 4);
+
+        for (session_time, dist) in [(5.0, 0.5), (9.0, 0.875)] {
+            telemetry.session_time = session_time;
+            telemetry.lap_dist_by_car = vec![dist];
+            telemetry.car_laps_completed = vec![3];
+            timer.update(&mut telemetry);
+        }
+        telemetry.session_time = 10.0;
+        telemetry.lap_dist_by_car = vec![0.125];
+        telemetry.car_laps_completed = vec![4];
+        let laps = timer.update(&mut telemetry);
+        assert_eq!(laps[0].lap, 4);
+        assert_eq!(laps[0].time, secs(9.0));
+    }
+
+    #[test]
+    fn splits_sectors() {
+        let mut timer = LapTimer::default();
+        timer.set_sectors(&SplitTimeInfo {
+            sectors: vec![Sector { num: 0, start_pct: 0.0 }, Sector { num: 1, start_pct: 0.5 }],
+        });
+
+        drive(&mut timer, &[(0.0, 0.875), (1.0, 0.125), (2.0, 0.375), (3.0, 0.625)]);
+        assert_eq!(timer.timing(0).unwrap().sectors, vec![secs(2.0)]);
+
+        let laps = drive(&mut timer, &[(4.0, 0.875), (5.0, 0.125)]);
+        assert_eq!(laps[0].time, secs(4.0));
+        assert_eq!(laps[0].sectors, vec![secs(2.0), secs(2.0)]);
+        let timing = timer.timing(0).unwrap();
+        assert!(timing.sectors.is_empty());
+        assert_eq!(timing.best_sectors, vec![Some(secs(2.0)), Some(secs(2.0))]);
+
+        // A faster second sector only improves that sector
+        let laps = drive(&mut timer, &[(6.0, 0.375), (7.0, 0.625), (8.0, 0.875), (8.5, 0.125)]);
+        assert_eq!(laps[0].sectors, vec![secs(2.0), secs(1.75)]);
+        assert_eq!(timer.timing(0).unwrap().best_sectors, vec![Some(secs(2.0)), Some(secs(1.75))]);
+
+        // Changing the sectors drops the splits taken with the old ones
+        timer.set_sectors(&SplitTimeInfo {
+            sectors: vec![Sector { num: 0, start_pct: 0.0 }, Sector { num: 1, start_pct: 0.25 }],
+        });
+        assert!(timer.timing(0).unwrap().best_sectors.is_empty());
+    }
+
+    #[test]
+    fn leaves_the_sectors_out_of_the_telemetry() {
+        let mut timer = LapTimer::default();
+        timer.set_sectors(&SplitTimeInfo {
+            sectors: vec![Sector { num: 0, start_pct: 0.0 }, Sector { num: 1, start_pct: 0.5 }],
+        });
+        drive(&mut timer, &[(0.0, 0.875), (1.0, 0.125), (2.0, 0.375), (3.0, 0.625), (4.0, 0.875)]);
+
+        let mut telemetry = Telemetry {
+            session_time: 5.0,
+            lap_dist_by_car: vec![0.125],
+            ..Telemetry::default()
+        };
+        timer.update(&mut telemetry);
+        let timing = timer.timing(0).unwrap();
+        assert_eq!(timing.best_sectors.len(), 2);
+        assert_eq!(telemetry.lap_timing, vec![timing.without_sectors()]);
+        assert_eq!(telemetry.lap_timing[0].last, Some(secs(4.0)));
+        assert!(telemetry.lap_timing[0].best_sectors.is_empty());
+    }
+
+    #[test]
+    fn abandons_the_lap_when_reversing_over_the_line() {
+        let mut timer = LapTimer::default();
+        drive(&mut timer, &[(0.0, 0.875), (1.0, 0.125), (2.0, 0.0625)]);
+        assert!(drive(&mut timer, &[(3.0, 0.9375)]).is_empty());
+        assert_eq!(timer.timing(0).unwrap().current, None);
+
+        // Driving forward over the line again only starts a new lap
+        assert!(drive(&mut timer, &[(4.0, 0.0625)]).is_empty());
+        let laps = drive(&mut timer, &[(6.0, 0.5), (8.0, 0.9375), (9.0, 0.1875)]);
+        assert_eq!(laps.len(), 1);
+        assert_eq!(laps[0].time, secs(4.75));
+    }
+
+    #[test]
+    fn abandons_the_lap_in_the_garage() {
+        let mut timer = LapTimer::default();
+        drive(&mut timer, &[(0.0, 0.875), (1.0, 0.125), (5.0, 0.5)]);
+        assert!(drive(&mut timer, &[(6.0, -1.0)]).is_empty());
+        assert_eq!(timer.timing(0).unwrap().current, None);
+
+        // Back out of the pits, the lap that was going on is not timed
+        assert!(drive(&mut timer, &[(60.0, 0.625), (63.0, 0.875), (64.0, 0.125)]).is_empty());
+        assert!(timer.timing(0).unwrap().last.is_none());
+    }
+
+    #[test]
+    fn times_laps_through_the_pit_stall() {
+        let mut timer = LapTimer::default();
+        drive(&mut timer, &[(0.0, 0.875), (1.0, 0.125)]);
+        // Stopped in the pit stall for half a minute, the in lap still counts
+        let laps = drive(&mut timer, &[(5.0, 0.5), (35.0, 0.5), (40.0, 0.9375), (41.0, 0.1875)]);
+        assert_eq!(laps.len(), 1);
+        assert_eq!(laps[0].time, secs(39.75));
+    }
+
+    #[test]
+    fn resets_when_the_session_time_goes_back() {
+        let mut timer = LapTimer::default();
+        drive(&mut timer, &[(100.0, 0.875), (101.0, 0.125), (105.0, 0.5), (110.0, 0.9375), (111.0, 0.1875)]);
+        assert!(timer.session_best().is_some());
+
+        assert!(drive(&mut timer, &[(0.0, 0.5)]).is_empty());
+        assert_eq!(timer.session_best(), None);
+        assert_eq!(timer.timing(0).unwrap().last, None);
+    }
+}
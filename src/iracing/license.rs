@@ -0,0 +1,165 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+// Lowest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LicenseClass {
+    Rookie,
+    D,
+    C,
+    B,
+    A,
+    Pro,
+    ProWc,
+}
+
+impl LicenseClass {
+    fn parse(letter: &str) -> Option<LicenseClass> {
+        match letter {
+            "R" => Some(LicenseClass::Rookie),
+            "D" => Some(LicenseClass::D),
+            "C" => Some(LicenseClass::C),
+            "B" => Some(LicenseClass::B),
+            "A" => Some(LicenseClass::A),
+            "P" => Some(LicenseClass::Pro),
+            "WC" => Some(LicenseClass::ProWc),
+            _ => None,
+        }
+    }
+
+    pub fn letter(&self) -> &'static str {
+        match self {
+            LicenseClass::Rookie => "R",
+            LicenseClass::D => "D",
+            LicenseClass::C => "C",
+            LicenseClass::B => "B",
+            LicenseClass::A => "A",
+            LicenseClass::Pro => "P",
+            LicenseClass::ProWc => "WC",
+        }
+    }
+
+    // The badge colors of the sim, 0xRRGGBB, for when the roster has none
+    pub fn color(&self) -> u32 {
+        match self {
+            LicenseClass::Rookie => 0xfc0706,
+            LicenseClass::D => 0xff8c00,
+            LicenseClass::C => 0xffcc00,
+            LicenseClass::B => 0x00c702,
+            LicenseClass::A => 0x0153db,
+            LicenseClass::Pro | LicenseClass::ProWc => 0x000000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct License {
+    pub class: LicenseClass,
+    pub safety_rating: f32,
+    // 0xRRGGBB
+    pub color: u32,
+}
+
+impl License {
+    // From a `LicString` like `A 4.99`, and the `LicColor` of the roster if it has one
+    pub fn parse(license_string: &str, color: Option<u32>) -> Result<License, String> {
+        let mut parts = license_string.split_whitespace();
+        let class = parts.next().and_then(LicenseClass::parse);
+        let safety_rating = parts.next().and_then(|safety_rating| safety_rating.parse().ok());
+        match (class, safety_rating, parts.next()) {
+            (Some(class), Some(safety_rating), None) => Ok(License {
+                class,
+                safety_rating,
+                color: color.unwrap_or_else(|| class.color()),
+            }),
+            _ => Err(format!["expected a license like `A 4.99`, got {:?}", license_string]),
+        }
+    }
+
+    // Text on the badge, dark on the light colors and light on the dark ones
+    pub fn text_color(&self) -> u32 {
+        let channel = |shift: u32| ((self.color >> shift) & 0xff) as f32;
+        let luminance = 0.299 * channel(16) + 0.587 * channel(8) + 0.114 * channel(0);
+        if luminance > 150.0 { 0x000000 } else { 0xffffff }
+    }
+}
+
+// The color is only how the license is shown, so it is left out of comparisons
+impl PartialEq for License {
+    fn eq(&self, other: &License) -> bool {
+        self.class == other.class && self.safety_rating == other.safety_rating
+    }
+}
+
+// By class, and by safety rating within the class
+impl PartialOrd for License {
+    fn partial_cmp(&self, other: &License) -> Option<Ordering> {
+        match self.class.cmp(&other.class) {
+            Ordering::Equal => self.safety_rating.partial_cmp(&other.safety_rating),
+            ordering => Some(ordering),
+        }
+    }
+}
+
+impl fmt::Display for License {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:.2}", self.class.letter(), self.safety_rating)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn license(license_string: &str) -> License {
+        License::parse(license_string, None).unwrap()
+    }
+
+    #[test]
+    fn parses_license_strings() {
+        let parsed = license("WC 3.21");
+        assert_eq!(parsed.class, LicenseClass::ProWc);
+        assert_eq!(parsed.safety_rating, 3.21);
+        assert_eq!(parsed.color, 0x000000);
+        assert_eq!(License::parse("A 4.99", Some(0x123456)).unwrap().color, 0x123456);
+        assert_eq!(parsed.to_string(), "WC 3.21");
+
+        assert!(License::parse("A", None).is_err());
+        assert!(License::parse("X 2.50", None).is_err());
+        assert!(License::parse("A 2.50 extra", None).is_err());
+    }
+
+    #[test]
+    fn orders_by_class_first() {
+        assert!(license("R 4.99") < license("D 1.00"));
+        assert!(license("C 2.50") < license("B 1.50"));
+        assert!(license("A 4.99") < license("P 1.00"));
+        assert!(license("P 4.99") < license("WC 1.00"));
+    }
+
+    #[test]
+    fn orders_by_safety_rating_within_a_class() {
+        assert!(license("B 2.49") < license("B 2.50"));
+        assert!(license("B 3.99") > license("B 3.10"));
+        assert_eq!(license("B 3.10").partial_cmp(&license("B 3.10")), Some(Ordering::Equal));
+    }
+
+    #[test]
+    fn ignores_the_color_when_comparing() {
+        let custom = License::parse("A 4.99", Some(0x123456)).unwrap();
+        assert_eq!(custom, license("A 4.99"));
+        assert_eq!(custom.partial_cmp(&license("A 4.99")), Some(Ordering::Equal));
+        assert_ne!(custom, license("A 4.98"));
+    }
+
+    #[test]
+    fn picks_a_readable_text_color() {
+        // Dark text on the light badges
+        assert_eq!(license("C 2.50").text_color(), 0x000000);
+        assert_eq!(License::parse("A 2.50", Some(0xffffff)).unwrap().text_color(), 0x000000);
+        // Light text on the dark ones
+        assert_eq!(license("A 2.50").text_color(), 0xffffff);
+        assert_eq!(license("R 2.50").text_color(), 0xffffff);
+        assert_eq!(license("P 2.50").text_color(), 0xffffff);
+    }
+}
@@ -1,29 +1,138 @@
-use yaml_rust::{YamlLoader, Yaml};
+use yaml_rust::YamlLoader;
 
 mod data_collector;
+mod ibt_reader;
+pub mod snapshot;
+pub mod flags;
+pub mod broadcast;
+pub mod replay;
+pub mod session_info;
+pub mod session_diff;
+pub mod car_setup;
+pub mod lap_timer;
+pub mod delta;
+pub mod gaps;
+pub mod tyres;
+pub mod irating;
+pub mod license;
+pub mod sof;
+pub mod ibt_writer;
+pub mod telemetry_source;
 pub mod data_producer;
 
+use std::cell::RefCell;
 use std::time::Instant;
 use std::convert::TryFrom;
 
-#[derive(Debug, Clone)]
-pub struct Driver {
-    pub name: String,
-    pub irating: u32,
-    pub safety_rating: f32,
-}
+use flags::{ Flags, EngineWarnings, PitSvFlags, PaceFlags, PitServiceStatus, TrackLocation };
+use session_info::{ WeekendInfo, Session, SplitTimeInfo, CameraInfo, RadioInfo, FieldError, Section };
+use session_info::CarClass;
+use license::License;
+use car_setup::CarSetup;
+use lap_timer::{ Lap, LapTiming };
+use delta::Deltas;
+use gaps::CarGaps;
+use tyres::TyreState;
+
+pub use session_info::Driver;
+pub use session_diff::SessionEvent;
 
 #[derive(Debug, Clone)]
 pub struct Telemetry {
     pub timestamp: Instant,
+    // Seconds since the start of the session, also while in a replay
+    pub session_time: f64,
+    pub session_num: i32,
     pub throttle: f32,
     pub brake: f32,
     pub gear: u16,
     pub velocity: f32,
-    pub deltas: Vec<f32>,
+    // Liters
+    pub fuel_level: f32,
+    // Kilograms per hour
+    pub fuel_use_per_hour: f32,
+    pub session_flags: Flags,
+    pub engine_warnings: EngineWarnings,
+    pub deltas: Deltas,
     pub lap_dist_by_car: Vec<f32>,
     pub car_positions: Vec<i32>,
+    // Within the class of each car
+    pub car_class_positions: Vec<i32>,
+    pub car_laps: Vec<i32>,
+    pub car_laps_completed: Vec<i32>,
+    // Indexed by car, filled in by the `LapTimer`. The sectors are left out, see `LapTimer::timing` for those.
+    pub lap_timing: Vec<LapTiming>,
+    // The sim's estimate of how long the class of each car takes from the line to where it is
+    pub car_est_times: Vec<f32>,
+    pub car_track_locations: Vec<TrackLocation>,
+    pub car_on_pit_road: Vec<bool>,
+    // The flags shown to each car, e.g. a blue flag or a black flag for a single driver
+    pub car_session_flags: Vec<Flags>,
+    pub car_pace_flags: Vec<PaceFlags>,
+    // Indexed by car, filled in by the `GapCalculator`
+    pub gaps: Vec<CarGaps>,
     pub is_on_track: bool,
+
+    pub track_location: TrackLocation,
+    pub on_pit_road: bool,
+    // What the pit crew will do at the next stop, as currently selected in the pit menu
+    pub pit_service_flags: PitSvFlags,
+    pub pit_service_fuel: f32,
+    pub pit_service_status: PitServiceStatus,
+
+    pub tyres: TyreState,
+    pub incidents: IncidentCounts,
+}
+
+// Nothing known yet, like before the first telemetry line of a connection
+impl Default for Telemetry {
+    fn default() -> Telemetry {
+        Telemetry {
+            timestamp: Instant::now(),
+            session_time: 0.0,
+            session_num: 0,
+            throttle: 0.0,
+            brake: 0.0,
+            gear: 0,
+            velocity: 0.0,
+            fuel_level: 0.0,
+            fuel_use_per_hour: 0.0,
+            session_flags: Flags::default(),
+            engine_warnings: EngineWarnings::default(),
+            deltas: Deltas::default(),
+            lap_dist_by_car: vec![],
+            car_positions: vec![],
+            car_class_positions: vec![],
+            car_laps: vec![],
+            car_laps_completed: vec![],
+            lap_timing: vec![],
+            car_est_times: vec![],
+            car_track_locations: vec![],
+            car_on_pit_road: vec![],
+            car_session_flags: vec![],
+            car_pace_flags: vec![],
+            gaps: vec![],
+            is_on_track: false,
+            track_location: TrackLocation::NotInWorld,
+            on_pit_road: false,
+            pit_service_flags: PitSvFlags::default(),
+            pit_service_fuel: 0.0,
+            pit_service_status: PitServiceStatus::None,
+            tyres: TyreState::default(),
+            incidents: IncidentCounts::default(),
+        }
+    }
+}
+
+// The incident counts of the player's car in the current session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IncidentCounts {
+    // Picked up by the player
+    pub my: i32,
+    // By all drivers of the car, the same as `my` outside of team events
+    pub team: i32,
+    // By whoever is driving the car now
+    pub driver: i32,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -37,67 +146,135 @@ pub struct DriverInfo {
     pub car_idx: usize,
     pub username: String,
     pub irating: i32,
-    pub license_string: String,
+    pub license: Option<License>,
+    // Liters that fit in the tank, as limited by the series
+    pub fuel_capacity: Option<f32>,
+    pub fuel_kg_per_liter: f32,
 }
 
 #[derive(Debug, Clone)]
 pub struct SessionInfo {
-    // pub name: String,
-    // pub drivers: Vec<Driver>,
-
     pub track: TrackSpec,
     pub driver: DriverInfo,
+
+    pub weekend: WeekendInfo,
+    pub sessions: Vec<Session>,
+    // Not written by older versions of the sim
+    pub current_session_num: Option<i32>,
+    pub drivers: Vec<Driver>,
+    // Fastest first, by the sim's estimate of their lap time
+    pub classes: Vec<CarClass>,
+    pub split_time_info: SplitTimeInfo,
+    pub camera_info: CameraInfo,
+    pub radio_info: RadioInfo,
+    // Only available for the player's own car, and not while spectating
+    pub car_setup: Option<CarSetup>,
+
+    // Fields that were there but could not be parsed, and were left at their defaults
+    pub errors: Vec<FieldError>,
+}
+
+impl SessionInfo {
+    pub fn current_session(&self) -> Option<&Session> {
+        let num = self.current_session_num?;
+        self.sessions.iter().find(|session| session.num == num)
+    }
+
+    pub fn driver(&self, car_idx: usize) -> Option<&Driver> {
+        self.drivers.iter().find(|driver| driver.car_idx == car_idx)
+    }
+
+    // None for the pace car and spectators
+    pub fn class_of(&self, car_idx: usize) -> Option<&CarClass> {
+        let driver = self.driver(car_idx)?;
+        self.classes.iter().find(|class| class.id == driver.car_class_id && !driver.is_pace_car && !driver.is_spectator)
+    }
+
+    pub fn is_multiclass(&self) -> bool {
+        self.classes.len() > 1
+    }
 }
 
 impl TryFrom<&String> for SessionInfo {
     type Error = String;
 
     fn try_from(str: &String) -> Result<Self, Self::Error> {
-        if str.len() == 0 {
+        if str.trim().is_empty() {
             return Err(String::from("Empty Session Info"));
         }
 
-        let parsed = &YamlLoader::load_from_str(str)
-                .map_err(|err| format!("Failed to parse yaml: {:?}", err))?[0];
-
-        let track_name = parsed["WeekendInfo"]["TrackName"].as_str()
-                .ok_or("TrackName not found")?;
-        let track_configuration = match &parsed["WeekendInfo"]["TrackConfigName"] {
-            Yaml::String(track_config_name) => Ok(track_config_name.clone()),
-            Yaml::Null => Ok("Grand Prix".to_string()),
-            _ => Err("Unspecified track configuration!")
-        }?;
-
-        let driver_idx = parsed["DriverInfo"]["DriverCarIdx"].as_i64()
-                .ok_or("Failed to find driver index")?;
-        let driver = match &parsed["DriverInfo"]["Drivers"] {
-            Yaml::Array(drivers) => {
-                drivers.iter()
-                    .find(|driver| driver["CarIdx"].as_i64().unwrap() == driver_idx)
-                    .ok_or("Did not find current driver in drivers list")
-            },
-            _ => Err("Did not find list of drivers"),
-        }?;
+        let documents = YamlLoader::load_from_str(&session_info::sanitize(str))
+                .map_err(|err| format!("Failed to parse yaml: {:?}", err))?;
+        let parsed = documents.get(0).ok_or("Empty Session Info")?;
+
+        let errors = RefCell::new(vec![]);
+        let root = Section::root(parsed, &errors);
+
+        let weekend = WeekendInfo::parse(&root.section("WeekendInfo"));
+        if weekend.track_name.is_empty() {
+            return Err(String::from("TrackName not found"));
+        }
+        let track_configuration = match weekend.track_config_name.as_str() {
+            "" => "Grand Prix".to_string(),
+            configuration => configuration.to_string(),
+        };
+
+        let driver_info = root.section("DriverInfo");
+        let drivers = driver_info.list("Drivers", Driver::parse);
+        let driver_idx = driver_info.quantity("DriverCarIdx")
+                .ok_or("Failed to find driver index")? as usize;
+        let driver = drivers.iter()
+                .find(|driver| driver.car_idx == driver_idx)
+                .ok_or("Did not find current driver in drivers list")?;
 
         let driver = DriverInfo {
-            car_idx: driver["CarIdx"].as_i64().unwrap() as usize,
-            username: driver["UserName"].as_str().unwrap().to_string(),
-            irating: driver["IRating"].as_i64().unwrap() as i32,
-            license_string: driver["LicString"].as_str().unwrap().to_string(),
+            car_idx: driver.car_idx,
+            username: driver.name.clone(),
+            irating: driver.irating,
+            license: driver.license,
+            fuel_capacity: driver_info.quantity("DriverCarFuelMaxLtr")
+                .map(|liters| (liters * driver_info.limit("DriverCarMaxFuelPct").unwrap_or(1.0)) as f32),
+            fuel_kg_per_liter: driver_info.float("DriverCarFuelKgPerLtr"),
         };
 
+        let sessions = root.section("SessionInfo");
         Ok(SessionInfo {
             track: TrackSpec {
-                name: track_name.to_string(),
-                configuration: track_configuration.to_string(),
+                name: weekend.track_name.clone(),
+                configuration: track_configuration,
             },
             driver,
+
+            weekend,
+            sessions: sessions.list("Sessions", Session::parse),
+            current_session_num: sessions.quantity("CurrentSessionNum").map(|num| num as i32),
+            classes: CarClass::from_drivers(&drivers),
+            drivers,
+            split_time_info: SplitTimeInfo::parse(&root.section("SplitTimeInfo")),
+            camera_info: CameraInfo::parse(&root.section("CameraInfo")),
+            radio_info: RadioInfo::parse(&root.section("RadioInfo")),
+            car_setup: CarSetup::parse(&root.section("CarSetup")),
+            // Last, so it includes the errors of all the fields above
+            errors: errors.take(),
         })
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    Connected { tick_rate: i32 },
+    Disconnected,
+    // The sim uses another version of the irsdk memory layout than the one we were built against
+    VersionMismatch { expected: i32, found: i32 },
+}
+
 #[derive(Debug, Clone)]
 pub enum Update {
     Session(SessionInfo),
     Telemetry(Telemetry),
+    Connection(ConnectionStatus),
+    // What changed compared to the previous session info, sent after the `Session` update it came with
+    SessionEvent(SessionEvent),
+    // Sent right after the telemetry in which the car crossed the line
+    LapCompleted(Lap),
 }
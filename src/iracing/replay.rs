@@ -0,0 +1,187 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::iracing::broadcast::{ CommandSink, SimCommand, ReplayPosition, ReplaySearch };
+
+// Drives the replay tape of the sim, e.g. for reviewing an incident:
+//
+//   replay.jump_to(2, Duration::from_secs(1234))?;
+//   replay.step_frames(-60)?;
+//   replay.slow_motion(4)?;
+//
+// Replay commands only work while out of the car.
+pub struct ReplayController {
+    sink: Arc<dyn CommandSink>,
+}
+
+impl ReplayController {
+    pub fn new(sink: Arc<dyn CommandSink>) -> ReplayController {
+        ReplayController { sink }
+    }
+
+    // Jumps to a point in time of the given session, as numbered in the session info
+    pub fn jump_to(&self, session_num: i32, session_time: Duration) -> Result<(), String> {
+        let session_num = i16::try_from(session_num)
+            .map_err(|_| format!["Invalid session number {}", session_num])?;
+        let session_time_ms = i32::try_from(session_time.as_millis())
+            .map_err(|_| format!["Session time {:?} is too far into the session", session_time])?;
+
+        self.sink.send(SimCommand::ReplaySearchSessionTime { session_num, session_time_ms })
+    }
+
+    // Starts playing `lead_in` before the given moment, e.g. to review an incident or cut a clip around it
+    pub fn review(&self, session_num: i32, session_time: Duration, lead_in: Duration) -> Result<(), String> {
+        self.jump_to(session_num, session_time.saturating_sub(lead_in))?;
+        self.play()
+    }
+
+    // Moves relative to the current frame, backwards for negative counts. The sim runs at 60 frames per second.
+    pub fn step_frames(&self, frames: i32) -> Result<(), String> {
+        self.sink.send(SimCommand::ReplaySetPlayPosition { position: ReplayPosition::Current, frame: frames })
+    }
+
+    pub fn goto_frame(&self, frame: i32) -> Result<(), String> {
+        self.sink.send(SimCommand::ReplaySetPlayPosition { position: ReplayPosition::Begin, frame })
+    }
+
+    pub fn search(&self, search: ReplaySearch) -> Result<(), String> {
+        self.sink.send(SimCommand::ReplaySearch(search))
+    }
+
+    pub fn next_incident(&self) -> Result<(), String> {
+        self.search(ReplaySearch::NextIncident)
+    }
+
+    pub fn prev_incident(&self) -> Result<(), String> {
+        self.search(ReplaySearch::PrevIncident)
+    }
+
+    pub fn next_lap(&self) -> Result<(), String> {
+        self.search(ReplaySearch::NextLap)
+    }
+
+    pub fn prev_lap(&self) -> Result<(), String> {
+        self.search(ReplaySearch::PrevLap)
+    }
+
+    pub fn next_frame(&self) -> Result<(), String> {
+        self.search(ReplaySearch::NextFrame)
+    }
+
+    pub fn prev_frame(&self) -> Result<(), String> {
+        self.search(ReplaySearch::PrevFrame)
+    }
+
+    pub fn to_live(&self) -> Result<(), String> {
+        self.search(ReplaySearch::ToEnd)
+    }
+
+    pub fn play(&self) -> Result<(), String> {
+        self.set_speed(1)
+    }
+
+    pub fn pause(&self) -> Result<(), String> {
+        self.set_speed(0)
+    }
+
+    // Plays at a multiple of real time, negative speeds rewind
+    pub fn set_speed(&self, speed: i16) -> Result<(), String> {
+        self.sink.send(SimCommand::ReplaySetPlaySpeed { speed, slow_motion: false })
+    }
+
+    // Plays at 1 / divisor of real time
+    pub fn slow_motion(&self, divisor: i16) -> Result<(), String> {
+        if divisor == 0 {
+            return Err("Slow motion divisor can not be 0".to_string());
+        }
+        self.sink.send(SimCommand::ReplaySetPlaySpeed { speed: divisor, slow_motion: true })
+    }
+
+    pub fn erase_tape(&self) -> Result<(), String> {
+        self.sink.send(SimCommand::ReplayEraseTape)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iracing::broadcast::RecordingCommandSink;
+
+    fn controller() -> (Arc<RecordingCommandSink>, ReplayController) {
+        let sink = Arc::new(RecordingCommandSink::new());
+        (sink.clone(), ReplayController::new(sink))
+    }
+
+    #[test]
+    fn jumps_to_a_session_time() {
+        let (sink, replay) = controller();
+        replay.jump_to(2, Duration::from_millis(1_234_567)).unwrap();
+        assert_eq!(sink.commands(), [
+            SimCommand::ReplaySearchSessionTime { session_num: 2, session_time_ms: 1_234_567 },
+        ]);
+    }
+
+    #[test]
+    fn rejects_jumps_out_of_range() {
+        let (sink, replay) = controller();
+        assert!(replay.jump_to(i16::MAX as i32 + 1, Duration::from_secs(1)).is_err());
+        assert!(replay.jump_to(-1 - i16::MAX as i32 - 1, Duration::from_secs(1)).is_err());
+        assert!(replay.jump_to(0, Duration::from_millis(i32::MAX as u64 + 1)).is_err());
+        assert!(sink.commands().is_empty());
+
+        replay.jump_to(0, Duration::from_millis(i32::MAX as u64)).unwrap();
+        assert_eq!(sink.commands(), [
+            SimCommand::ReplaySearchSessionTime { session_num: 0, session_time_ms: i32::MAX },
+        ]);
+    }
+
+    #[test]
+    fn reviews_from_before_the_moment() {
+        let (sink, replay) = controller();
+        replay.review(1, Duration::from_secs(100), Duration::from_secs(5)).unwrap();
+        assert_eq!(sink.commands(), [
+            SimCommand::ReplaySearchSessionTime { session_num: 1, session_time_ms: 95_000 },
+            SimCommand::ReplaySetPlaySpeed { speed: 1, slow_motion: false },
+        ]);
+    }
+
+    #[test]
+    fn reviews_from_the_start_of_the_session_at_most() {
+        let (sink, replay) = controller();
+        replay.review(1, Duration::from_secs(3), Duration::from_secs(5)).unwrap();
+        assert_eq!(sink.commands(), [
+            SimCommand::ReplaySearchSessionTime { session_num: 1, session_time_ms: 0 },
+            SimCommand::ReplaySetPlaySpeed { speed: 1, slow_motion: false },
+        ]);
+    }
+
+    #[test]
+    fn steps_back_from_the_current_frame() {
+        let (sink, replay) = controller();
+        replay.step_frames(-60).unwrap();
+        assert_eq!(sink.commands(), [
+            SimCommand::ReplaySetPlayPosition { position: ReplayPosition::Current, frame: -60 },
+        ]);
+    }
+
+    #[test]
+    fn plays_in_slow_motion() {
+        let (sink, replay) = controller();
+        replay.slow_motion(4).unwrap();
+        assert!(replay.slow_motion(0).is_err());
+        assert_eq!(sink.commands(), [
+            SimCommand::ReplaySetPlaySpeed { speed: 4, slow_motion: true },
+        ]);
+    }
+
+    #[test]
+    fn searches_incidents_and_laps() {
+        let (sink, replay) = controller();
+        replay.next_incident().unwrap();
+        replay.next_lap().unwrap();
+        assert_eq!(sink.commands(), [
+            SimCommand::ReplaySearch(ReplaySearch::NextIncident),
+            SimCommand::ReplaySearch(ReplaySearch::NextLap),
+        ]);
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashSet;
+
+use crate::iracing::SessionInfo;
+use crate::iracing::session_info::{ Driver, Session, ResultPosition };
+
+#[derive(Debug, Clone)]
+pub enum SessionEvent {
+    DriverJoined(Driver),
+    DriverLeft(Driver),
+    // Another driver of the same team took over the car
+    DriverSwap { car_idx: usize, from: Driver, to: Driver },
+    CarClassAdded { class_id: i64, short_name: String },
+    SessionChanged { from: Option<Session>, to: Session },
+    ResultsUpdated { session_num: i32, results: Vec<ResultPosition> },
+}
+
+// The sim sends the full session info whenever anything in it changes. This keeps the
+// previous one around to tell what actually changed.
+#[derive(Default)]
+pub struct SessionDiff {
+    previous: Option<SessionInfo>,
+}
+
+impl SessionDiff {
+    pub fn new() -> SessionDiff {
+        SessionDiff::default()
+    }
+
+    // The first session info only sets the baseline, everything in it is reported by `Update::Session`
+    pub fn update(&mut self, session_info: &SessionInfo) -> Vec<SessionEvent> {
+        let events = match &self.previous {
+            Some(previous) => diff(previous, session_info),
+            None => vec![],
+        };
+        self.previous = Some(session_info.clone());
+        events
+    }
+}
+
+pub fn diff(previous: &SessionInfo, current: &SessionInfo) -> Vec<SessionEvent> {
+    let mut events = vec![];
+
+    if let Some(session) = current.current_session() {
+        let previous_session = previous.current_session();
+        let changed = match previous_session {
+            Some(previous_session) => previous_session.num != session.num || previous_session.session_type != session.session_type,
+            None => true,
+        };
+        if changed {
+            events.push(SessionEvent::SessionChanged { from: previous_session.cloned(), to: session.clone() });
+        }
+    }
+
+    for driver in &previous.drivers {
+        match current.driver(driver.car_idx) {
+            None => events.push(SessionEvent::DriverLeft(driver.clone())),
+            Some(new_driver) if new_driver.user_id != driver.user_id => {
+                if driver.team_id != 0 && new_driver.team_id == driver.team_id {
+                    events.push(SessionEvent::DriverSwap {
+                        car_idx: driver.car_idx,
+                        from: driver.clone(),
+                        to: new_driver.clone(),
+                    });
+                } else {
+                    // The car slot was handed to someone else entirely
+                    events.push(SessionEvent::DriverLeft(driver.clone()));
+                    events.push(SessionEvent::DriverJoined(new_driver.clone()));
+                }
+            },
+            Some(_) => (),
+        }
+    }
+    for driver in &current.drivers {
+        if previous.driver(driver.car_idx).is_none() {
+            events.push(SessionEvent::DriverJoined(driver.clone()));
+        }
+    }
+
+    let mut known_classes: HashSet<i64> = previous.drivers.iter()
+        .map(|driver| driver.car_class_id)
+        .collect();
+    for driver in current.drivers.iter().filter(|driver| !driver.is_pace_car) {
+        if known_classes.insert(driver.car_class_id) {
+            events.push(SessionEvent::CarClassAdded {
+                class_id: driver.car_class_id,
+                short_name: driver.car_class_short_name.clone(),
+            });
+        }
+    }
+
+    for session in &current.sessions {
+        let previous_results = previous.sessions.iter()
+            .find(|previous_session| previous_session.num == session.num)
+            .map(|previous_session| &previous_session.results);
+        let updated = match previous_results {
+            Some(previous_results) => *previous_results != session.results,
+            None => !session.results.is_empty(),
+        };
+        if updated {
+            events.push(SessionEvent::ResultsUpdated { session_num: session.num, results: session.results.clone() });
+        }
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    const DAYTONA_MULTICLASS_RACE: &str = include_str!("../../tests/fixtures/session_info/daytona_multiclass_race.yaml");
+
+    fn session_info() -> SessionInfo {
+        SessionInfo::try_from(&DAYTONA_MULTICLASS_RACE.to_string()).unwrap()
+    }
+
+    // The events of going from `previous` to `current`
+    fn diff_events(previous: &SessionInfo, current: &SessionInfo) -> Vec<SessionEvent> {
+        let mut session_diff = SessionDiff::new();
+        assert!(session_diff.update(previous).is_empty());
+        session_diff.update(current)
+    }
+
+    #[test]
+    fn nothing_changed() {
+        assert!(diff_events(&session_info(), &session_info()).is_empty());
+    }
+
+    #[test]
+    fn driver_joined_and_left() {
+        let previous = session_info();
+        let mut current = previous.clone();
+        let left = current.drivers.remove(4);
+        let mut joined = left.clone();
+        joined.car_idx = 5;
+        joined.user_id = 777;
+        current.drivers.push(joined);
+
+        let events = diff_events(&previous, &current);
+        assert_eq!(events.len(), 2, "{:?}", events);
+        assert!(matches!(&events[0], SessionEvent::DriverLeft(driver) if driver.car_idx == 4));
+        assert!(matches!(&events[1], SessionEvent::DriverJoined(driver) if driver.car_idx == 5 && driver.user_id == 777));
+    }
+
+    #[test]
+    fn driver_swap_within_team() {
+        let mut previous = session_info();
+        previous.drivers[2].team_id = 42;
+        let mut current = previous.clone();
+        current.drivers[2].user_id = 999;
+        current.drivers[2].name = "Second Driver".to_string();
+
+        let events = diff_events(&previous, &current);
+        assert_eq!(events.len(), 1, "{:?}", events);
+        match &events[0] {
+            SessionEvent::DriverSwap { car_idx, from, to } => {
+                assert_eq!(*car_idx, 2);
+                assert_eq!(from.name, "#2 Jamie O'Brien");
+                assert_eq!(to.name, "Second Driver");
+            },
+            other => panic!("Expected a driver swap, got {:?}", other),
+        }
+
+        // Without a team the car was handed to someone else
+        let mut previous = session_info();
+        previous.drivers[2].team_id = 0;
+        let mut current = previous.clone();
+        current.drivers[2].user_id = 999;
+        let events = diff_events(&previous, &current);
+        assert!(matches!(&events[..], [SessionEvent::DriverLeft(_), SessionEvent::DriverJoined(_)]), "{:?}", events);
+    }
+
+    #[test]
+    fn car_class_added() {
+        let previous = session_info();
+        let mut current = previous.clone();
+        let mut driver = current.drivers[4].clone();
+        driver.car_idx = 5;
+        driver.car_class_id = 4074;
+        driver.car_class_short_name = "LMP2".to_string();
+        current.drivers.push(driver);
+
+        let events = diff_events(&previous, &current);
+        assert_eq!(events.len(), 2, "{:?}", events);
+        assert!(matches!(&events[0], SessionEvent::DriverJoined(driver) if driver.car_idx == 5));
+        assert!(matches!(&events[1], SessionEvent::CarClassAdded { class_id: 4074, short_name } if short_name == "LMP2"));
+    }
+
+    #[test]
+    fn session_changed() {
+        let mut previous = session_info();
+        previous.current_session_num = Some(1);
+        let current = session_info();
+
+        let events = diff_events(&previous, &current);
+        assert_eq!(events.len(), 1, "{:?}", events);
+        match &events[0] {
+            SessionEvent::SessionChanged { from, to } => {
+                assert_eq!(from.as_ref().unwrap().session_type, "Lone Qualify");
+                assert_eq!(to.session_type, "Race");
+            },
+            other => panic!("Expected a session change, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn results_updated() {
+        let previous = session_info();
+        let mut current = previous.clone();
+        current.sessions[2].results[0].laps_complete += 1;
+
+        let events = diff_events(&previous, &current);
+        assert!(matches!(&events[..], [SessionEvent::ResultsUpdated { session_num: 2, .. }]), "{:?}", events);
+    }
+}
@@ -0,0 +1,787 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::time::Duration;
+
+use yaml_rust::Yaml;
+
+use crate::iracing::license::License;
+
+// Typed views of the sections of the session info YAML. The sim writes most numbers with
+// their unit attached, e.g. `TrackLength: 5.79 km`, so values are parsed leniently and
+// fall back to defaults when a field is missing or broken.
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Weather {
+    pub kind: String,
+    pub skies: String,
+    pub surface_temp_c: f32,
+    pub air_temp_c: f32,
+    pub air_pressure_hg: f32,
+    pub wind_velocity_ms: f32,
+    pub wind_direction_rad: f32,
+    pub relative_humidity_pct: f32,
+    pub fog_level_pct: f32,
+    pub precipitation_pct: f32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WeekendInfo {
+    pub track_name: String,
+    pub track_id: i64,
+    pub track_display_name: String,
+    pub track_config_name: String,
+    pub track_length_km: f32,
+    pub track_city: String,
+    pub track_country: String,
+    pub track_num_turns: i32,
+    pub track_pit_speed_limit_kph: f32,
+    pub track_type: String,
+    pub weather: Weather,
+
+    pub series_id: i64,
+    pub season_id: i64,
+    pub session_id: i64,
+    pub sub_session_id: i64,
+    pub league_id: i64,
+    pub official: bool,
+    pub race_week: i32,
+    pub event_type: String,
+    pub category: String,
+    pub team_racing: bool,
+
+    // Strength of field is computed per class, over the starters
+    pub num_car_classes: i32,
+    pub num_car_types: i32,
+    pub num_starters: i32,
+    // None when incidents are unlimited
+    pub incident_limit: Option<i32>,
+}
+
+impl WeekendInfo {
+    pub fn parse(yaml: &Section) -> WeekendInfo {
+        let options = yaml.section("WeekendOptions");
+
+        WeekendInfo {
+            track_name: yaml.string("TrackName"),
+            track_id: yaml.int("TrackID"),
+            track_display_name: yaml.string("TrackDisplayName"),
+            track_config_name: yaml.string("TrackConfigName"),
+            track_length_km: yaml.float("TrackLength"),
+            track_city: yaml.string("TrackCity"),
+            track_country: yaml.string("TrackCountry"),
+            track_num_turns: yaml.int("TrackNumTurns") as i32,
+            track_pit_speed_limit_kph: yaml.float("TrackPitSpeedLimit"),
+            track_type: yaml.string("TrackType"),
+            weather: Weather {
+                kind: yaml.string("TrackWeatherType"),
+                skies: yaml.string("TrackSkies"),
+                surface_temp_c: yaml.float("TrackSurfaceTemp"),
+                air_temp_c: yaml.float("TrackAirTemp"),
+                air_pressure_hg: yaml.float("TrackAirPressure"),
+                wind_velocity_ms: yaml.float("TrackWindVel"),
+                wind_direction_rad: yaml.float("TrackWindDir"),
+                relative_humidity_pct: yaml.float("TrackRelativeHumidity"),
+                fog_level_pct: yaml.float("TrackFogLevel"),
+                precipitation_pct: yaml.float("TrackPrecipitation"),
+            },
+
+            series_id: yaml.int("SeriesID"),
+            season_id: yaml.int("SeasonID"),
+            session_id: yaml.int("SessionID"),
+            sub_session_id: yaml.int("SubSessionID"),
+            league_id: yaml.int("LeagueID"),
+            official: yaml.flag("Official"),
+            race_week: yaml.int("RaceWeek") as i32,
+            event_type: yaml.string("EventType"),
+            category: yaml.string("Category"),
+            team_racing: yaml.flag("TeamRacing"),
+
+            num_car_classes: yaml.int("NumCarClasses") as i32,
+            num_car_types: yaml.int("NumCarTypes") as i32,
+            num_starters: options.int("NumStarters") as i32,
+            incident_limit: options.limit("IncidentLimit").map(|limit| limit as i32),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResultPosition {
+    pub position: i32,
+    pub class_position: i32,
+    pub car_idx: usize,
+    pub lap: i32,
+    pub time: f32,
+    pub fastest_lap: i32,
+    pub fastest_time: f32,
+    pub last_time: f32,
+    pub laps_led: i32,
+    pub laps_complete: i32,
+    pub incidents: i32,
+    pub reason_out: String,
+}
+
+impl ResultPosition {
+    pub fn parse(yaml: &Section) -> ResultPosition {
+        ResultPosition {
+            position: yaml.int("Position") as i32,
+            class_position: yaml.int("ClassPosition") as i32,
+            car_idx: yaml.int("CarIdx") as usize,
+            lap: yaml.int("Lap") as i32,
+            time: yaml.float("Time"),
+            fastest_lap: yaml.int("FastestLap") as i32,
+            fastest_time: yaml.float("FastestTime"),
+            last_time: yaml.float("LastTime"),
+            laps_led: yaml.int("LapsLed") as i32,
+            laps_complete: yaml.int("LapsComplete") as i32,
+            incidents: yaml.int("Incidents") as i32,
+            reason_out: yaml.string("ReasonOutStr"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Session {
+    pub num: i32,
+    pub session_type: String,
+    pub name: String,
+    // None when the session is unlimited
+    pub laps: Option<u32>,
+    pub time: Option<Duration>,
+    pub track_rubber_state: String,
+    // Ordered by position, empty until the session has started
+    pub results: Vec<ResultPosition>,
+    pub official: bool,
+}
+
+impl Session {
+    pub fn parse(yaml: &Section) -> Session {
+        Session {
+            num: yaml.int("SessionNum") as i32,
+            session_type: yaml.string("SessionType"),
+            name: yaml.string("SessionName"),
+            laps: yaml.limit("SessionLaps").map(|laps| laps as u32),
+            time: yaml.limit("SessionTime").map(|seconds| Duration::from_secs_f64(seconds.max(0.0))),
+            track_rubber_state: yaml.string("SessionTrackRubberState"),
+            results: yaml.list("ResultsPositions", ResultPosition::parse),
+            official: yaml.flag("ResultsOfficial"),
+        }
+    }
+}
+
+// An entry in the roster of cars in the session
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Driver {
+    pub car_idx: usize,
+    pub name: String,
+    pub user_id: i64,
+    pub team_id: i64,
+    pub team_name: String,
+    // As shown on the car, so leading zeros matter
+    pub car_number: String,
+    pub car_id: i64,
+    // Identifies the car model, e.g. `mx5 mx52016`
+    pub car_path: String,
+    pub car_name: String,
+    pub car_short_name: String,
+    pub car_class_id: i64,
+    pub car_class_short_name: String,
+    pub car_class_color: u32,
+    pub car_class_est_lap_time: f32,
+    pub irating: i32,
+    pub license_level: i32,
+    // None when the roster has no license for the driver
+    pub license: Option<License>,
+    pub is_spectator: bool,
+    pub is_pace_car: bool,
+    pub is_ai: bool,
+    pub club_name: String,
+    pub incidents: i32,
+    pub team_incidents: i32,
+}
+
+impl Driver {
+    pub fn parse(yaml: &Section) -> Driver {
+        Driver {
+            car_idx: yaml.int("CarIdx") as usize,
+            name: yaml.string("UserName"),
+            user_id: yaml.int("UserID"),
+            team_id: yaml.int("TeamID"),
+            team_name: yaml.string("TeamName"),
+            car_number: yaml.string("CarNumber"),
+            car_id: yaml.int("CarID"),
+            car_path: yaml.string("CarPath"),
+            car_name: yaml.string("CarScreenName"),
+            car_short_name: yaml.string("CarScreenNameShort"),
+            car_class_id: yaml.int("CarClassID"),
+            car_class_short_name: yaml.string("CarClassShortName"),
+            car_class_color: yaml.color("CarClassColor").unwrap_or(0xffffff),
+            car_class_est_lap_time: yaml.float("CarClassEstLapTime"),
+            irating: yaml.int("IRating") as i32,
+            license_level: yaml.int("LicLevel") as i32,
+            license: yaml.parsed("LicString", |license| License::parse(license, yaml.color("LicColor"))),
+            is_spectator: yaml.flag("IsSpectator"),
+            is_pace_car: yaml.flag("CarIsPaceCar"),
+            is_ai: yaml.flag("CarIsAI"),
+            club_name: yaml.string("ClubName"),
+            incidents: yaml.int("CurDriverIncidentCount") as i32,
+            team_incidents: yaml.int("TeamIncidentCount") as i32,
+        }
+    }
+}
+
+// The roster only has classes through the cars in them
+#[derive(Debug, Clone, PartialEq)]
+pub struct CarClass {
+    pub id: i64,
+    pub short_name: String,
+    // 0xRRGGBB
+    pub color: u32,
+    pub est_lap_time: f32,
+}
+
+impl CarClass {
+    // Fastest first by their estimated lap time. Classes without an estimate come last,
+    // and otherwise the order they first appear in the roster is kept.
+    pub fn from_drivers(drivers: &[Driver]) -> Vec<CarClass> {
+        let mut classes: Vec<CarClass> = vec![];
+        for driver in drivers.iter().filter(|driver| !driver.is_pace_car && !driver.is_spectator) {
+            if !classes.iter().any(|class| class.id == driver.car_class_id) {
+                classes.push(CarClass {
+                    id: driver.car_class_id,
+                    short_name: driver.car_class_short_name.clone(),
+                    color: driver.car_class_color,
+                    est_lap_time: driver.car_class_est_lap_time,
+                });
+            }
+        }
+        classes.sort_by(|a, b| match (a.est_lap_time > 0.0, b.est_lap_time > 0.0) {
+            (true, true) => a.est_lap_time.total_cmp(&b.est_lap_time),
+            (known_a, known_b) => known_b.cmp(&known_a),
+        });
+        classes
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sector {
+    pub num: i32,
+    // Where the sector starts, as a fraction of the lap
+    pub start_pct: f32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SplitTimeInfo {
+    pub sectors: Vec<Sector>,
+}
+
+impl SplitTimeInfo {
+    pub fn parse(yaml: &Section) -> SplitTimeInfo {
+        SplitTimeInfo {
+            sectors: yaml.list("Sectors", |sector| Sector {
+                num: sector.int("SectorNum") as i32,
+                start_pct: sector.float("SectorStartPct"),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Camera {
+    pub num: i32,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CameraGroup {
+    pub num: i32,
+    pub name: String,
+    pub is_scenic: bool,
+    pub cameras: Vec<Camera>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CameraInfo {
+    pub groups: Vec<CameraGroup>,
+}
+
+impl CameraInfo {
+    pub fn parse(yaml: &Section) -> CameraInfo {
+        CameraInfo {
+            groups: yaml.list("Groups", |group| CameraGroup {
+                num: group.int("GroupNum") as i32,
+                name: group.string("GroupName"),
+                is_scenic: group.flag("IsScenic"),
+                cameras: group.list("Cameras", |camera| Camera {
+                    num: camera.int("CameraNum") as i32,
+                    name: camera.string("CameraName"),
+                }),
+            }),
+        }
+    }
+
+    pub fn group(&self, name: &str) -> Option<&CameraGroup> {
+        self.groups.iter().find(|group| group.name == name)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Frequency {
+    pub num: i32,
+    pub name: String,
+    pub priority: i32,
+    // -1 when the frequency is not tied to a car
+    pub car_idx: i32,
+    pub can_scan: bool,
+    pub can_squawk: bool,
+    pub muted: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Radio {
+    pub num: i32,
+    pub hop_count: i32,
+    pub tuned_to_frequency_num: i32,
+    pub scanning: bool,
+    pub frequencies: Vec<Frequency>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RadioInfo {
+    pub selected_radio_num: i32,
+    pub radios: Vec<Radio>,
+}
+
+impl RadioInfo {
+    pub fn parse(yaml: &Section) -> RadioInfo {
+        RadioInfo {
+            selected_radio_num: yaml.int("SelectedRadioNum") as i32,
+            radios: yaml.list("Radios", |radio| Radio {
+                num: radio.int("RadioNum") as i32,
+                hop_count: radio.int("HopCount") as i32,
+                tuned_to_frequency_num: radio.int("TunedToFrequencyNum") as i32,
+                scanning: radio.flag("ScanningIsOn"),
+                frequencies: radio.list("Frequencies", |frequency| Frequency {
+                    num: frequency.int("FrequencyNum") as i32,
+                    name: frequency.string("FrequencyName"),
+                    priority: frequency.int("Priority") as i32,
+                    car_idx: frequency.int("CarIdx") as i32,
+                    can_scan: frequency.flag("CanScan"),
+                    can_squawk: frequency.flag("CanSquawk"),
+                    muted: frequency.flag("Muted"),
+                }),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+// A part of the session info YAML. Values that are there but can not be parsed are recorded as
+// errors and replaced by defaults, so one odd entry does not throw away the rest. Missing fields
+// are not errors, as the sim adds new fields over time.
+pub struct Section<'a> {
+    yaml: &'a Yaml,
+    path: String,
+    errors: &'a RefCell<Vec<FieldError>>,
+}
+
+impl<'a> Section<'a> {
+    pub fn root(yaml: &'a Yaml, errors: &'a RefCell<Vec<FieldError>>) -> Section<'a> {
+        Section { yaml, path: String::new(), errors }
+    }
+
+    fn path(&self, key: &str) -> String {
+        if self.path.is_empty() {
+            key.to_string()
+        } else {
+            format!["{}.{}", self.path, key]
+        }
+    }
+
+    fn error(&self, key: &str, message: String) {
+        self.errors.borrow_mut().push(FieldError { path: self.path(key), message });
+    }
+
+    fn get(&self, key: &str) -> &'a Yaml {
+        let yaml: &'a Yaml = self.yaml;
+        &yaml[key]
+    }
+
+    pub fn section(&self, key: &str) -> Section<'a> {
+        Section { yaml: self.get(key), path: self.path(key), errors: self.errors }
+    }
+
+    // The keys of a mapping in the order they were written, none when it is not a mapping
+    pub fn keys(&self) -> Vec<String> {
+        match self.yaml {
+            Yaml::Hash(entries) => entries.keys().filter_map(|key| key.as_str().map(str::to_string)).collect(),
+            _ => vec![],
+        }
+    }
+
+    pub fn is_mapping(&self, key: &str) -> bool {
+        matches!(self.get(key), Yaml::Hash(_))
+    }
+
+    pub fn list<T>(&self, key: &str, parse: impl Fn(&Section<'a>) -> T) -> Vec<T> {
+        match self.get(key) {
+            Yaml::Array(entries) => entries.iter().enumerate()
+                .map(|(i, entry)| parse(&Section {
+                    yaml: entry,
+                    path: format!["{}[{}]", self.path(key), i],
+                    errors: self.errors,
+                }))
+                .collect(),
+            Yaml::BadValue | Yaml::Null => vec![],
+            other => {
+                self.error(key, format!["expected a list, got {:?}", other]);
+                vec![]
+            },
+        }
+    }
+
+    pub fn string(&self, key: &str) -> String {
+        match self.get(key) {
+            Yaml::String(value) | Yaml::Real(value) => value.clone(),
+            Yaml::Integer(value) => value.to_string(),
+            Yaml::Boolean(value) => value.to_string(),
+            Yaml::BadValue | Yaml::Null => String::new(),
+            other => {
+                self.error(key, format!["expected text, got {:?}", other]);
+                String::new()
+            },
+        }
+    }
+
+    // The number part of a value like `23.89 C`
+    pub fn quantity(&self, key: &str) -> Option<f64> {
+        let yaml = self.get(key);
+        let value = match yaml {
+            Yaml::Integer(value) => Some(*value as f64),
+            Yaml::Real(_) => yaml.as_f64(),
+            Yaml::String(value) => value.split_whitespace().next().and_then(|number| number.parse().ok()),
+            Yaml::BadValue | Yaml::Null => return None,
+            _ => None,
+        };
+        if value.is_none() {
+            self.error(key, format!["expected a number, got {:?}", yaml]);
+        }
+        value
+    }
+
+    pub fn int(&self, key: &str) -> i64 {
+        self.quantity(key).map(|value| value as i64).unwrap_or(0)
+    }
+
+    pub fn float(&self, key: &str) -> f32 {
+        self.quantity(key).unwrap_or(0.0) as f32
+    }
+
+    pub fn flag(&self, key: &str) -> bool {
+        match self.get(key) {
+            Yaml::Boolean(value) => *value,
+            Yaml::BadValue | Yaml::Null => false,
+            _ => self.int(key) != 0,
+        }
+    }
+
+    // Limits such as the session laps are written as `unlimited` when there are none
+    pub fn limit(&self, key: &str) -> Option<f64> {
+        match self.get(key) {
+            Yaml::String(value) if value == "unlimited" => None,
+            _ => self.quantity(key),
+        }
+    }
+
+    // Text in a format of its own, parsed by `parse`. None when the value is empty.
+    pub fn parsed<T>(&self, key: &str, parse: impl Fn(&str) -> Result<T, String>) -> Option<T> {
+        let value = self.string(key);
+        if value.is_empty() {
+            return None;
+        }
+        match parse(&value) {
+            Ok(parsed) => Some(parsed),
+            Err(message) => {
+                self.error(key, message);
+                None
+            },
+        }
+    }
+
+    // Colors are written as hex, e.g. `0xffda59`, which YAML may or may not read as a number
+    pub fn color(&self, key: &str) -> Option<u32> {
+        match self.get(key) {
+            Yaml::Integer(value) => Some(*value as u32),
+            Yaml::BadValue | Yaml::Null => None,
+            // Cars without a driver, like the pace car, have no license color
+            Yaml::String(value) if value == "0xundefined" => None,
+            other => {
+                let color = other.as_str()
+                    .and_then(|value| u32::from_str_radix(value.trim_start_matches("0x"), 16).ok());
+                if color.is_none() {
+                    self.error(key, format!["expected a hex color, got {:?}", other]);
+                }
+                color
+            },
+        }
+    }
+}
+
+// Values that are free text chosen by users, which are quoted regardless of their content so
+// names like `#1 Racing`, `Fast: Team` or `yes` come through as written
+const FREE_TEXT_KEYS: &[&str] = &[
+    "UserName", "AbbrevName", "Initials", "TeamName", "ClubName", "DivisionName",
+    "DriverSetupName", "FrequencyName", "CarDesignStr", "HelmetDesignStr", "SuitDesignStr",
+    "CarNumberDesignStr", "SessionName", "TrackDisplayName", "TrackDisplayShortName",
+];
+
+// Rewrites the session info into YAML that a strict parser accepts. The sim writes values
+// verbatim after the key, so anything with YAML syntax in it needs quoting.
+pub fn sanitize(raw: &str) -> String {
+    let mut sanitized = String::with_capacity(raw.len());
+    for line in raw.lines() {
+        let line: String = line.chars()
+            .filter_map(|c| match c {
+                '\t' => Some(' '),
+                c if c.is_control() => None,
+                c => Some(c),
+            })
+            .collect();
+
+        let indent = line.len() - line.trim_start_matches(|c| c == ' ' || c == '-').len();
+        let (key, value) = match line[indent..].split_once(':') {
+            Some((key, value)) if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => (key, value.trim()),
+            _ => {
+                sanitized.push_str(&line);
+                sanitized.push('\n');
+                continue;
+            },
+        };
+
+        if !value.is_empty() && (FREE_TEXT_KEYS.contains(&key) || needs_quotes(value)) && !is_quoted(value) {
+            sanitized.push_str(&format!["{}{}: '{}'\n", &line[..indent], key, value.replace('\'', "''")]);
+        } else {
+            sanitized.push_str(&line);
+            sanitized.push('\n');
+        }
+    }
+    sanitized
+}
+
+// Whether the value is a single YAML scalar in double or single quotes, where single quotes
+// are escaped by doubling them
+fn is_quoted(value: &str) -> bool {
+    let quote = match value.chars().next() {
+        Some(quote @ ('"' | '\'')) => quote,
+        _ => return false,
+    };
+    if value.len() < 2 || !value.ends_with(quote) {
+        return false;
+    }
+
+    let inner = &value[1..value.len() - 1];
+    if quote == '"' {
+        !inner.contains('"')
+    } else {
+        !inner.replace("''", "").contains('\'')
+    }
+}
+
+fn needs_quotes(value: &str) -> bool {
+    value.starts_with(|c| "@`!&*%|>{}[],?'\"#".contains(c))
+        || value.ends_with(':')
+        || value.contains(": ")
+        || value.contains(" #")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iracing::SessionInfo;
+    use crate::iracing::license::LicenseClass;
+    use std::convert::TryFrom;
+
+    const SPA_PRACTICE: &str = include_str!("../../tests/fixtures/session_info/spa_practice.yaml");
+    const DAYTONA_MULTICLASS_RACE: &str = include_str!("../../tests/fixtures/session_info/daytona_multiclass_race.yaml");
+    // A full size team race, laid out like the sim writes it, with every section
+    const SPA_24H_MULTICLASS_RACE: &str = include_str!("../../tests/fixtures/session_info/spa_24h_multiclass_race.yaml");
+    const OVAL_ODD_ENTRIES: &str = include_str!("../../tests/fixtures/session_info/oval_odd_entries.yaml");
+
+    fn parse(session_info: &str) -> SessionInfo {
+        SessionInfo::try_from(&session_info.to_string()).unwrap()
+    }
+
+    #[test]
+    fn parses_practice_session() {
+        let session_info = parse(SPA_PRACTICE);
+        assert!(session_info.errors.is_empty(), "{:?}", session_info.errors);
+
+        assert_eq!(session_info.track.name, "spa 2019 gp");
+        assert_eq!(session_info.track.configuration, "Grand Prix Pits");
+        assert_eq!(session_info.weekend.track_length_km, 6.93);
+        assert_eq!(session_info.weekend.weather.air_temp_c, 23.89);
+        assert_eq!(session_info.weekend.incident_limit, None);
+
+        assert_eq!(session_info.sessions.len(), 1);
+        assert_eq!(session_info.sessions[0].laps, None);
+        assert_eq!(session_info.sessions[0].time, None);
+        assert_eq!(session_info.sessions[0].results[0].fastest_time, 139.8417);
+
+        assert_eq!(session_info.driver.username, "Søren Ørsted");
+        assert_eq!(session_info.drivers[0].car_number, "64");
+        assert_eq!(session_info.split_time_info.sectors.len(), 3);
+        assert!(session_info.camera_info.group("Scenic").unwrap().is_scenic);
+        assert_eq!(session_info.radio_info.radios[0].frequencies[2].name, "@ADMIN");
+    }
+
+    #[test]
+    fn parses_multiclass_race() {
+        let session_info = parse(DAYTONA_MULTICLASS_RACE);
+        assert!(session_info.errors.is_empty(), "{:?}", session_info.errors);
+
+        assert_eq!(session_info.weekend.sub_session_id, 61234567);
+        assert!(session_info.weekend.official);
+        assert_eq!(session_info.weekend.num_car_classes, 2);
+        assert_eq!(session_info.weekend.num_starters, 4);
+        assert_eq!(session_info.weekend.incident_limit, Some(25));
+
+        let race = session_info.current_session().unwrap();
+        assert_eq!(race.session_type, "Race");
+        assert_eq!(race.laps, Some(24));
+        assert_eq!(race.results.len(), 4);
+        assert_eq!(race.results[3].reason_out, "Disconnected");
+        assert_eq!(session_info.sessions[1].time, Some(Duration::from_secs(600)));
+
+        assert_eq!(session_info.driver.car_idx, 3);
+        assert!(session_info.driver(0).unwrap().is_pace_car);
+        assert_eq!(session_info.driver(1).unwrap().name, "Marco \"The Hammer\" Rossi");
+        assert_eq!(session_info.driver(1).unwrap().car_number, "01");
+        assert_eq!(session_info.driver(1).unwrap().car_class_color, 0xffda59);
+        assert_eq!(session_info.driver(2).unwrap().name, "#2 Jamie O'Brien");
+        assert_eq!(session_info.driver(2).unwrap().team_name, "Apex: Endurance Team");
+        assert_eq!(session_info.driver(3).unwrap().car_class_short_name, "GTD");
+        assert_eq!(session_info.driver(4).unwrap().name, "*Lucky* Luca Bianchi");
+        assert_eq!(session_info.driver(4).unwrap().team_name, "[DNF] Racing # Sim");
+
+        let classes: Vec<&str> = session_info.classes.iter().map(|class| class.short_name.as_str()).collect();
+        assert_eq!(classes, vec!["GTP", "GTD"]);
+        assert!(session_info.is_multiclass());
+        assert_eq!(session_info.class_of(1).unwrap().color, 0xffda59);
+        assert!(session_info.class_of(0).is_none());
+
+        // Sorted by lap time, not by where the classes appear in the roster
+        let mut drivers = session_info.drivers.clone();
+        drivers.reverse();
+        let class_names = |drivers: &[Driver]| -> Vec<String> {
+            CarClass::from_drivers(drivers).into_iter().map(|class| class.short_name).collect()
+        };
+        assert_eq!(class_names(&drivers), ["GTP", "GTD"]);
+
+        // Ties and classes without an estimate keep the roster order
+        for driver in drivers.iter_mut() {
+            driver.car_class_est_lap_time = 100.0;
+        }
+        assert_eq!(class_names(&drivers), ["GTD", "GTP"]);
+        for driver in drivers.iter_mut().filter(|driver| driver.car_class_short_name == "GTD") {
+            driver.car_class_est_lap_time = 0.0;
+        }
+        assert_eq!(class_names(&drivers), ["GTP", "GTD"]);
+
+        let license = |car_idx: usize| session_info.driver(car_idx).unwrap().license.unwrap();
+        assert_eq!(license(1).class, LicenseClass::A);
+        assert_eq!(license(1).safety_rating, 4.99);
+        assert_eq!(license(1).color, 0x0153db);
+        assert!(license(1) > license(2));
+        assert!(license(2) > license(3));
+        // The pace car has no color of its own
+        assert_eq!(license(0).color, LicenseClass::Rookie.color());
+        assert_eq!(session_info.driver.license, Some(license(3)));
+    }
+
+    #[test]
+    fn keeps_what_parses_from_odd_entries() {
+        let session_info = parse(OVAL_ODD_ENTRIES);
+
+        let mut broken: Vec<&str> = session_info.errors.iter().map(|err| err.path.as_str()).collect();
+        broken.sort();
+        assert_eq!(broken, vec![
+            "DriverInfo.Drivers[2].CarClassColor",
+            "DriverInfo.Drivers[2].CurDriverIncidentCount",
+            "DriverInfo.Drivers[2].IRating",
+        ]);
+
+        assert_eq!(session_info.track.configuration, "Grand Prix");
+        assert_eq!(session_info.sessions[0].name, "HEAT 1: FEATURE");
+        assert_eq!(session_info.sessions[0].laps, Some(100));
+        assert_eq!(session_info.current_session_num, None);
+
+        assert_eq!(session_info.driver.username, "yes");
+        assert_eq!(session_info.drivers.len(), 4);
+        assert_eq!(session_info.driver(1).unwrap().team_name, "1234");
+        assert_eq!(session_info.driver(1).unwrap().car_number, "007");
+        assert_eq!(session_info.driver(2).unwrap().name, "Björn Åberg");
+        assert_eq!(session_info.driver(2).unwrap().irating, 0);
+        assert_eq!(session_info.driver(2).unwrap().license.unwrap().to_string(), "A 2.50");
+        // No color in the roster
+        assert_eq!(session_info.driver(2).unwrap().license.unwrap().color, 0x0153db);
+        assert!(session_info.driver(3).unwrap().is_spectator);
+    }
+
+    #[test]
+    fn parses_large_team_race() {
+        let session_info = parse(SPA_24H_MULTICLASS_RACE);
+        assert!(session_info.errors.is_empty(), "{:?}", session_info.errors);
+
+        assert!(session_info.weekend.team_racing);
+        assert_eq!(session_info.weekend.num_car_classes, 3);
+        assert_eq!(session_info.weekend.incident_limit, Some(300));
+        assert_eq!(session_info.current_session().unwrap().time, Some(Duration::from_secs(86400)));
+        assert_eq!(session_info.current_session().unwrap().results.len(), 45);
+
+        assert_eq!(session_info.drivers.len(), 46);
+        let classes: Vec<&str> = session_info.classes.iter().map(|class| class.short_name.as_str()).collect();
+        assert_eq!(classes, vec!["GTP", "LMP2", "GT3"]);
+        assert_eq!(session_info.driver.username, "Kasper Nielsen");
+        assert_eq!(session_info.class_of(session_info.driver.car_idx).unwrap().short_name, "GT3");
+        assert_eq!(session_info.driver(2).unwrap().team_name, "Apex: Endurance");
+        assert_eq!(session_info.driver(3).unwrap().team_name, "#51 AF Corse Esports");
+        assert_eq!(session_info.split_time_info.sectors.len(), 7);
+        assert!(session_info.car_setup.is_some());
+    }
+
+    #[test]
+    fn sanitize_quotes_yaml_syntax_in_values() {
+        let sanitized = sanitize(" Drivers:\n - UserName: Sam: 'Quick' #1\n   CarNumber: \"12\"\n   IRating: 1500\n");
+        assert_eq!(sanitized, " Drivers:\n - UserName: 'Sam: ''Quick'' #1'\n   CarNumber: \"12\"\n   IRating: 1500\n");
+    }
+
+    #[test]
+    fn sanitize_keeps_quoted_values() {
+        let quoted = " Drivers:\n - UserName: 'Sam''s #1'\n   TeamName: \"Fast: Team\"\n";
+        assert_eq!(sanitize(quoted), quoted);
+        // Not a single scalar, so it is quoted as a whole
+        assert_eq!(sanitize(" TeamName: 'Sam' & Co'\n"), " TeamName: '''Sam'' & Co'''\n");
+    }
+
+    #[test]
+    fn sanitize_drops_control_characters() {
+        assert_eq!(sanitize(" TeamName: Team\u{1}\tName\n"), " TeamName: 'Team Name'\n");
+    }
+
+    #[test]
+    fn rejects_missing_essentials() {
+        assert!(SessionInfo::try_from(&String::new()).is_err());
+        assert!(SessionInfo::try_from(&"WeekendInfo:\n TrackName: monza full\n".to_string()).is_err());
+        assert!(SessionInfo::try_from(&"DriverInfo:\n DriverCarIdx: 0\n".to_string()).is_err());
+    }
+}
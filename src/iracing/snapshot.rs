@@ -0,0 +1,348 @@
+#![allow(non_upper_case_globals)]
+
+use std::marker::PhantomData;
+use std::mem::{ align_of, size_of };
+
+use crate::iracing::data_collector::{
+    irsdk_VarType,
+    irsdk_VarType_irsdk_char, irsdk_VarType_irsdk_bool, irsdk_VarType_irsdk_int,
+    irsdk_VarType_irsdk_bitField, irsdk_VarType_irsdk_float, irsdk_VarType_irsdk_double,
+    DataHeader, IracingValue,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VarKind {
+    Char,
+    Bool,
+    Int,
+    BitField,
+    Float,
+    Double,
+}
+
+impl VarKind {
+    pub fn from_raw(var_type: i32) -> Option<VarKind> {
+        match var_type as irsdk_VarType {
+            irsdk_VarType_irsdk_char => Some(VarKind::Char),
+            irsdk_VarType_irsdk_bool => Some(VarKind::Bool),
+            irsdk_VarType_irsdk_int => Some(VarKind::Int),
+            irsdk_VarType_irsdk_bitField => Some(VarKind::BitField),
+            irsdk_VarType_irsdk_float => Some(VarKind::Float),
+            irsdk_VarType_irsdk_double => Some(VarKind::Double),
+            _ => None,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            VarKind::Char | VarKind::Bool => 1,
+            VarKind::Int | VarKind::BitField | VarKind::Float => 4,
+            VarKind::Double => 8,
+        }
+    }
+}
+
+// A Rust type a telemetry variable of the given kind can be read as
+pub trait VarType: Copy {
+    const KIND: VarKind;
+
+    fn read(bytes: &[u8]) -> Self;
+}
+
+/// Types for which every bit pattern is valid, so a telemetry line can be viewed as a slice of them.
+///
+/// # Safety
+///
+/// Implementors must be plain old data with a size matching `KIND`.
+pub unsafe trait PodVarType: VarType {}
+
+macro_rules! pod_var_type {
+    ($type:ty, $kind:expr) => {
+        impl VarType for $type {
+            const KIND: VarKind = $kind;
+
+            fn read(bytes: &[u8]) -> Self {
+                <$type>::from_ne_bytes(bytes[..size_of::<$type>()].try_into().unwrap())
+            }
+        }
+
+        unsafe impl PodVarType for $type {}
+
+        impl VarShape for $type {
+            type Element = $type;
+            const IS_ARRAY: bool = false;
+        }
+    };
+}
+
+pod_var_type!(u8, VarKind::Char);
+pod_var_type!(i32, VarKind::Int);
+pod_var_type!(u32, VarKind::BitField);
+pod_var_type!(f32, VarKind::Float);
+pod_var_type!(f64, VarKind::Double);
+
+impl VarType for bool {
+    const KIND: VarKind = VarKind::Bool;
+
+    fn read(bytes: &[u8]) -> Self {
+        bytes[0] != 0
+    }
+}
+
+impl VarShape for bool {
+    type Element = bool;
+    const IS_ARRAY: bool = false;
+}
+
+// Whether a handle refers to a single value `T` or to all entries of an array variable `[T]`
+pub trait VarShape {
+    type Element: VarType;
+    const IS_ARRAY: bool;
+}
+
+impl<T: VarType> VarShape for [T] {
+    type Element = T;
+    const IS_ARRAY: bool = true;
+}
+
+// The location of a variable in a telemetry line, resolved once from the headers of a connection.
+// `VarHandle<f32>` refers to a single value and `VarHandle<[f32]>` to an array like `CarIdxLapDistPct`.
+pub struct VarHandle<T: ?Sized> {
+    offset: usize,
+    count: usize,
+    _type: PhantomData<fn() -> Box<T>>,
+}
+
+impl<T: ?Sized> VarHandle<T> {
+    fn at(offset: usize, count: usize) -> VarHandle<T> {
+        VarHandle { offset, count, _type: PhantomData }
+    }
+}
+
+impl<T: ?Sized> Clone for VarHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ?Sized> Copy for VarHandle<T> {}
+
+impl<T: ?Sized> std::fmt::Debug for VarHandle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "VarHandle {{ offset: {}, count: {} }}", self.offset, self.count)
+    }
+}
+
+impl<T: VarShape + ?Sized> VarHandle<T> {
+    // `line_len` is the length of the telemetry lines the handle will be used on. Variables
+    // that do not fit in it, e.g. from a truncated .ibt header, are not resolved.
+    pub fn resolve(headers: &[DataHeader], line_len: usize, name: &str) -> Option<VarHandle<T>> {
+        let header = headers.iter().find(|header| header.name == name)?;
+        if header.kind != Some(T::Element::KIND) {
+            warn!["Telemetry variable {} is a {:?}, not a {:?}", name, header.kind, T::Element::KIND];
+            return None;
+        }
+
+        let count = if T::IS_ARRAY { header.count } else { 1 };
+        let end = count.checked_mul(size_of::<T::Element>())
+            .and_then(|size| header.offset.checked_add(size));
+        if end.map_or(true, |end| end > line_len) {
+            warn!["Telemetry variable {} does not fit in a line of {} bytes", name, line_len];
+            return None;
+        }
+
+        if T::IS_ARRAY && header.offset % align_of::<T::Element>() != 0 {
+            warn!["Telemetry variable {} is not aligned", name];
+            return None;
+        }
+        Some(VarHandle::at(header.offset, count))
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+}
+
+// Storage for a single telemetry line. Backed by u64s so the line is aligned for all
+// variable types, which is what allows `Snapshot::slice` to hand out typed slices.
+#[derive(Default)]
+pub struct LineBuffer {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl LineBuffer {
+    pub fn new(len: usize) -> LineBuffer {
+        LineBuffer { words: vec![0u64; (len + 7) / 8], len }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn resize(&mut self, len: usize) {
+        self.words.resize((len + 7) / 8, 0);
+        self.len = len;
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.words.as_ptr() as *const u8, self.len) }
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.words.as_mut_ptr() as *mut u8, self.len) }
+    }
+
+    pub fn snapshot(&self) -> Snapshot<'_> {
+        Snapshot { line: self.as_bytes() }
+    }
+}
+
+// A borrowed view of the most recent telemetry line of a connection
+#[derive(Clone, Copy)]
+pub struct Snapshot<'a> {
+    line: &'a [u8],
+}
+
+impl<'a> Snapshot<'a> {
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.line
+    }
+
+    pub fn get<T: VarType>(&self, handle: &VarHandle<T>) -> T {
+        T::read(&self.line[handle.offset..handle.offset + size_of::<T>()])
+    }
+
+    pub fn slice<T: PodVarType>(&self, handle: &VarHandle<[T]>) -> &'a [T] {
+        let bytes = &self.line[handle.offset..handle.offset + handle.count * size_of::<T>()];
+        // The line is 8 byte aligned and the handle offset was checked to be aligned for T
+        unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const T, handle.count) }
+    }
+
+    // For arrays of types that can't be viewed as a slice directly, like bool
+    pub fn iter<T: VarType>(&self, handle: &VarHandle<[T]>) -> impl Iterator<Item = T> + 'a {
+        let line = self.line;
+        let offset = handle.offset;
+        (0..handle.count).map(move |idx| {
+            let start = offset + idx * size_of::<T>();
+            T::read(&line[start..start + size_of::<T>()])
+        })
+    }
+
+    // Decodes any variable without a typed handle. This allocates for arrays, so it
+    // is meant for inspecting unknown variables rather than for every tick.
+    pub fn value(&self, header: &DataHeader) -> IracingValue {
+        let kind = match header.kind {
+            Some(kind) => kind,
+            None => return IracingValue::Unknown,
+        };
+        let start = header.offset;
+        let end = start + kind.size() * header.count;
+        if end > self.line.len() {
+            return IracingValue::Unknown;
+        }
+
+        let count = header.count;
+        match (kind, count) {
+            (VarKind::Double, 1) => IracingValue::Double(self.get::<f64>(&VarHandle::at(start, 1))),
+            (VarKind::Double, _) => IracingValue::DoubleVector(self.iter::<f64>(&VarHandle::at(start, count)).collect()),
+            (VarKind::Int, 1) => IracingValue::Int(self.get::<i32>(&VarHandle::at(start, 1))),
+            (VarKind::Int, _) => IracingValue::IntVector(self.iter::<i32>(&VarHandle::at(start, count)).collect()),
+            (VarKind::Float, 1) => IracingValue::Float(self.get::<f32>(&VarHandle::at(start, 1))),
+            (VarKind::Float, _) => IracingValue::FloatVector(self.iter::<f32>(&VarHandle::at(start, count)).collect()),
+            (VarKind::Bool, 1) => IracingValue::Boolean(self.get::<bool>(&VarHandle::at(start, 1))),
+            (VarKind::Bool, _) => IracingValue::BooleanVector(self.iter::<bool>(&VarHandle::at(start, count)).collect()),
+            (VarKind::Char, 1) => IracingValue::Char(self.get::<u8>(&VarHandle::at(start, 1))),
+            (VarKind::Char, _) => IracingValue::CharVector(self.iter::<u8>(&VarHandle::at(start, count)).collect()),
+            (VarKind::BitField, 1) => IracingValue::Bitfield(self.get::<u32>(&VarHandle::at(start, 1))),
+            (VarKind::BitField, _) => IracingValue::BitfieldVector(self.iter::<u32>(&VarHandle::at(start, count)).collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(name: &str, kind: VarKind, offset: usize, count: usize) -> DataHeader {
+        DataHeader {
+            name: name.to_string(),
+            description: String::new(),
+            unit: String::new(),
+            kind: Some(kind),
+            offset,
+            count,
+        }
+    }
+
+    // CarIdxSessionFlags[2] at 0, EngineWarnings at 8, DriverInitials[3] at 12,
+    // CarIdxOnPitRoad[3] at 15, PitSvFlags at 20 and a lone char at 24
+    fn line() -> (Vec<DataHeader>, LineBuffer) {
+        let headers = vec![
+            header("CarIdxSessionFlags", VarKind::BitField, 0, 2),
+            header("EngineWarnings", VarKind::BitField, 8, 1),
+            header("DriverInitials", VarKind::Char, 12, 3),
+            header("CarIdxOnPitRoad", VarKind::Bool, 15, 3),
+            header("PitSvFlags", VarKind::BitField, 20, 1),
+            header("Gear", VarKind::Char, 24, 1),
+        ];
+
+        let mut buffer = LineBuffer::new(25);
+        let bytes = buffer.as_bytes_mut();
+        bytes[0..4].copy_from_slice(&0x0004u32.to_ne_bytes());
+        bytes[4..8].copy_from_slice(&0x0020u32.to_ne_bytes());
+        bytes[8..12].copy_from_slice(&0x0010u32.to_ne_bytes());
+        bytes[12..15].copy_from_slice(b"MvW");
+        bytes[15..18].copy_from_slice(&[0, 1, 0]);
+        bytes[20..24].copy_from_slice(&0x0011u32.to_ne_bytes());
+        bytes[24] = b'R';
+        (headers, buffer)
+    }
+
+    #[test]
+    fn decodes_chars() {
+        let (headers, buffer) = line();
+        let snapshot = buffer.snapshot();
+        assert_eq!(snapshot.value(&headers[2]), IracingValue::CharVector(b"MvW".to_vec()));
+        assert_eq!(snapshot.value(&headers[5]), IracingValue::Char(b'R'));
+    }
+
+    #[test]
+    fn decodes_bitfields() {
+        let (headers, buffer) = line();
+        let snapshot = buffer.snapshot();
+        assert_eq!(snapshot.value(&headers[0]), IracingValue::BitfieldVector(vec![0x0004, 0x0020]));
+        assert_eq!(snapshot.value(&headers[1]), IracingValue::Bitfield(0x0010));
+        assert_eq!(snapshot.value(&headers[4]), IracingValue::Bitfield(0x0011));
+    }
+
+    #[test]
+    fn decodes_boolean_vectors() {
+        let (headers, buffer) = line();
+        assert_eq!(buffer.snapshot().value(&headers[3]), IracingValue::BooleanVector(vec![false, true, false]));
+    }
+
+    #[test]
+    fn does_not_decode_past_the_line() {
+        let (_, buffer) = line();
+        let snapshot = buffer.snapshot();
+        assert_eq!(snapshot.value(&header("CarIdxSessionFlags", VarKind::BitField, 20, 2)), IracingValue::Unknown);
+        let unknown = DataHeader { kind: None, ..header("Unknown", VarKind::Int, 0, 1) };
+        assert_eq!(snapshot.value(&unknown), IracingValue::Unknown);
+    }
+
+    #[test]
+    fn resolves_typed_handles() {
+        let (headers, buffer) = line();
+        let snapshot = buffer.snapshot();
+
+        let on_pit_road = VarHandle::<[bool]>::resolve(&headers, buffer.len(), "CarIdxOnPitRoad").unwrap();
+        assert_eq!(snapshot.iter(&on_pit_road).collect::<Vec<_>>(), [false, true, false]);
+        let session_flags = VarHandle::<[u32]>::resolve(&headers, buffer.len(), "CarIdxSessionFlags").unwrap();
+        assert_eq!(snapshot.slice(&session_flags), [0x0004, 0x0020]);
+
+        // Wrong kinds and variables that don't fit the line are not resolved
+        assert!(VarHandle::<i32>::resolve(&headers, buffer.len(), "EngineWarnings").is_none());
+        assert!(VarHandle::<[u32]>::resolve(&headers, 4, "CarIdxSessionFlags").is_none());
+    }
+}
@@ -0,0 +1,79 @@
+use crate::iracing::SessionInfo;
+use crate::iracing::irating::BR;
+
+// The rating a field of equally rated drivers would need to be as hard to beat, which is how
+// iRacing computes the strength of field. None for an empty field.
+pub fn strength_of_field(iratings: &[i32]) -> Option<i32> {
+    if iratings.is_empty() {
+        return None;
+    }
+    let sum: f64 = iratings.iter().map(|&irating| (-irating as f64 / BR).exp()).sum();
+    Some((BR * (iratings.len() as f64 / sum).ln()).round() as i32)
+}
+
+// The racing drivers with a known rating. AI drivers and drivers without a rating in the
+// roster are left out.
+fn iratings(session_info: &SessionInfo, class_id: Option<i64>) -> Vec<i32> {
+    session_info.drivers.iter()
+        .filter(|driver| !driver.is_ai && driver.irating > 0)
+        .filter(|driver| match session_info.class_of(driver.car_idx) {
+            Some(class) => class_id.map_or(true, |class_id| class.id == class_id),
+            None => false,
+        })
+        .map(|driver| driver.irating)
+        .collect()
+}
+
+// Recomputed from every session info, so it follows drivers joining and leaving
+pub fn session_strength_of_field(session_info: &SessionInfo) -> Option<i32> {
+    strength_of_field(&iratings(session_info, None))
+}
+
+pub fn class_strength_of_field(session_info: &SessionInfo, class_id: i64) -> Option<i32> {
+    strength_of_field(&iratings(session_info, Some(class_id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    const DAYTONA_MULTICLASS_RACE: &str = include_str!("../../tests/fixtures/session_info/daytona_multiclass_race.yaml");
+
+    #[test]
+    fn empty_field_has_no_strength() {
+        assert_eq!(strength_of_field(&[]), None);
+    }
+
+    #[test]
+    fn equal_field_is_its_own_strength() {
+        assert_eq!(strength_of_field(&[1350]), Some(1350));
+        assert_eq!(strength_of_field(&[2500; 20]), Some(2500));
+    }
+
+    // The expected values below are worked out by hand from the formula. They are not checked
+    // against the strength of field iRacing published for a real race, as no such result is
+    // part of the fixtures (see tests/fixtures/session_info/README.md).
+    #[test]
+    fn weighs_lower_ratings_more() {
+        // With `BR` being 1600 / ln 2 every 1600 points halve the weight of a driver, so the strength
+        // is 1600 * log2(count / sum(2 ^ (-irating / 1600))). For 1600 and 3200 that is
+        // 1600 * log2(2 / (1/2 + 1/4)) = 1600 * (3 - log2 3) = 2264.06, below the average of 2400.
+        assert_eq!(strength_of_field(&[1600, 3200]), Some(2264));
+        // 1600 * log2(4 / (3/2 + 1/8)) = 1600 * (6 - log2 13) = 2079.30
+        assert_eq!(strength_of_field(&[1600, 1600, 1600, 4800]), Some(2079));
+    }
+
+    #[test]
+    fn splits_multiclass_field() {
+        let session_info = SessionInfo::try_from(&DAYTONA_MULTICLASS_RACE.to_string()).unwrap();
+        let gtp = session_info.classes[0].id;
+        let gtd = session_info.classes[1].id;
+
+        // The pace car is left out, leaving 4312 and 3877 in GTP and 1876 and 1654 in GTD
+        assert_eq!(session_strength_of_field(&session_info), Some(2643));
+        assert_eq!(class_strength_of_field(&session_info, gtp), Some(4084));
+        assert_eq!(class_strength_of_field(&session_info, gtd), Some(1762));
+        assert_eq!(class_strength_of_field(&session_info, -1), None);
+    }
+}
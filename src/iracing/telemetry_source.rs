@@ -0,0 +1,332 @@
+use std::ffi::CString;
+use std::path::PathBuf;
+use std::time::{ Duration, Instant };
+
+use async_std::task;
+use async_std::task::{Context, Poll};
+use async_std::stream::Stream;
+use async_std::pin::Pin;
+
+use crate::iracing::data_collector::{
+    irsdk_varHeader, irsdk_VarType,
+    irsdk_VarType_irsdk_float, irsdk_VarType_irsdk_int, irsdk_VarType_irsdk_bool, irsdk_VarType_irsdk_double,
+    DataHeader, Update,
+    IracingConnection, IracingConnectionError, CollectorError,
+};
+use crate::iracing::ibt_reader::IbtReader;
+use crate::iracing::ConnectionStatus;
+use crate::iracing::snapshot::{ LineBuffer, Snapshot };
+
+// A stream of raw iRacing updates together with the description of the variables in it
+pub trait TelemetryConnection: Stream<Item = Result<Update, CollectorError>> + Unpin + Send {
+    fn var_headers(&self) -> &[irsdk_varHeader];
+
+    // The telemetry line behind the most recent `Update::Telemetry`
+    fn snapshot(&self) -> Snapshot<'_>;
+
+    // The length of the telemetry lines, as announced by the header
+    fn line_len(&self) -> usize;
+
+    fn tick_rate(&self) -> i32;
+
+    // How often reading an update had to be retried, for sources that can be overwritten while read
+    fn torn_reads(&self) -> u64 {
+        0
+    }
+
+    fn headers(&self) -> Vec<DataHeader> {
+        self.var_headers().iter().map(DataHeader::from).collect()
+    }
+}
+
+// Something `IracingTask` can (re-)connect to in order to receive telemetry
+pub trait TelemetrySource: Send {
+    type Connection: TelemetryConnection;
+
+    fn connect(&mut self) -> Result<Self::Connection, IracingConnectionError>;
+}
+
+#[derive(Debug, Clone)]
+pub enum SourceConfig {
+    // Without a memory file the sim's own named memory map is used, which is only available on Windows
+    Live { memory_file: Option<PathBuf> },
+    Ibt { path: PathBuf, real_time: bool, looping: bool },
+    Synthetic,
+}
+
+impl TelemetryConnection for IracingConnection {
+    fn var_headers(&self) -> &[irsdk_varHeader] {
+        IracingConnection::var_headers(self)
+    }
+
+    fn snapshot(&self) -> Snapshot<'_> {
+        IracingConnection::snapshot(self)
+    }
+
+    fn line_len(&self) -> usize {
+        IracingConnection::line_len(self)
+    }
+
+    fn tick_rate(&self) -> i32 {
+        IracingConnection::tick_rate(self)
+    }
+
+    fn torn_reads(&self) -> u64 {
+        IracingConnection::torn_reads(self)
+    }
+}
+
+impl TelemetryConnection for IbtReader {
+    fn var_headers(&self) -> &[irsdk_varHeader] {
+        IbtReader::var_headers(self)
+    }
+
+    fn snapshot(&self) -> Snapshot<'_> {
+        IbtReader::snapshot(self)
+    }
+
+    fn line_len(&self) -> usize {
+        IbtReader::line_len(self)
+    }
+
+    fn tick_rate(&self) -> i32 {
+        IbtReader::tick_rate(self)
+    }
+}
+
+pub struct LiveSource {
+    memory_file: Option<PathBuf>,
+}
+
+impl LiveSource {
+    pub fn new(memory_file: Option<PathBuf>) -> LiveSource {
+        LiveSource { memory_file }
+    }
+}
+
+impl TelemetrySource for LiveSource {
+    type Connection = IracingConnection;
+
+    fn connect(&mut self) -> Result<IracingConnection, IracingConnectionError> {
+        match &self.memory_file {
+            Some(path) => IracingConnection::from_file(path),
+            None => IracingConnection::new(),
+        }
+    }
+}
+
+pub struct IbtSource {
+    path: PathBuf,
+    real_time: bool,
+    looping: bool,
+    played: bool,
+}
+
+impl IbtSource {
+    pub fn new(path: PathBuf, real_time: bool, looping: bool) -> IbtSource {
+        IbtSource { path, real_time, looping, played: false }
+    }
+}
+
+impl TelemetrySource for IbtSource {
+    type Connection = IbtReader;
+
+    fn connect(&mut self) -> Result<IbtReader, IracingConnectionError> {
+        if self.played && !self.looping {
+            return Err(IracingConnectionError::Exhausted);
+        }
+        self.played = true;
+
+        IbtReader::open(&self.path, self.real_time)
+            .map_err(IracingConnectionError::Failed)
+    }
+}
+
+pub struct SyntheticSource;
+
+impl TelemetrySource for SyntheticSource {
+    type Connection = SyntheticConnection;
+
+    fn connect(&mut self) -> Result<SyntheticConnection, IracingConnectionError> {
+        Ok(SyntheticConnection::new())
+    }
+}
+
+const SYNTHETIC_SESSION_INFO: &str = "---
+WeekendInfo:
+ TrackName: monza full
+ TrackConfigName: Grand Prix
+DriverInfo:
+ DriverCarIdx: 1
+ Drivers:
+ - CarIdx: 0
+   UserName: Leading Driver
+   IRating: 2000
+   LicString: A 3.50
+ - CarIdx: 1
+   UserName: Test Driver
+   IRating: 1
+   LicString: R 0.01
+ - CarIdx: 2
+   UserName: Trailing Driver
+   IRating: 1500
+   LicString: C 2.10
+SplitTimeInfo:
+ Sectors:
+ - SectorNum: 0
+   SectorStartPct: 0.000000
+ - SectorNum: 1
+   SectorStartPct: 0.330000
+ - SectorNum: 2
+   SectorStartPct: 0.660000
+...
+";
+
+const SYNTHETIC_CARS: usize = 3;
+const SYNTHETIC_TICK: Duration = Duration::from_millis(50);
+
+fn synthetic_var_header(name: &str, type_: irsdk_VarType, offset: usize, count: usize) -> irsdk_varHeader {
+    let mut header: irsdk_varHeader = unsafe { std::mem::zeroed() };
+    header.type_ = type_ as i32;
+    header.offset = offset as i32;
+    header.count = count as i32;
+
+    let name = CString::new(name).unwrap();
+    for (dst, src) in header.name.iter_mut().zip(name.as_bytes()) {
+        *dst = *src as _;
+    }
+    header
+}
+
+// Generates a car driving around Monza, encoded exactly like a live telemetry line
+// so the decoding in `IracingTask` is exercised without the sim running.
+pub struct SyntheticConnection {
+    var_headers: Vec<irsdk_varHeader>,
+    buffer: LineBuffer,
+    announced: bool,
+    session_info_sent: bool,
+    next_tick: Instant,
+
+    session_time: f64,
+    position: f32,
+    laps: i32,
+    brake: f32,
+}
+
+impl SyntheticConnection {
+    const SESSION_TIME: usize = 0;
+    const THROTTLE: usize = 1;
+    const BRAKE: usize = 2;
+    const LAP_DIST: usize = 3;
+    const POSITIONS: usize = 4;
+    const LAPS: usize = 5;
+    const LAPS_COMPLETED: usize = 6;
+    const IS_ON_TRACK: usize = 7;
+
+    // How far ahead of the player the other cars are
+    const CAR_OFFSETS: [f32; SYNTHETIC_CARS] = [0.04, 0.0, -0.04];
+
+    pub fn new() -> SyntheticConnection {
+        let var_headers = vec![
+            synthetic_var_header("SessionTime", irsdk_VarType_irsdk_double, 0, 1),
+            synthetic_var_header("Throttle", irsdk_VarType_irsdk_float, 8, 1),
+            synthetic_var_header("Brake", irsdk_VarType_irsdk_float, 12, 1),
+            synthetic_var_header("CarIdxLapDistPct", irsdk_VarType_irsdk_float, 16, SYNTHETIC_CARS),
+            synthetic_var_header("CarIdxPosition", irsdk_VarType_irsdk_int, 16 + 4 * SYNTHETIC_CARS, SYNTHETIC_CARS),
+            synthetic_var_header("CarIdxLap", irsdk_VarType_irsdk_int, 16 + 8 * SYNTHETIC_CARS, SYNTHETIC_CARS),
+            synthetic_var_header("CarIdxLapCompleted", irsdk_VarType_irsdk_int, 16 + 12 * SYNTHETIC_CARS, SYNTHETIC_CARS),
+            synthetic_var_header("IsOnTrack", irsdk_VarType_irsdk_bool, 16 + 16 * SYNTHETIC_CARS, 1),
+        ];
+
+        SyntheticConnection {
+            var_headers,
+            buffer: LineBuffer::new(16 + 16 * SYNTHETIC_CARS + 1),
+            announced: false,
+            session_info_sent: false,
+            next_tick: Instant::now(),
+            session_time: 0.0,
+            position: 0.0,
+            laps: 0,
+            brake: 0.0,
+        }
+    }
+
+    fn write<T: Copy>(&mut self, var: usize, idx: usize, value: T) {
+        let offset = self.var_headers[var].offset as usize + idx * std::mem::size_of::<T>();
+        unsafe {
+            std::ptr::write_unaligned(self.buffer.as_bytes_mut().as_mut_ptr().add(offset) as *mut T, value);
+        }
+    }
+
+    fn advance(&mut self) {
+        self.session_time += SYNTHETIC_TICK.as_secs_f64();
+        self.position += 0.001;
+        if self.position >= 1.0 {
+            self.position -= 1.0;
+            self.laps += 1;
+        }
+        self.brake = (self.brake + 0.05) % 1.05;
+
+        self.write(Self::SESSION_TIME, 0, self.session_time);
+        self.write(Self::THROTTLE, 0, 1.0f32 - self.brake.min(1.0));
+        self.write(Self::BRAKE, 0, self.brake);
+        for (car_idx, offset) in Self::CAR_OFFSETS.into_iter().enumerate() {
+            let distance = self.laps as f32 + self.position + offset;
+            let laps_completed = distance.floor() as i32;
+            self.write(Self::LAP_DIST, car_idx, distance - laps_completed as f32);
+            self.write(Self::POSITIONS, car_idx, car_idx as i32);
+            self.write(Self::LAPS, car_idx, laps_completed + 1);
+            self.write(Self::LAPS_COMPLETED, car_idx, laps_completed);
+        }
+        self.write(Self::IS_ON_TRACK, 0, 1u8);
+    }
+}
+
+impl TelemetryConnection for SyntheticConnection {
+    fn var_headers(&self) -> &[irsdk_varHeader] {
+        &self.var_headers
+    }
+
+    fn snapshot(&self) -> Snapshot<'_> {
+        self.buffer.snapshot()
+    }
+
+    fn line_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn tick_rate(&self) -> i32 {
+        (1000 / SYNTHETIC_TICK.as_millis()) as i32
+    }
+}
+
+impl Stream for SyntheticConnection {
+    type Item = Result<Update, CollectorError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if !self.announced {
+            self.announced = true;
+            return Poll::Ready(Some(Ok(Update::Connection(ConnectionStatus::Connected { tick_rate: self.tick_rate() }))));
+        }
+
+        if !self.session_info_sent {
+            self.session_info_sent = true;
+            return Poll::Ready(Some(Ok(Update::SessionInfo(SYNTHETIC_SESSION_INFO.to_string()))));
+        }
+
+        let now = Instant::now();
+        if now < self.next_tick {
+            let waiter = cx.waker().clone();
+            let wait = self.next_tick - now;
+            task::spawn(async move {
+                task::sleep(wait).await;
+                waiter.wake();
+            });
+            return Poll::Pending;
+        }
+        self.next_tick = now + SYNTHETIC_TICK;
+
+        self.advance();
+        Poll::Ready(Some(Ok(Update::Telemetry)))
+    }
+}
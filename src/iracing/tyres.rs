@@ -0,0 +1,78 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Corner {
+    LeftFront,
+    RightFront,
+    LeftRear,
+    RightRear,
+}
+
+impl Corner {
+    pub const ALL: [Corner; 4] = [Corner::LeftFront, Corner::RightFront, Corner::LeftRear, Corner::RightRear];
+
+    // How the telemetry variables of the corner start, e.g. `LFtempL`
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            Corner::LeftFront => "LF",
+            Corner::RightFront => "RF",
+            Corner::LeftRear => "LR",
+            Corner::RightRear => "RR",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TyreCorner {
+    // Celsius across the tread, from the left to the right side of the car. Only updated in the pits.
+    pub temps: [f32; 3],
+    // Fraction of the tread left across the tread, 1 for a new tyre. Only updated in the pits.
+    pub wear: [f32; 3],
+    // kPa
+    pub pressure: f32,
+    pub cold_pressure: f32,
+    // Meters
+    pub ride_height: f32,
+}
+
+impl TyreCorner {
+    pub fn average_temp(&self) -> f32 {
+        self.temps.iter().sum::<f32>() / 3.0
+    }
+
+    // The most worn part of the tread
+    pub fn min_wear(&self) -> f32 {
+        self.wear.iter().copied().fold(1.0, f32::min)
+    }
+}
+
+impl fmt::Display for TyreCorner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.0}/{:.0}/{:.0}C {:.0}/{:.0}/{:.0}% {:.1} kPa",
+            self.temps[0], self.temps[1], self.temps[2],
+            self.wear[0] * 100.0, self.wear[1] * 100.0, self.wear[2] * 100.0,
+            self.pressure)
+    }
+}
+
+// The tyres of the player's car
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TyreState {
+    // In the order of `Corner::ALL`
+    pub corners: [TyreCorner; 4],
+}
+
+impl TyreState {
+    pub fn corner(&self, corner: Corner) -> &TyreCorner {
+        &self.corners[corner as usize]
+    }
+}
+
+impl fmt::Display for TyreState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let corners: Vec<String> = Corner::ALL.iter()
+            .map(|&corner| format!["{} {}", corner.prefix(), self.corner(corner)])
+            .collect();
+        write!(f, "{}", corners.join(", "))
+    }
+}
@@ -1,38 +1,121 @@
+mod config;
 mod iracing;
 mod overlay;
 mod plot;
 mod head2head;
 mod track;
+mod incidents;
+mod standings;
+mod relative;
+mod pit_strategy;
+mod setup_tracker;
+mod fuel;
+mod tyre_tracker;
+mod incident_tracker;
 
 #[macro_use] extern crate log;
 extern crate env_logger;
 extern crate yaml_rust;
 
+#[cfg(windows)]
 use windows::{
     Win32::System::Threading::*,
 };
 
 use async_std::task;
-use overlay::Overlays;
+use async_std::channel::Sender;
+use async_std::sync::{ Arc, Mutex };
+use overlay::{ Overlays, SharedStateTracker };
+use pit_strategy::{ PitPlan, PitStrategy };
+use setup_tracker::SetupTracker;
+use fuel::FuelTracker;
+use tyre_tracker::TyreTracker;
+
+use iracing::Update;
+use iracing::data_producer::IracingTask;
+use iracing::telemetry_source::{ TelemetrySource, SourceConfig, LiveSource, IbtSource, SyntheticSource };
+use iracing::ibt_writer::RecordingSource;
+use iracing::broadcast::{ CommandSink, RecordingCommandSink };
+
+use std::path::PathBuf;
+
+fn spawn_producer<S: TelemetrySource + 'static>(sender: Sender<Update>, source: S, record: Option<PathBuf>) -> task::JoinHandle<()> {
+    match record {
+        Some(directory) => {
+            let data_producer = IracingTask::new(sender, RecordingSource::new(source, directory));
+            task::spawn(async {
+                data_producer.execute().await
+            })
+        },
+        None => {
+            let data_producer = IracingTask::new(sender, source);
+            task::spawn(async {
+                data_producer.execute().await
+            })
+        },
+    }
+}
+
+#[cfg(windows)]
+fn command_sink() -> Arc<dyn CommandSink> {
+    match iracing::broadcast::WindowsCommandSink::new() {
+        Ok(sink) => Arc::new(sink),
+        Err(err) => {
+            error!["{}, commands to the sim are ignored", err];
+            Arc::new(RecordingCommandSink::new())
+        }
+    }
+}
+
+// Without the sim there is nobody to send commands to
+#[cfg(not(windows))]
+fn command_sink() -> Arc<dyn CommandSink> {
+    Arc::new(RecordingCommandSink::new())
+}
 
 fn main() {
     env_logger::Builder::from_default_env()
         .filter_level(log::LevelFilter::Debug)
         .init();
 
+    let config = match config::Config::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}\n{}", err, config::USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    #[cfg(windows)]
     unsafe {
         SetPriorityClass(GetCurrentProcess(), HIGH_PRIORITY_CLASS);
     }
 
     let (sender, receiver) = async_std::channel::unbounded();
 
-    // let data_producer = iracing::data_producer::TestTask::new(sender);
-    let data_producer = iracing::data_producer::IracingTask::new(sender);
-    let data_producer_thread = task::spawn(async {
-        data_producer.execute().await
-    });
+    let data_producer_thread = match config.source {
+        SourceConfig::Live { memory_file } => spawn_producer(sender, LiveSource::new(memory_file), config.record),
+        SourceConfig::Ibt { path, real_time, looping } => spawn_producer(sender, IbtSource::new(path, real_time, looping), config.record),
+        SourceConfig::Synthetic => spawn_producer(sender, SyntheticSource, config.record),
+    };
+
+    let mut state_trackers: Vec<SharedStateTracker> = vec![
+        Arc::new(Mutex::new(SetupTracker::new())),
+        Arc::new(Mutex::new(FuelTracker::new())),
+        Arc::new(Mutex::new(TyreTracker::new())),
+    ];
+    if let Some(path) = &config.pit_plan {
+        let plan = match PitPlan::load(path) {
+            Ok(plan) => plan,
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        };
+        state_trackers.push(Arc::new(Mutex::new(PitStrategy::new(plan, command_sink()))));
+    }
 
-    let overlays = Overlays::new(receiver);
+    let overlays = Overlays::new(receiver, state_trackers);
     overlays.start_event_loop();
 
     task::block_on(data_producer_thread);
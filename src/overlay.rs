@@ -16,9 +16,13 @@ use winit::event_loop::EventLoop;
 use winit::platform::run_return::EventLoopExtRunReturn;
 
 use crate::iracing::Update;
+use crate::iracing::license::License;
 use crate::plot::PlotOverlay;
 use crate::head2head::Head2HeadOverlay;
 use crate::track::TrackOverlay;
+use crate::incidents::IncidentOverlay;
+use crate::standings::StandingsOverlay;
+use crate::relative::RelativeOverlay;
 
 use async_trait::async_trait;
 
@@ -58,20 +62,59 @@ pub trait StateTracker {
     async fn process(&mut self, update: &Update);
 }
 
+pub type SharedStateTracker = Arc<Mutex<dyn StateTracker + Send + Sync>>;
+
+// Colors in the session info are 0xRRGGBB
+pub fn color_from_rgb(rgb: u32, alpha: f32) -> skia_safe::Color4f {
+    let channel = |shift: u32| ((rgb >> shift) & 0xff) as f32 / 255.0;
+    skia_safe::Color4f::new(channel(16), channel(8), channel(0), alpha)
+}
+
+// A monospaced font, so columns of text line up
+pub fn load_font(size: f32) -> skia_safe::Font {
+    let mut font_collection = skia_safe::textlayout::FontCollection::new();
+    font_collection.set_default_font_manager(skia_safe::FontMgr::new(), None);
+    let families = vec!["Consolas", "Monaco"];
+    let typeface = font_collection.find_typefaces(&families, skia_safe::FontStyle::normal()).pop()
+        .or_else(|| font_collection.default_fallback())
+        .expect("No font available for the overlays");
+
+    let mut font = skia_safe::Font::new(typeface, Some(size));
+    font.set_subpixel(true);
+    font
+}
+
+// A badge like the sim's, in the color of the license class. `y` is the baseline of the text.
+pub fn draw_license(canvas: &mut skia_safe::Canvas, license: &License, x: f32, y: f32, font: &skia_safe::Font) {
+    let size = font.size();
+    let background = skia_safe::Paint::new(color_from_rgb(license.color, 1.0), None);
+    canvas.draw_rect(skia_safe::Rect::from_xywh(x, y - size, 3.8 * size, size + 4.0), &background);
+    let text = skia_safe::Paint::new(color_from_rgb(license.text_color(), 1.0), None);
+    canvas.draw_str(&license.to_string(), skia_safe::Point::new(x + 2.0, y), font, &text);
+}
+
 impl Overlays {
-    pub fn new(state_receiver: Receiver<Update>) -> Overlays {
+    // Besides the trackers of the overlays, `extra_state_trackers` receive all updates as well
+    pub fn new(state_receiver: Receiver<Update>, extra_state_trackers: Vec<SharedStateTracker>) -> Overlays {
         let event_loop = EventLoop::<()>::with_user_event();
 
         let (plot_overlay, plot_overlay_state) = PlotOverlay::new();
         let (track_overlay, track_overlay_state) = TrackOverlay::new();
+        let (incident_overlay, incident_overlay_state) = IncidentOverlay::new();
+        let (standings_overlay, standings_overlay_state) = StandingsOverlay::new();
+        let (relative_overlay, relative_overlay_state) = RelativeOverlay::new();
         // let (head2head_overlay, head2head_overlay_state) = Head2HeadOverlay::new();
 
         let state_updater = async_std::task::spawn(async move {
-            let mut state_trackers: Vec<Arc<Mutex<dyn StateTracker + Send + Sync>>> = vec![
+            let mut state_trackers: Vec<SharedStateTracker> = vec![
                 Arc::new(Mutex::new(plot_overlay_state)),
                 Arc::new(Mutex::new(track_overlay_state)),
+                Arc::new(Mutex::new(incident_overlay_state)),
+                Arc::new(Mutex::new(standings_overlay_state)),
+                Arc::new(Mutex::new(relative_overlay_state)),
                 // Arc::new(Mutex::new(head2head_overlay_state),
             ];
+            state_trackers.extend(extra_state_trackers);
 
             while let Ok(update) = state_receiver.recv().await {
                 if let Update::Telemetry(telemetry) = &update {
@@ -101,6 +144,9 @@ impl Overlays {
         let overlays: Vec<Box<dyn Overlay>> = vec![
             Box::new(plot_overlay),
             Box::new(track_overlay),
+            Box::new(incident_overlay),
+            Box::new(standings_overlay),
+            Box::new(relative_overlay),
             // Box::new(head2head_overlay),
         ];
         let windows: Vec<_> = overlays.iter()
@@ -0,0 +1,371 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use yaml_rust::{ YamlLoader, Yaml };
+
+use async_trait::async_trait;
+
+use crate::overlay::StateTracker;
+use crate::iracing::{ Update, Telemetry };
+use crate::iracing::broadcast::{ CommandSink, SimCommand, PitCommand };
+use crate::iracing::flags::{ PitSvFlags, PitServiceStatus, TrackLocation };
+
+// Seconds of session time the sim gets to reflect our commands in the pit menu before they are
+// sent again. Session time rather than wall clock time, so replays faster than real time work too.
+const VERIFY_TIMEOUT: f64 = 1.0;
+const MAX_ATTEMPTS: u32 = 3;
+
+// What the pit crew should do at the next stop, e.g.
+//
+// fuel: 40                  # liters to add, leave out to not refuel
+// tyres: [lf, rf, lr, rr]   # or `all`
+// fast_repair: false
+// windshield: true
+#[derive(Debug, Clone, PartialEq)]
+pub struct PitPlan {
+    pub fuel: Option<u32>,
+    pub tyres: PitSvFlags,
+    pub fast_repair: bool,
+    pub windshield: bool,
+}
+
+impl PitPlan {
+    pub fn load(path: &Path) -> Result<PitPlan, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| format!["Failed to read pit plan {}: {}", path.display(), err])?;
+        PitPlan::from_yaml(&content)
+            .map_err(|err| format!["Invalid pit plan {}: {}", path.display(), err])
+    }
+
+    pub fn from_yaml(content: &str) -> Result<PitPlan, String> {
+        let documents = YamlLoader::load_from_str(content)
+            .map_err(|err| format!("Failed to parse yaml: {:?}", err))?;
+        let plan = match documents.first() {
+            Some(plan) => plan,
+            None => return Err("The plan is empty".to_string()),
+        };
+
+        let fuel = match &plan["fuel"] {
+            Yaml::Integer(liters) if *liters > 0 => Some(*liters as u32),
+            Yaml::Integer(_) | Yaml::BadValue | Yaml::Null => None,
+            other => return Err(format!["fuel must be a whole number of liters, got {:?}", other]),
+        };
+
+        let tyre_flag = |name: &str| match name {
+            "lf" => Ok(PitSvFlags::LFTireChange),
+            "rf" => Ok(PitSvFlags::RFTireChange),
+            "lr" => Ok(PitSvFlags::LRTireChange),
+            "rr" => Ok(PitSvFlags::RRTireChange),
+            "all" => Ok(PitSvFlags::LFTireChange | PitSvFlags::RFTireChange | PitSvFlags::LRTireChange | PitSvFlags::RRTireChange),
+            unknown => Err(format!["Unknown tyre {}, expected one of lf, rf, lr, rr or all", unknown]),
+        };
+        let tyres = match &plan["tyres"] {
+            Yaml::String(name) => tyre_flag(name)?,
+            Yaml::Array(names) => names.iter().try_fold(PitSvFlags::default(), |tyres, name| {
+                let name = name.as_str().ok_or("tyres must be a list of names")?;
+                Ok::<_, String>(tyres | tyre_flag(name)?)
+            })?,
+            Yaml::BadValue | Yaml::Null => PitSvFlags::default(),
+            other => return Err(format!["tyres must be a list of names, got {:?}", other]),
+        };
+
+        let flag = |name: &str| match &plan[name] {
+            Yaml::Boolean(value) => Ok(*value),
+            Yaml::BadValue | Yaml::Null => Ok(false),
+            other => Err(format!["{} must be true or false, got {:?}", name, other]),
+        };
+
+        Ok(PitPlan {
+            fuel,
+            tyres,
+            fast_repair: flag("fast_repair")?,
+            windshield: flag("windshield")?,
+        })
+    }
+
+    // The pit menu flags the plan results in
+    pub fn service_flags(&self) -> PitSvFlags {
+        let mut flags = self.tyres;
+        if self.fuel.is_some() {
+            flags = flags | PitSvFlags::FuelFill;
+        }
+        if self.fast_repair {
+            flags = flags | PitSvFlags::FastRepair;
+        }
+        if self.windshield {
+            flags = flags | PitSvFlags::WindshieldTearoff;
+        }
+        flags
+    }
+
+    pub fn commands(&self) -> Vec<SimCommand> {
+        // Start from a clean pit menu, so only what is in the plan gets done
+        let mut commands = vec![PitCommand::Clear];
+        if let Some(liters) = self.fuel {
+            commands.push(PitCommand::Fuel(Some(liters)));
+        }
+        for (tyre, command) in [
+            (PitSvFlags::LFTireChange, PitCommand::LeftFront(None)),
+            (PitSvFlags::RFTireChange, PitCommand::RightFront(None)),
+            (PitSvFlags::LRTireChange, PitCommand::LeftRear(None)),
+            (PitSvFlags::RRTireChange, PitCommand::RightRear(None)),
+        ] {
+            if self.tyres.contains(tyre) {
+                commands.push(command);
+            }
+        }
+        if self.fast_repair {
+            commands.push(PitCommand::FastRepair);
+        }
+        if self.windshield {
+            commands.push(PitCommand::Windshield);
+        }
+
+        commands.into_iter().map(SimCommand::Pit).collect()
+    }
+
+    pub fn is_applied(&self, telemetry: &Telemetry) -> bool {
+        if telemetry.pit_service_flags != self.service_flags() {
+            return false;
+        }
+        match self.fuel {
+            Some(liters) => (telemetry.pit_service_fuel - liters as f32).abs() < 0.5,
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PitStopState {
+    // Out on track, waiting for the approach to pit entry
+    Waiting,
+    // The plan was sent, waiting for the pit menu to reflect it
+    Requested { attempt: u32, since: f64 },
+    // The pit menu matches the plan
+    Confirmed,
+    // The pit menu still differs from the plan after all attempts
+    Mismatch { expected: PitSvFlags, actual: PitSvFlags },
+    Servicing,
+    // Nothing more is sent until the car is back out on track
+    Done,
+}
+
+// Fills in the pit menu from a plan when the player approaches pit entry, and checks
+// that the sim actually picked it up
+pub struct PitStrategy {
+    plan: PitPlan,
+    sink: Arc<dyn CommandSink>,
+    state: PitStopState,
+    last_service_status: PitServiceStatus,
+}
+
+impl PitStrategy {
+    pub fn new(plan: PitPlan, sink: Arc<dyn CommandSink>) -> PitStrategy {
+        PitStrategy {
+            plan,
+            sink,
+            state: PitStopState::Waiting,
+            last_service_status: PitServiceStatus::None,
+        }
+    }
+
+    fn send_plan(&self) {
+        for command in self.plan.commands() {
+            if let Err(err) = self.sink.send(command) {
+                error!["Failed to send pit command: {}", err];
+            }
+        }
+    }
+
+    fn update(&mut self, telemetry: &Telemetry) {
+        if telemetry.pit_service_status != self.last_service_status {
+            self.last_service_status = telemetry.pit_service_status;
+            if telemetry.pit_service_status.is_error() {
+                warn!["Pit service problem: {:?}", telemetry.pit_service_status];
+            }
+        }
+
+        let back_on_track = telemetry.track_location == TrackLocation::OnTrack && !telemetry.on_pit_road;
+        if back_on_track && self.state != PitStopState::Waiting {
+            if !matches!(self.state, PitStopState::Done) {
+                info!["Left the pits without being serviced"];
+            }
+            self.state = PitStopState::Waiting;
+        }
+
+        self.state = match self.state {
+            PitStopState::Waiting => {
+                // Pit commands are only accepted while in the car
+                if telemetry.is_on_track && telemetry.track_location == TrackLocation::ApproachingPits {
+                    info!["Approaching pit entry, requesting {:?}", self.plan];
+                    self.send_plan();
+                    PitStopState::Requested { attempt: 1, since: telemetry.session_time }
+                } else {
+                    PitStopState::Waiting
+                }
+            },
+            PitStopState::Requested { .. } | PitStopState::Confirmed | PitStopState::Mismatch { .. }
+                if telemetry.pit_service_status == PitServiceStatus::InProgress => {
+                PitStopState::Servicing
+            },
+            PitStopState::Requested { attempt, since } => {
+                if self.plan.is_applied(telemetry) {
+                    info!["Pit menu confirmed: {:?}, fuel {:.1}", telemetry.pit_service_flags, telemetry.pit_service_fuel];
+                    PitStopState::Confirmed
+                } else if (since..since + VERIFY_TIMEOUT).contains(&telemetry.session_time) {
+                    PitStopState::Requested { attempt, since }
+                } else if attempt < MAX_ATTEMPTS {
+                    warn!["Pit menu does not match the plan yet, sending it again"];
+                    self.send_plan();
+                    PitStopState::Requested { attempt: attempt + 1, since: telemetry.session_time }
+                } else {
+                    error!["Pit menu still differs from the plan after {} attempts: expected {:?}, got {:?}",
+                        attempt, self.plan.service_flags(), telemetry.pit_service_flags];
+                    PitStopState::Mismatch { expected: self.plan.service_flags(), actual: telemetry.pit_service_flags }
+                }
+            },
+            PitStopState::Servicing if telemetry.pit_service_status == PitServiceStatus::Complete => {
+                info!["Pit service complete"];
+                PitStopState::Done
+            },
+            state => state,
+        };
+    }
+}
+
+#[async_trait]
+impl StateTracker for PitStrategy {
+    async fn process(&mut self, update: &Update) {
+        if let Update::Telemetry(telemetry) = update {
+            self.update(telemetry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iracing::broadcast::RecordingCommandSink;
+
+    const PLAN: &str = "fuel: 40\ntyres: [lf, rf]\nfast_repair: false\nwindshield: true\n";
+
+    fn plan() -> PitPlan {
+        PitPlan::from_yaml(PLAN).unwrap()
+    }
+
+    fn on_track(session_time: f64) -> Telemetry {
+        Telemetry {
+            session_time,
+            is_on_track: true,
+            track_location: TrackLocation::OnTrack,
+            ..Telemetry::default()
+        }
+    }
+
+    fn approaching_pits(session_time: f64) -> Telemetry {
+        Telemetry { track_location: TrackLocation::ApproachingPits, ..on_track(session_time) }
+    }
+
+    #[test]
+    fn parses_plans() {
+        assert_eq!(plan(), PitPlan {
+            fuel: Some(40),
+            tyres: PitSvFlags::LFTireChange | PitSvFlags::RFTireChange,
+            fast_repair: false,
+            windshield: true,
+        });
+
+        let all = PitPlan::from_yaml("tyres: all\nfuel: 0\n").unwrap();
+        assert_eq!(all.fuel, None);
+        assert!(all.tyres.contains(PitSvFlags::LRTireChange | PitSvFlags::RRTireChange));
+
+        assert!(PitPlan::from_yaml("").is_err());
+        assert!(PitPlan::from_yaml("fuel: lots\n").is_err());
+        assert!(PitPlan::from_yaml("tyres: [lf, xx]\n").is_err());
+        assert!(PitPlan::from_yaml("windshield: 1\n").is_err());
+    }
+
+    #[test]
+    fn clears_the_menu_before_the_plan() {
+        assert_eq!(plan().commands(), vec![
+            SimCommand::Pit(PitCommand::Clear),
+            SimCommand::Pit(PitCommand::Fuel(Some(40))),
+            SimCommand::Pit(PitCommand::LeftFront(None)),
+            SimCommand::Pit(PitCommand::RightFront(None)),
+            SimCommand::Pit(PitCommand::Windshield),
+        ]);
+    }
+
+    #[test]
+    fn checks_the_pit_menu_against_the_plan() {
+        let plan = plan();
+        let mut telemetry = Telemetry {
+            pit_service_flags: plan.service_flags(),
+            pit_service_fuel: 40.2,
+            ..Telemetry::default()
+        };
+        assert!(plan.is_applied(&telemetry));
+
+        telemetry.pit_service_fuel = 35.0;
+        assert!(!plan.is_applied(&telemetry));
+
+        telemetry.pit_service_fuel = 40.0;
+        telemetry.pit_service_flags = telemetry.pit_service_flags | PitSvFlags::FastRepair;
+        assert!(!plan.is_applied(&telemetry));
+    }
+
+    #[test]
+    fn confirms_the_plan_on_approach() {
+        let sink = Arc::new(RecordingCommandSink::new());
+        let mut strategy = PitStrategy::new(plan(), sink.clone());
+
+        strategy.update(&on_track(100.0));
+        assert_eq!(strategy.state, PitStopState::Waiting);
+        assert!(sink.commands().is_empty());
+
+        strategy.update(&approaching_pits(101.0));
+        assert_eq!(strategy.state, PitStopState::Requested { attempt: 1, since: 101.0 });
+        assert_eq!(sink.commands(), plan().commands());
+
+        let applied = Telemetry {
+            pit_service_flags: plan().service_flags(),
+            pit_service_fuel: 40.0,
+            on_pit_road: true,
+            ..approaching_pits(101.2)
+        };
+        strategy.update(&applied);
+        assert_eq!(strategy.state, PitStopState::Confirmed);
+
+        strategy.update(&Telemetry { pit_service_status: PitServiceStatus::InProgress, ..applied.clone() });
+        assert_eq!(strategy.state, PitStopState::Servicing);
+        strategy.update(&Telemetry { pit_service_status: PitServiceStatus::Complete, ..applied });
+        assert_eq!(strategy.state, PitStopState::Done);
+
+        strategy.update(&on_track(160.0));
+        assert_eq!(strategy.state, PitStopState::Waiting);
+        assert_eq!(sink.commands().len(), plan().commands().len());
+    }
+
+    #[test]
+    fn retries_by_session_time() {
+        let sink = Arc::new(RecordingCommandSink::new());
+        let mut strategy = PitStrategy::new(plan(), sink.clone());
+        let commands = plan().commands().len();
+
+        strategy.update(&approaching_pits(10.0));
+        strategy.update(&approaching_pits(10.9));
+        assert_eq!(sink.commands().len(), commands);
+
+        strategy.update(&approaching_pits(11.0));
+        assert_eq!(strategy.state, PitStopState::Requested { attempt: 2, since: 11.0 });
+        assert_eq!(sink.commands().len(), 2 * commands);
+
+        strategy.update(&approaching_pits(12.0));
+        strategy.update(&approaching_pits(13.0));
+        assert_eq!(sink.commands().len(), 3 * commands);
+        assert_eq!(strategy.state, PitStopState::Mismatch {
+            expected: plan().service_flags(),
+            actual: PitSvFlags::default(),
+        });
+    }
+}
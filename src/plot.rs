@@ -8,7 +8,7 @@ use skia_vulkan::skia_safe;
 
 
 use crate::overlay::{ Overlay, Drawable, StateUpdater, StateTracker, WindowSpec };
-use crate::iracing::{ Update, Telemetry };
+use crate::iracing::{ Update, Telemetry, ConnectionStatus };
 
 use async_trait::async_trait;
 
@@ -128,6 +128,13 @@ impl StateTracker for PlotStateTracker {
 
                 measurement_sender.await.unwrap();
             },
+            Update::Connection(status) => {
+                let connected = matches!(status, ConnectionStatus::Connected { .. });
+                if !connected && self.is_visible {
+                    self.sender.send(StateUpdate::WindowVisible(false)).await.unwrap();
+                    self.is_visible = false;
+                }
+            },
             _ => (),
         }
     }
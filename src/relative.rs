@@ -0,0 +1,204 @@
+use async_std::channel;
+use async_std::channel::{ Sender, Receiver };
+use skia_vulkan::winit::window::Window;
+
+use skia_vulkan::skia_safe;
+use skia_vulkan::skia_safe::{ Paint, Point, Rect };
+
+use crate::overlay::{ Overlay, Drawable, StateUpdater, StateTracker, WindowSpec, color_from_rgb, draw_license, load_font };
+use crate::iracing::{ Update, Telemetry, SessionInfo, ConnectionStatus };
+use crate::iracing::license::License;
+
+use async_trait::async_trait;
+
+const LINE_HEIGHT: f32 = 20.0;
+// Number of cars shown on either side of the player
+const AROUND: usize = 3;
+
+#[derive(Debug, Clone)]
+struct Row {
+    class_color: u32,
+    // Within the class
+    position: i32,
+    car_number: String,
+    name: String,
+    license: Option<License>,
+    // Positive for cars ahead on track
+    seconds: f32,
+    // Laps the car is ahead of the player in the race, negative when behind
+    laps: i32,
+    is_player: bool,
+}
+
+enum StateUpdate {
+    // Ahead on track first
+    Relative(Vec<Row>),
+    WindowVisible(bool),
+}
+
+pub struct RelativeOverlay {
+    rows: Vec<Row>,
+    receiver: Receiver<StateUpdate>,
+
+    font: skia_safe::Font,
+    paint_text: Paint,
+    paint_player: Paint,
+    paint_lap_ahead: Paint,
+    paint_lap_behind: Paint,
+}
+
+pub struct RelativeOverlayState {
+    session_info: Option<SessionInfo>,
+    sender: Sender<StateUpdate>,
+    is_visible: bool,
+}
+
+impl RelativeOverlay {
+    pub fn new() -> (RelativeOverlay, RelativeOverlayState) {
+        let (sender, receiver) = channel::unbounded();
+        (
+            RelativeOverlay {
+                rows: vec![],
+                receiver,
+
+                font: load_font(14.0),
+                paint_text: Paint::new(skia_safe::Color4f::new(1.0, 1.0, 1.0, 1.0), None),
+                paint_player: Paint::new(skia_safe::Color4f::new(0.9, 0.9, 0.2, 1.0), None),
+                paint_lap_ahead: Paint::new(skia_safe::Color4f::new(1.0, 0.4, 0.4, 1.0), None),
+                paint_lap_behind: Paint::new(skia_safe::Color4f::new(0.4, 0.6, 1.0, 1.0), None),
+            },
+            RelativeOverlayState {
+                session_info: None,
+                sender,
+                is_visible: false,
+            },
+        )
+    }
+}
+
+impl Overlay for RelativeOverlay {
+    fn window_spec(&self) -> WindowSpec {
+        WindowSpec {
+            title: "Relative".to_string(),
+            width: 380.0,
+            height: LINE_HEIGHT * (2 * AROUND + 1) as f32 + 8.0,
+        }
+    }
+}
+
+impl Drawable for RelativeOverlay {
+    fn draw(&mut self, canvas: &mut skia_safe::Canvas, window_size: (u32, u32)) {
+        canvas.clear(skia_safe::Color::from_argb(150, 0, 0, 0));
+
+        for (i, row) in self.rows.iter().enumerate() {
+            let y = LINE_HEIGHT * (i + 1) as f32;
+            let class_paint = Paint::new(color_from_rgb(row.class_color, 1.0), None);
+            canvas.draw_rect(Rect::from_xywh(2.0, y - LINE_HEIGHT + 4.0, 4.0, LINE_HEIGHT - 4.0), &class_paint);
+
+            let name: String = row.name.chars().take(16).collect();
+            let line = format!["{:>2} {:>4} {:<16} {:>+5.1}", row.position, format!["#{}", row.car_number], name, row.seconds];
+            let paint = if row.is_player {
+                &self.paint_player
+            } else if row.laps > 0 {
+                &self.paint_lap_ahead
+            } else if row.laps < 0 {
+                &self.paint_lap_behind
+            } else {
+                &self.paint_text
+            };
+            canvas.draw_str(&line, Point::new(10.0, y - 4.0), &self.font, paint);
+            if let Some(license) = &row.license {
+                draw_license(canvas, license, window_size.0 as f32 - 60.0, y - 4.0, &self.font);
+            }
+        }
+    }
+}
+
+// The cars closest to the player on track, regardless of their class or position
+fn relative(session_info: &SessionInfo, telemetry: &Telemetry) -> Vec<Row> {
+    let player = session_info.driver.car_idx;
+    let dist = |car_idx: usize| telemetry.lap_dist_by_car.get(car_idx).copied().unwrap_or(-1.0);
+    let progress = |car_idx: usize| telemetry.car_laps_completed.get(car_idx)
+        .map_or(0.0, |&laps| laps.max(0) as f32) + dist(car_idx);
+    let lap_time = match session_info.class_of(player) {
+        Some(class) if class.est_lap_time > 0.0 && dist(player) >= 0.0 => class.est_lap_time,
+        _ => return vec![],
+    };
+
+    let mut rows: Vec<Row> = session_info.drivers.iter()
+        .filter(|driver| dist(driver.car_idx) >= 0.0)
+        .filter_map(|driver| {
+            let class = session_info.class_of(driver.car_idx)?;
+            // Half a lap ahead is as far as it goes, further than that the car is closer from behind
+            let on_track = (dist(driver.car_idx) - dist(player) + 0.5).rem_euclid(1.0) - 0.5;
+            let est_time = |car_idx: usize| telemetry.car_est_times.get(car_idx).copied();
+            let seconds = match (est_time(driver.car_idx), est_time(player)) {
+                // The estimate is for the class of the car, so it is first turned into how long the
+                // class of the player takes to the same point
+                (Some(other), Some(own)) if class.est_lap_time > 0.0 => {
+                    let other = other / class.est_lap_time * lap_time;
+                    (other - own + lap_time / 2.0).rem_euclid(lap_time) - lap_time / 2.0
+                },
+                _ => on_track * lap_time,
+            };
+
+            Some(Row {
+                class_color: class.color,
+                position: telemetry.car_class_positions.get(driver.car_idx).copied().unwrap_or(0),
+                car_number: driver.car_number.clone(),
+                name: driver.name.clone(),
+                license: driver.license,
+                seconds,
+                laps: (progress(driver.car_idx) - progress(player) - on_track).round() as i32,
+                is_player: driver.car_idx == player,
+            })
+        })
+        .collect();
+    rows.sort_by(|a, b| b.seconds.total_cmp(&a.seconds));
+
+    let player_row = match rows.iter().position(|row| row.is_player) {
+        Some(player_row) => player_row,
+        None => return vec![],
+    };
+    let from = player_row.saturating_sub(AROUND);
+    let to = (player_row + AROUND + 1).min(rows.len());
+    rows[from..to].to_vec()
+}
+
+#[async_trait]
+impl StateTracker for RelativeOverlayState {
+    async fn process(&mut self, update: &Update) {
+        match update {
+            Update::Session(session_info) => self.session_info = Some(session_info.clone()),
+            Update::Telemetry(telemetry) => {
+                if let Some(session_info) = &self.session_info {
+                    self.sender.send(StateUpdate::Relative(relative(session_info, telemetry))).await.unwrap();
+                }
+
+                if self.is_visible != telemetry.is_on_track {
+                    self.sender.send(StateUpdate::WindowVisible(telemetry.is_on_track)).await.unwrap();
+                    self.is_visible = telemetry.is_on_track;
+                }
+            },
+            Update::Connection(status) => {
+                let connected = matches!(status, ConnectionStatus::Connected { .. });
+                if !connected && self.is_visible {
+                    self.sender.send(StateUpdate::WindowVisible(false)).await.unwrap();
+                    self.is_visible = false;
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+impl StateUpdater for RelativeOverlay {
+    fn set_state(&mut self, window: &Window) {
+        while let Ok(update) = self.receiver.try_recv() {
+            match update {
+                StateUpdate::Relative(rows) => self.rows = rows,
+                StateUpdate::WindowVisible(visible) => window.set_visible(visible),
+            }
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::time::Instant;
+
+use async_trait::async_trait;
+
+use crate::overlay::StateTracker;
+use crate::iracing::Update;
+use crate::iracing::car_setup::{ CarSetup, SetupReport };
+
+#[derive(Debug, Clone)]
+pub struct SetupSnapshot {
+    // None when the sim does not say which session is running
+    pub session_num: Option<i32>,
+    pub taken_at: Instant,
+    pub setup: CarSetup,
+}
+
+// Keeps every setup the player ran, so the changes between stints can be reported
+pub struct SetupTracker {
+    snapshots: Vec<SetupSnapshot>,
+}
+
+impl SetupTracker {
+    pub fn new() -> SetupTracker {
+        SetupTracker { snapshots: vec![] }
+    }
+
+    pub fn snapshots(&self) -> &[SetupSnapshot] {
+        &self.snapshots
+    }
+
+    // The setup last used in the given session
+    pub fn latest(&self, session_num: i32) -> Option<&SetupSnapshot> {
+        self.snapshots.iter().rev().find(|snapshot| snapshot.session_num == Some(session_num))
+    }
+
+    // What changed between two of the snapshots, in the order they were taken
+    pub fn report(&self, from: usize, to: usize) -> Option<SetupReport> {
+        Some(SetupReport::diff(&self.snapshots.get(from)?.setup, &self.snapshots.get(to)?.setup))
+    }
+
+    fn update(&mut self, session_num: Option<i32>, setup: &CarSetup) {
+        let report = match self.snapshots.last() {
+            Some(last) if last.session_num == session_num && last.setup == *setup => return,
+            Some(last) => Some(SetupReport::diff(&last.setup, setup)),
+            None => None,
+        };
+
+        match report.map(|report| report.adjustments()) {
+            Some(adjustments) if !adjustments.is_empty() => info!["Setup changed: {}", adjustments],
+            Some(_) => (),
+            None => info!["Running setup with {} values", setup.values().len()],
+        }
+
+        self.snapshots.push(SetupSnapshot {
+            session_num,
+            taken_at: Instant::now(),
+            setup: setup.clone(),
+        });
+    }
+}
+
+#[async_trait]
+impl StateTracker for SetupTracker {
+    async fn process(&mut self, update: &Update) {
+        if let Update::Session(session_info) = update {
+            if let Some(setup) = &session_info.car_setup {
+                self.update(session_info.current_session_num, setup);
+            }
+        }
+    }
+}
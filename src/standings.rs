@@ -0,0 +1,227 @@
+use async_std::channel;
+use async_std::channel::{ Sender, Receiver };
+use skia_vulkan::winit::window::Window;
+
+use skia_vulkan::skia_safe;
+use skia_vulkan::skia_safe::{ Paint, Point, Rect };
+
+use crate::overlay::{ Overlay, Drawable, StateUpdater, StateTracker, WindowSpec, color_from_rgb, draw_license, load_font };
+use crate::iracing::{ Update, Telemetry, SessionInfo, ConnectionStatus };
+use crate::iracing::license::License;
+use crate::iracing::gaps::Gap;
+use crate::iracing::{ irating, sof };
+
+use async_trait::async_trait;
+
+const LINE_HEIGHT: f32 = 20.0;
+
+#[derive(Debug, Clone)]
+struct Row {
+    // Within the class
+    position: i32,
+    car_number: String,
+    name: String,
+    license: Option<License>,
+    to_leader: Option<Gap>,
+    interval: Option<Gap>,
+    // Only in races
+    irating_change: Option<i32>,
+    is_player: bool,
+}
+
+#[derive(Debug, Clone)]
+struct ClassStandings {
+    short_name: String,
+    color: u32,
+    strength_of_field: Option<i32>,
+    rows: Vec<Row>,
+}
+
+enum StateUpdate {
+    // Fastest class first
+    Standings(Vec<ClassStandings>),
+    // Of the whole field
+    StrengthOfField(Option<i32>),
+    WindowVisible(bool),
+}
+
+pub struct StandingsOverlay {
+    standings: Vec<ClassStandings>,
+    strength_of_field: Option<i32>,
+    receiver: Receiver<StateUpdate>,
+
+    font: skia_safe::Font,
+    paint_text: Paint,
+    paint_player: Paint,
+}
+
+pub struct StandingsOverlayState {
+    session_info: Option<SessionInfo>,
+    strength_of_field: Option<i32>,
+    sender: Sender<StateUpdate>,
+    is_visible: bool,
+}
+
+impl StandingsOverlay {
+    pub fn new() -> (StandingsOverlay, StandingsOverlayState) {
+        let (sender, receiver) = channel::unbounded();
+        (
+            StandingsOverlay {
+                standings: vec![],
+                strength_of_field: None,
+                receiver,
+
+                font: load_font(14.0),
+                paint_text: Paint::new(skia_safe::Color4f::new(1.0, 1.0, 1.0, 1.0), None),
+                paint_player: Paint::new(skia_safe::Color4f::new(0.9, 0.9, 0.2, 1.0), None),
+            },
+            StandingsOverlayState {
+                session_info: None,
+                strength_of_field: None,
+                sender,
+                is_visible: false,
+            },
+        )
+    }
+}
+
+impl Overlay for StandingsOverlay {
+    fn window_spec(&self) -> WindowSpec {
+        WindowSpec {
+            title: "Standings".to_string(),
+            width: 470.0,
+            height: 500.0,
+        }
+    }
+}
+
+fn format_gap(gap: &Option<Gap>) -> String {
+    gap.map_or(String::new(), |gap| gap.to_string())
+}
+
+impl Drawable for StandingsOverlay {
+    fn draw(&mut self, canvas: &mut skia_safe::Canvas, window_size: (u32, u32)) {
+        canvas.clear(skia_safe::Color::from_argb(150, 0, 0, 0));
+
+        let show_classes = self.standings.len() > 1;
+        let mut y = LINE_HEIGHT;
+        if let Some(strength_of_field) = self.strength_of_field {
+            canvas.draw_str(&format!["SOF {}", strength_of_field], Point::new(8.0, y - 4.0), &self.font, &self.paint_text);
+            y += LINE_HEIGHT;
+        }
+        for class in &self.standings {
+            let class_paint = Paint::new(color_from_rgb(class.color, 1.0), None);
+            if show_classes {
+                let strength_of_field = class.strength_of_field.map_or(String::new(), |sof| format!["SOF {}", sof]);
+                let header = format!["{} {}", class.short_name, strength_of_field];
+                canvas.draw_str(&header, Point::new(8.0, y - 4.0), &self.font, &class_paint);
+                y += LINE_HEIGHT;
+            }
+
+            for row in &class.rows {
+                if y > window_size.1 as f32 {
+                    return;
+                }
+
+                canvas.draw_rect(Rect::from_xywh(2.0, y - LINE_HEIGHT + 4.0, 4.0, LINE_HEIGHT - 4.0), &class_paint);
+                let name: String = row.name.chars().take(16).collect();
+                let irating_change = row.irating_change.map_or(String::new(), |change| format!["{:+}", change]);
+                let line = format!["{:>2} {:>4} {:<16} {:>6} {:>6} {:>4}",
+                    row.position, format!["#{}", row.car_number], name,
+                    format_gap(&row.to_leader), format_gap(&row.interval), irating_change];
+                let paint = if row.is_player { &self.paint_player } else { &self.paint_text };
+                canvas.draw_str(&line, Point::new(10.0, y - 4.0), &self.font, paint);
+                if let Some(license) = &row.license {
+                    draw_license(canvas, license, window_size.0 as f32 - 60.0, y - 4.0, &self.font);
+                }
+                y += LINE_HEIGHT;
+            }
+        }
+    }
+}
+
+// The racing cars of every class, ordered by their position in the class
+fn standings(session_info: &SessionInfo, telemetry: &Telemetry) -> Vec<ClassStandings> {
+    let class_position = |car_idx: usize| telemetry.car_class_positions.get(car_idx).copied().unwrap_or(0);
+    let is_race = session_info.current_session().map_or(false, |session| session.session_type == "Race");
+    let irating_changes = if is_race { irating::estimate_field(session_info, telemetry) } else { vec![] };
+
+    session_info.classes.iter()
+        .map(|class| {
+            let mut rows: Vec<Row> = session_info.drivers.iter()
+                .filter(|driver| session_info.class_of(driver.car_idx).map(|class| class.id) == Some(class.id))
+                .map(|driver| {
+                    let gaps = telemetry.gaps.get(driver.car_idx).copied().unwrap_or_default();
+                    Row {
+                        position: class_position(driver.car_idx),
+                        car_number: driver.car_number.clone(),
+                        name: driver.name.clone(),
+                        license: driver.license,
+                        to_leader: gaps.class_to_leader,
+                        interval: gaps.class_interval,
+                        irating_change: irating_changes.iter()
+                            .find(|change| change.car_idx == driver.car_idx)
+                            .map(|change| change.change),
+                        is_player: driver.car_idx == session_info.driver.car_idx,
+                    }
+                })
+                .collect();
+            // Cars without a position yet go last
+            rows.sort_by_key(|row| if row.position > 0 { row.position } else { i32::MAX });
+
+            ClassStandings {
+                short_name: class.short_name.clone(),
+                color: class.color,
+                strength_of_field: sof::class_strength_of_field(session_info, class.id),
+                rows,
+            }
+        })
+        .collect()
+}
+
+#[async_trait]
+impl StateTracker for StandingsOverlayState {
+    async fn process(&mut self, update: &Update) {
+        match update {
+            Update::Session(session_info) => {
+                // Follows drivers joining and leaving
+                let strength_of_field = sof::session_strength_of_field(session_info);
+                if strength_of_field != self.strength_of_field {
+                    self.strength_of_field = strength_of_field;
+                    self.sender.send(StateUpdate::StrengthOfField(strength_of_field)).await.unwrap();
+                }
+                self.session_info = Some(session_info.clone());
+            },
+            Update::Telemetry(telemetry) => {
+                if let Some(session_info) = &self.session_info {
+                    self.sender.send(StateUpdate::Standings(standings(session_info, telemetry))).await.unwrap();
+                }
+
+                if self.is_visible != telemetry.is_on_track {
+                    self.sender.send(StateUpdate::WindowVisible(telemetry.is_on_track)).await.unwrap();
+                    self.is_visible = telemetry.is_on_track;
+                }
+            },
+            Update::Connection(status) => {
+                let connected = matches!(status, ConnectionStatus::Connected { .. });
+                if !connected && self.is_visible {
+                    self.sender.send(StateUpdate::WindowVisible(false)).await.unwrap();
+                    self.is_visible = false;
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+impl StateUpdater for StandingsOverlay {
+    fn set_state(&mut self, window: &Window) {
+        while let Ok(update) = self.receiver.try_recv() {
+            match update {
+                StateUpdate::Standings(standings) => self.standings = standings,
+                StateUpdate::StrengthOfField(strength_of_field) => self.strength_of_field = strength_of_field,
+                StateUpdate::WindowVisible(visible) => window.set_visible(visible),
+            }
+        }
+    }
+}
@@ -9,8 +9,8 @@ use skia_vulkan::skia_safe::Paint;
 use skia_vulkan::skia_safe::ContourMeasureIter;
 use skia_vulkan::winit::window::Window;
 
-use crate::overlay::{ Overlay, Drawable, StateUpdater, StateTracker, WindowSpec };
-use crate::iracing::{ Update, TrackSpec };
+use crate::overlay::{ Overlay, Drawable, StateUpdater, StateTracker, WindowSpec, color_from_rgb };
+use crate::iracing::{ Update, TrackSpec, ConnectionStatus };
 
 use async_std::fs::File;
 use async_std::prelude::*;
@@ -22,6 +22,10 @@ use async_trait::async_trait;
 pub struct State {
     cars_lap_dist: Vec<f32>,
     cars_position: Vec<i32>,
+    cars_class_position: Vec<i32>,
+    // Class id and color of each car, None for the pace car
+    cars_class: Vec<Option<(i64, u32)>>,
+    multiclass: bool,
     driver_idx: usize,
     track: Option<Track>,
 }
@@ -53,6 +57,9 @@ impl TrackOverlay {
         let start_state = State {
             cars_lap_dist: vec![],
             cars_position: vec![],
+            cars_class_position: vec![],
+            cars_class: vec![],
+            multiclass: false,
             driver_idx: 0,
             track: None,
         };
@@ -140,11 +147,21 @@ impl Drawable for TrackOverlay {
             if let Some(measure) = measures.next() {
                 let length = measure.length();
 
+                let class = |car_idx: usize| self.state.cars_class.get(car_idx).copied().flatten();
+                let multiclass = self.state.multiclass;
+                // Ahead and behind are within the class of the player, other classes are drawn in their own color
+                let positions = if multiclass { &self.state.cars_class_position } else { &self.state.cars_position };
+                let position = |car_idx: usize| positions.get(car_idx).copied().unwrap_or(0);
+
                 for (car_idx, car_dist) in self.state.cars_lap_dist.iter().enumerate() {
                     if let Some((point, _tangent)) = measure.pos_tan((1.0 - car_dist) * length) {
+                        let class_paint;
                         let car_paint = if car_idx == self.state.driver_idx {
                             &self.paint_current_driver
-                        } else if self.state.cars_position[car_idx] < self.state.cars_position[self.state.driver_idx] {
+                        } else if multiclass && class(car_idx).map(|(id, _)| id) != class(self.state.driver_idx).map(|(id, _)| id) {
+                            class_paint = Paint::new(color_from_rgb(class(car_idx).map_or(0x808080, |(_, color)| color), 1.0), None);
+                            &class_paint
+                        } else if position(car_idx) < position(self.state.driver_idx) {
                             &self.paint_cars_front
                         } else {
                             &self.paint_cars_behind
@@ -166,6 +183,7 @@ impl StateTracker for TrackOverlayState {
             Update::Telemetry(telemetry) => {
                 new_state.cars_lap_dist = telemetry.lap_dist_by_car.clone();
                 new_state.cars_position = telemetry.car_positions.clone();
+                new_state.cars_class_position = telemetry.car_class_positions.clone();
 
                 if telemetry.is_on_track != self.is_on_track {
                     self.sender.send(StateUpdate::WindowVisible(telemetry.is_on_track)).await.unwrap();
@@ -183,7 +201,20 @@ impl StateTracker for TrackOverlayState {
                 }
 
                 new_state.driver_idx = session_info.driver.car_idx;
-            }
+                let cars = session_info.drivers.iter().map(|driver| driver.car_idx + 1).max().unwrap_or(0);
+                new_state.multiclass = session_info.is_multiclass();
+                new_state.cars_class = (0..cars)
+                    .map(|car_idx| session_info.class_of(car_idx).map(|class| (class.id, class.color)))
+                    .collect();
+            },
+            Update::Connection(status) => {
+                let connected = matches!(status, ConnectionStatus::Connected { .. });
+                if !connected && self.is_on_track {
+                    self.sender.send(StateUpdate::WindowVisible(false)).await.unwrap();
+                    self.is_on_track = false;
+                }
+            },
+            _ => (),
         }
 
         self.current_state = new_state.clone();
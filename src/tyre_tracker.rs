@@ -0,0 +1,271 @@
+use std::fmt;
+
+use async_trait::async_trait;
+
+use crate::overlay::StateTracker;
+use crate::iracing::{ Update, Telemetry, SessionInfo };
+use crate::iracing::lap_timer::Lap;
+use crate::iracing::tyres::TyreState;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TyreSnapshot {
+    pub session_num: i32,
+    pub session_time: f64,
+    pub tyres: TyreState,
+}
+
+// The time between leaving the pits and coming back in
+#[derive(Debug, Clone)]
+pub struct Stint {
+    pub session_num: i32,
+    pub started_at: f64,
+    // None while the stint is going on
+    pub ended_at: Option<f64>,
+    // Taken every time the player crosses the line
+    pub laps: Vec<TyreSnapshot>,
+    // The sim only measures the temperatures and wear in the pits, so this is the last sample
+    // before leaving them again. None while the stint is going on, or when it ended in the garage.
+    pub measured: Option<TyreSnapshot>,
+}
+
+// E.g. `12 laps from 630s to 1852s of session 2, LF 85/86/84C 92/91/93% 172.4 kPa, ...`
+impl fmt::Display for Stint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} laps from {:.0}s", self.laps.len(), self.started_at)?;
+        if let Some(ended_at) = self.ended_at {
+            write!(f, " to {:.0}s", ended_at)?;
+        }
+        write!(f, " of session {}", self.session_num)?;
+        match &self.measured {
+            Some(measured) => write!(f, ", {}", measured.tyres),
+            None => write!(f, ", not measured"),
+        }
+    }
+}
+
+// Keeps the tyre history of every stint of the player, to review how the tyres held up over a race.
+// Each stint is logged once it is over, with the tyres as measured when leaving the pits.
+#[derive(Default)]
+pub struct TyreTracker {
+    player_car_idx: Option<usize>,
+    stints: Vec<Stint>,
+    // Whether the last stint is still going on
+    in_stint: bool,
+    // Whether the stint ended on pit road, so the next pit exit comes with measurements
+    awaiting_measurement: bool,
+    last_on_pit_road: bool,
+    last: Option<TyreSnapshot>,
+}
+
+impl TyreTracker {
+    pub fn new() -> TyreTracker {
+        TyreTracker::default()
+    }
+
+    // The last one may still be going on
+    pub fn stints(&self) -> &[Stint] {
+        &self.stints
+    }
+
+    fn set_session(&mut self, session_info: &SessionInfo) {
+        self.player_car_idx = Some(session_info.driver.car_idx);
+    }
+
+    fn log_stint(&self) {
+        if let Some(stint) = self.stints.last() {
+            info!["Stint {}: {}", self.stints.len(), stint];
+        }
+    }
+
+    fn start_stint(&mut self, snapshot: &TyreSnapshot) {
+        if self.awaiting_measurement {
+            if let (Some(stint), Some(measured)) = (self.stints.last_mut(), &self.last) {
+                stint.measured = Some(measured.clone());
+            }
+            self.log_stint();
+        }
+
+        self.stints.push(Stint {
+            session_num: snapshot.session_num,
+            started_at: snapshot.session_time,
+            ended_at: None,
+            laps: vec![],
+            measured: None,
+        });
+        self.in_stint = true;
+        self.awaiting_measurement = false;
+    }
+
+    fn end_stint(&mut self, on_pit_road: bool, at: f64) {
+        if self.in_stint {
+            self.in_stint = false;
+            self.awaiting_measurement = on_pit_road;
+            if let Some(stint) = self.stints.last_mut() {
+                stint.ended_at = Some(at);
+            }
+            // Otherwise it is logged when leaving the pits, with the measurements
+            if !on_pit_road {
+                self.log_stint();
+            }
+        }
+    }
+
+    fn update(&mut self, telemetry: &Telemetry) {
+        let snapshot = TyreSnapshot {
+            session_num: telemetry.session_num,
+            session_time: telemetry.session_time,
+            tyres: telemetry.tyres,
+        };
+
+        if !telemetry.is_on_track {
+            // Towed or back in the garage, where the tyres can be changed without measuring them
+            if self.awaiting_measurement {
+                self.awaiting_measurement = false;
+                self.log_stint();
+            }
+            self.end_stint(false, telemetry.session_time);
+        } else if telemetry.on_pit_road && !self.last_on_pit_road {
+            self.end_stint(true, telemetry.session_time);
+        } else if !telemetry.on_pit_road && (self.last_on_pit_road || !self.in_stint) {
+            self.start_stint(&snapshot);
+        }
+        self.last_on_pit_road = telemetry.on_pit_road;
+        self.last = Some(snapshot);
+    }
+
+    fn complete_lap(&mut self, lap: &Lap) {
+        if Some(lap.car_idx) != self.player_car_idx || !self.in_stint {
+            return;
+        }
+        if let (Some(stint), Some(last)) = (self.stints.last_mut(), &self.last) {
+            stint.laps.push(last.clone());
+        }
+    }
+}
+
+#[async_trait]
+impl StateTracker for TyreTracker {
+    async fn process(&mut self, update: &Update) {
+        match update {
+            Update::Session(session_info) => self.set_session(session_info),
+            Update::Telemetry(telemetry) => self.update(telemetry),
+            Update::LapCompleted(lap) => self.complete_lap(lap),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // The tyres as they are measured in the pits, after driving on them for `laps`
+    fn tyres(laps: u32) -> TyreState {
+        let mut tyres = TyreState::default();
+        for corner in &mut tyres.corners {
+            corner.temps = [60.0 + laps as f32; 3];
+            corner.wear = [1.0 - laps as f32 / 100.0; 3];
+        }
+        tyres
+    }
+
+    fn update(tracker: &mut TyreTracker, session_time: f64, is_on_track: bool, on_pit_road: bool, tyres: TyreState) {
+        tracker.update(&Telemetry {
+            session_num: 2,
+            session_time,
+            is_on_track,
+            on_pit_road,
+            tyres,
+            ..Telemetry::default()
+        });
+    }
+
+    fn complete_lap(tracker: &mut TyreTracker, car_idx: usize) {
+        tracker.complete_lap(&Lap {
+            car_idx,
+            lap: 1,
+            time: Duration::from_secs(100),
+            sectors: vec![],
+            completed_at: 0.0,
+            is_personal_best: false,
+            is_session_best: false,
+        });
+    }
+
+    fn player_tracker() -> TyreTracker {
+        TyreTracker {
+            player_car_idx: Some(0),
+            ..TyreTracker::default()
+        }
+    }
+
+    #[test]
+    fn measures_stints_at_pit_exit() {
+        let mut tracker = player_tracker();
+        // Leaving the pit stall
+        update(&mut tracker, 10.0, true, true, tyres(0));
+        assert!(tracker.stints().is_empty());
+        update(&mut tracker, 20.0, true, false, tyres(0));
+        assert_eq!(tracker.stints().len(), 1);
+        assert_eq!(tracker.stints()[0].started_at, 20.0);
+        assert!(tracker.in_stint);
+
+        complete_lap(&mut tracker, 0);
+        complete_lap(&mut tracker, 3);
+        complete_lap(&mut tracker, 0);
+        assert_eq!(tracker.stints()[0].laps.len(), 2);
+
+        // Into the pits, where the tyres are measured
+        update(&mut tracker, 220.0, true, true, tyres(0));
+        assert!(!tracker.in_stint);
+        assert_eq!(tracker.stints()[0].ended_at, Some(220.0));
+        complete_lap(&mut tracker, 0);
+        assert_eq!(tracker.stints()[0].laps.len(), 2);
+        update(&mut tracker, 250.0, true, true, tyres(2));
+        assert_eq!(tracker.stints()[0].measured, None);
+
+        update(&mut tracker, 260.0, true, false, tyres(2));
+        assert_eq!(tracker.stints().len(), 2);
+        let measured = tracker.stints()[0].measured.as_ref().unwrap();
+        assert_eq!(measured.session_time, 250.0);
+        assert_eq!(measured.tyres, tyres(2));
+        assert_eq!(tracker.stints()[1].started_at, 260.0);
+        assert!(tracker.in_stint);
+    }
+
+    #[test]
+    fn does_not_measure_stints_ending_in_the_garage() {
+        let mut tracker = player_tracker();
+        update(&mut tracker, 20.0, true, false, tyres(0));
+        update(&mut tracker, 120.0, false, false, tyres(0));
+        assert!(!tracker.in_stint);
+        assert_eq!(tracker.stints()[0].ended_at, Some(120.0));
+
+        // Out of the garage through the pit stall
+        update(&mut tracker, 300.0, true, true, tyres(0));
+        update(&mut tracker, 310.0, true, false, tyres(0));
+        assert_eq!(tracker.stints().len(), 2);
+        assert_eq!(tracker.stints()[0].measured, None);
+
+        // Into the pits, but to the garage from there
+        update(&mut tracker, 400.0, true, true, tyres(1));
+        update(&mut tracker, 410.0, false, false, tyres(1));
+        update(&mut tracker, 500.0, true, true, tyres(0));
+        update(&mut tracker, 510.0, true, false, tyres(0));
+        assert_eq!(tracker.stints().len(), 3);
+        assert_eq!(tracker.stints()[1].ended_at, Some(400.0));
+        assert_eq!(tracker.stints()[1].measured, None);
+    }
+
+    #[test]
+    fn describes_stints() {
+        let mut tracker = player_tracker();
+        update(&mut tracker, 20.0, true, false, tyres(0));
+        complete_lap(&mut tracker, 0);
+        assert_eq!(tracker.stints()[0].to_string(), "1 laps from 20s of session 2, not measured");
+        update(&mut tracker, 120.0, true, true, tyres(2));
+        update(&mut tracker, 130.0, true, false, tyres(2));
+        assert_eq!(tracker.stints()[0].to_string(), format!["1 laps from 20s to 120s of session 2, {}", tyres(2)]);
+    }
+}